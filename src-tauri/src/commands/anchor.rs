@@ -0,0 +1,590 @@
+use std::ffi::{OsStr, OsString};
+#[cfg(not(unix))]
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+#[cfg(unix)]
+use std::ffi::{CStr, CString};
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+#[cfg(unix)]
+use crate::commands::atomic_rename::{c_path, exchange_at, rename_noreplace_at};
+#[cfg(not(unix))]
+use crate::commands::atomic_rename::{exchange, rename_noreplace};
+
+/// The directory a batch works in, opened once before its first operation.
+///
+/// Every path an operation touches is resolved from this directory one component at
+/// a time with `openat(O_NOFOLLOW)`, and the final step is a `renameat`, `mkdirat`,
+/// `openat` or `unlinkat` on the directory holding the entry. A directory that was
+/// swapped for a symbolic link after planning is refused instead of followed, so no
+/// operation can leave the selected tree. Only the directory itself, the entries
+/// below it and, next to it, the single root rename target given when it is opened
+/// can be reached, and `..` is refused. Where there is no `openat` (Windows) each component is checked just
+/// before the path is used, which narrows the window but cannot close it.
+pub(crate) struct Anchor {
+    root: PathBuf,
+    // 上级目录中可以访问的名称：根目录自身和根目录改名的目标
+    names_in_parent: Vec<OsString>,
+    #[cfg(unix)]
+    dir: OwnedFd,
+    // 根目录的上级目录：根目录自身的重命名在这里进行
+    #[cfg(unix)]
+    parent: Option<OwnedFd>,
+}
+
+// 解析后的条目：打开的上级目录和其中的名称
+struct Located {
+    path: PathBuf,
+    #[cfg(unix)]
+    dir: OwnedFd,
+    #[cfg(unix)]
+    name: CString,
+}
+
+/// What `Anchor::metadata` reads about an entry, without following a symbolic link.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct EntryMetadata {
+    pub is_dir: bool,
+    pub is_file: bool,
+    pub len: u64,
+    /// Last modification time in nanoseconds since the Unix epoch
+    pub modified: Option<u128>,
+    /// Device and inode where the platform has them, which stay the same across renames
+    pub identity: Option<String>,
+    /// The device holding the entry, where the platform has one
+    pub device: Option<u64>,
+}
+
+/// An entry whose parent directory stays open, for code that needs a path (copying
+/// trees, moving across devices). On Linux the path goes through
+/// `/proc/self/fd`, so it resolves through the opened directory and not by name.
+pub(crate) struct Pinned {
+    located: Located,
+}
+
+// 路径从哪里开始解析
+enum Start {
+    Root,
+    Parent,
+}
+
+impl Anchor {
+    /// Opens `root`. The directory itself may be reached through a symbolic link,
+    /// since it was chosen by the user; nothing below it is.
+    pub(crate) fn open(root: &Path) -> io::Result<Anchor> {
+        Self::open_at(root, root, None)
+    }
+
+    /// Opens `root` for a batch that renames it to `renamed`, which must be next to it.
+    pub(crate) fn open_with_rename(root: &Path, renamed: Option<&Path>) -> io::Result<Anchor> {
+        Self::open_at(root, root, renamed)
+    }
+
+    /// Opens `dir` for paths written under `root`: the directory of a batch whose
+    /// root rename already happened. Renaming it back to `root` is allowed.
+    pub(crate) fn open_renamed(root: &Path, dir: &Path) -> io::Result<Anchor> {
+        Self::open_at(root, dir, Some(dir))
+    }
+
+    fn open_at(root: &Path, dir: &Path, renamed: Option<&Path>) -> io::Result<Anchor> {
+        let mut names_in_parent: Vec<OsString> = root.file_name().map(OsStr::to_os_string).into_iter().collect();
+        if let Some(name) = renamed.filter(|renamed| renamed.parent() == root.parent()).and_then(Path::file_name) {
+            names_in_parent.push(name.to_os_string());
+        }
+        #[cfg(unix)]
+        {
+            let dir_fd = open_dir(libc::AT_FDCWD, &c_path(dir)?, true)?;
+            let parent = match (dir.parent(), dir.file_name()) {
+                (Some(parent), Some(_)) => {
+                    let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+                    open_dir(libc::AT_FDCWD, &c_path(parent)?, true).ok()
+                },
+                _ => None,
+            };
+            Ok(Anchor { root: root.to_path_buf(), names_in_parent, dir: dir_fd, parent })
+        }
+        #[cfg(not(unix))]
+        {
+            // 没有 openat 时只能按原来的路径解析
+            if !fs::metadata(dir)?.is_dir() {
+                return Err(io::Error::new(io::ErrorKind::NotADirectory, format!("{} is not a directory", dir.display())));
+            }
+            Ok(Anchor { root: root.to_path_buf(), names_in_parent })
+        }
+    }
+
+    /// Another handle on the same opened directories.
+    pub(crate) fn try_clone(&self) -> io::Result<Anchor> {
+        Ok(Anchor {
+            root: self.root.clone(),
+            names_in_parent: self.names_in_parent.clone(),
+            #[cfg(unix)]
+            dir: self.dir.try_clone()?,
+            #[cfg(unix)]
+            parent: self.parent.as_ref().map(OwnedFd::try_clone).transpose()?,
+        })
+    }
+
+    // 把路径拆成相对于根目录（或根目录的上级目录）的名称
+    fn split<'a>(&self, path: &'a Path) -> io::Result<(Start, Vec<&'a OsStr>)> {
+        let (start, relative) = match path.strip_prefix(&self.root) {
+            Ok(relative) => (Start::Root, relative),
+            Err(_) => {
+                let relative = self.root.parent().and_then(|parent| path.strip_prefix(parent).ok())
+                    // 上级目录自身无法操作，只是 create_dir_all 的起点
+                    .filter(|relative| relative.as_os_str().is_empty() || (relative.components().count() == 1
+                        && relative.file_name().is_some_and(|name| self.reachable_in_parent(name))))
+                    .ok_or_else(|| outside(path, &self.root))?;
+                (Start::Parent, relative)
+            },
+        };
+        let mut names = Vec::new();
+        for component in relative.components() {
+            match component {
+                Component::Normal(name) => names.push(name),
+                Component::CurDir => {},
+                _ => return Err(outside(path, &self.root)),
+            }
+        }
+        Ok((start, names))
+    }
+
+    // 根目录只改大小写时经过临时名称 .treenamer-tmp-{uuid}-{名称}，名称必须是可以访问的名称
+    fn reachable_in_parent(&self, name: &OsStr) -> bool {
+        let temporary = name.to_str()
+            .and_then(|name| name.strip_prefix(".treenamer-tmp-"))
+            .and_then(|rest| rest.split_once('-'))
+            .filter(|(id, _)| id.len() == 32 && id.chars().all(|c| c.is_ascii_hexdigit()))
+            .map(|(_, original)| OsStr::new(original));
+        self.names_in_parent.iter().any(|allowed| allowed == name || Some(allowed.as_os_str()) == temporary)
+    }
+
+    fn locate(&self, path: &Path) -> io::Result<Located> {
+        let (start, names) = self.split(path)?;
+        let (last, intermediate) = match (&start, names.split_last()) {
+            (Start::Root, Some((last, intermediate))) => (*last, intermediate),
+            (Start::Parent, Some((last, _))) => return self.in_parent(path, last),
+            // 根目录自身位于上级目录中
+            (Start::Root, None) => return self.in_parent(path, self.root.file_name().ok_or_else(|| outside(path, &self.root))?),
+            (Start::Parent, None) => return Err(outside(path, &self.root)),
+        };
+        #[cfg(unix)]
+        {
+            let mut dir = self.dir.try_clone()?;
+            for name in intermediate {
+                dir = step(&dir, name, path)?;
+            }
+            Ok(Located { path: path.to_path_buf(), dir, name: c_name(last)? })
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = last;
+            let mut current = self.root.clone();
+            for name in intermediate {
+                current.push(name);
+                match fs::symlink_metadata(&current) {
+                    Ok(metadata) if metadata.file_type().is_symlink() => return Err(symlink_refused(name, path)),
+                    // 缺少的目录由调用者处理
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => break,
+                    _ => {},
+                }
+            }
+            Ok(Located { path: path.to_path_buf() })
+        }
+    }
+
+    fn in_parent(&self, path: &Path, name: &OsStr) -> io::Result<Located> {
+        #[cfg(unix)]
+        {
+            let parent = self.parent.as_ref().ok_or_else(|| outside(path, &self.root))?;
+            Ok(Located { path: path.to_path_buf(), dir: parent.try_clone()?, name: c_name(name)? })
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = name;
+            Ok(Located { path: path.to_path_buf() })
+        }
+    }
+
+    /// Whether the entry at `path` is a directory, without following a symbolic link
+    /// at the end. Fails with `NotFound` when there is no entry.
+    pub(crate) fn is_dir(&self, path: &Path) -> io::Result<bool> {
+        let located = self.locate(path)?;
+        #[cfg(unix)]
+        {
+            is_dir_at(located.dir.as_raw_fd(), &located.name)
+        }
+        #[cfg(not(unix))]
+        {
+            fs::symlink_metadata(&located.path).map(|metadata| metadata.is_dir())
+        }
+    }
+
+    /// Reads the metadata of the entry at `path`, not following a symbolic link at
+    /// the end. Fails with `NotFound` when there is no entry.
+    pub(crate) fn metadata(&self, path: &Path) -> io::Result<EntryMetadata> {
+        let located = self.locate(path)?;
+        #[cfg(unix)]
+        {
+            let stat = stat_at(located.dir.as_raw_fd(), &located.name)?;
+            let modified = u128::try_from(stat.st_mtime).ok()
+                .map(|secs| secs * 1_000_000_000 + stat.st_mtime_nsec as u128);
+            Ok(EntryMetadata {
+                is_dir: stat.st_mode & libc::S_IFMT == libc::S_IFDIR,
+                is_file: stat.st_mode & libc::S_IFMT == libc::S_IFREG,
+                len: stat.st_size as u64,
+                modified,
+                identity: Some(format!("{}:{}", stat.st_dev, stat.st_ino)),
+                device: Some(stat.st_dev as u64),
+            })
+        }
+        #[cfg(not(unix))]
+        {
+            let metadata = fs::symlink_metadata(&located.path)?;
+            Ok(EntryMetadata {
+                is_dir: metadata.is_dir(),
+                is_file: metadata.is_file(),
+                len: metadata.len(),
+                modified: metadata.modified().ok()
+                    .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map(|duration| duration.as_nanos()),
+                identity: None,
+                device: None,
+            })
+        }
+    }
+
+    /// A stable identity of the entry at `path` (device and inode), used to tell after
+    /// a crash whether an exchange already happened. `None` where there is none.
+    pub(crate) fn identity(&self, path: &Path) -> Option<String> {
+        self.metadata(path).ok().and_then(|metadata| metadata.identity)
+    }
+
+    /// Whether the directory holding `path` lists exactly its name. Unlike `exists`,
+    /// this tells names that differ only by case apart on case-insensitive volumes.
+    pub(crate) fn is_listed(&self, path: &Path) -> bool {
+        let Ok(located) = self.locate(path) else { return false };
+        #[cfg(unix)]
+        {
+            // 重新打开目录，不与复制的描述符共享读取位置
+            open_dir(located.dir.as_raw_fd(), c".", true)
+                .and_then(read_names)
+                .is_ok_and(|names| names.iter().any(|name| name.as_bytes() == located.name.to_bytes()))
+        }
+        #[cfg(not(unix))]
+        {
+            let (Some(parent), Some(name)) = (located.path.parent(), located.path.file_name()) else { return false };
+            fs::read_dir(parent).is_ok_and(|entries| entries.flatten().any(|entry| entry.file_name() == name))
+        }
+    }
+
+    /// Whether there is an entry at `path`. An entry that cannot be reached counts
+    /// as present, so nothing is ever put in its place.
+    pub(crate) fn exists(&self, path: &Path) -> bool {
+        !matches!(self.is_dir(path), Err(e) if e.kind() == io::ErrorKind::NotFound)
+    }
+
+    pub(crate) fn create_dir(&self, path: &Path) -> io::Result<()> {
+        let located = self.locate(path)?;
+        #[cfg(unix)]
+        {
+            check(unsafe { libc::mkdirat(located.dir.as_raw_fd(), located.name.as_ptr(), 0o777) })
+        }
+        #[cfg(not(unix))]
+        {
+            fs::create_dir(&located.path)
+        }
+    }
+
+    /// Creates `path` and every missing directory on the way, refusing symbolic links
+    /// among the existing ones.
+    pub(crate) fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let (start, names) = self.split(path)?;
+        #[cfg(unix)]
+        {
+            let mut dir = match start {
+                Start::Root => self.dir.try_clone()?,
+                Start::Parent => self.parent.as_ref().ok_or_else(|| outside(path, &self.root))?.try_clone()?,
+            };
+            for name in names {
+                let c = c_name(name)?;
+                if let Err(e) = check(unsafe { libc::mkdirat(dir.as_raw_fd(), c.as_ptr(), 0o777) }) {
+                    if e.kind() != io::ErrorKind::AlreadyExists {
+                        return Err(e);
+                    }
+                }
+                dir = step(&dir, name, path)?;
+            }
+            Ok(())
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (start, names);
+            self.locate(path)?;
+            fs::create_dir_all(path)
+        }
+    }
+
+    /// Creates an empty file, failing if anything already exists at `path`.
+    pub(crate) fn create_file(&self, path: &Path) -> io::Result<()> {
+        let located = self.locate(path)?;
+        #[cfg(unix)]
+        {
+            let flags = libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+            let fd = unsafe { libc::openat(located.dir.as_raw_fd(), located.name.as_ptr(), flags, 0o666 as libc::c_uint) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            drop(unsafe { OwnedFd::from_raw_fd(fd) });
+            Ok(())
+        }
+        #[cfg(not(unix))]
+        {
+            fs::OpenOptions::new().write(true).create_new(true).open(&located.path).map(|_| ())
+        }
+    }
+
+    pub(crate) fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        let located = self.locate(path)?;
+        #[cfg(unix)]
+        {
+            check(unsafe { libc::unlinkat(located.dir.as_raw_fd(), located.name.as_ptr(), libc::AT_REMOVEDIR) })
+        }
+        #[cfg(not(unix))]
+        {
+            fs::remove_dir(&located.path)
+        }
+    }
+
+    pub(crate) fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let located = self.locate(path)?;
+        #[cfg(unix)]
+        {
+            check(unsafe { libc::unlinkat(located.dir.as_raw_fd(), located.name.as_ptr(), 0) })
+        }
+        #[cfg(not(unix))]
+        {
+            fs::remove_file(&located.path)
+        }
+    }
+
+    /// Removes the entry at `path` and, for a directory, everything below it. Symbolic
+    /// links inside are removed, never followed.
+    pub(crate) fn remove_all(&self, path: &Path) -> io::Result<()> {
+        let located = self.locate(path)?;
+        #[cfg(unix)]
+        {
+            remove_all_at(located.dir.as_raw_fd(), &located.name)
+        }
+        #[cfg(not(unix))]
+        {
+            match fs::symlink_metadata(&located.path) {
+                Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&located.path),
+                Ok(_) => fs::remove_file(&located.path),
+                Err(e) => Err(e),
+            }
+        }
+    }
+
+    /// The names in the directory at `path`, sorted.
+    pub(crate) fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>> {
+        let located = self.locate(path)?;
+        #[cfg(unix)]
+        let mut names = read_names(step(&located.dir, OsStr::from_bytes(located.name.to_bytes()), path)?)?;
+        #[cfg(not(unix))]
+        let mut names = fs::read_dir(&located.path)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<io::Result<Vec<_>>>()?;
+        names.sort();
+        Ok(names)
+    }
+
+    /// Renames `from` to `to` without replacing an existing target.
+    pub(crate) fn rename_noreplace(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let (from, to) = (self.locate(from)?, self.locate(to)?);
+        #[cfg(unix)]
+        {
+            rename_noreplace_at(from.dir.as_raw_fd(), &from.name, to.dir.as_raw_fd(), &to.name)
+        }
+        #[cfg(not(unix))]
+        {
+            rename_noreplace(&from.path, &to.path)
+        }
+    }
+
+    /// Atomically exchanges the entries at `a` and `b`; `Unsupported` where the
+    /// platform or filesystem cannot.
+    pub(crate) fn exchange(&self, a: &Path, b: &Path) -> io::Result<()> {
+        let (a, b) = (self.locate(a)?, self.locate(b)?);
+        #[cfg(unix)]
+        {
+            exchange_at(a.dir.as_raw_fd(), &a.name, b.dir.as_raw_fd(), &b.name)
+        }
+        #[cfg(not(unix))]
+        {
+            exchange(&a.path, &b.path)
+        }
+    }
+
+    /// Resolves `path` and keeps its parent directory open while the result is used.
+    pub(crate) fn pin(&self, path: &Path) -> io::Result<Pinned> {
+        Ok(Pinned { located: self.locate(path)? })
+    }
+}
+
+impl Pinned {
+    /// A path to the entry that resolves through the opened parent directory where
+    /// the platform allows it, and the checked path otherwise.
+    pub(crate) fn path(&self) -> PathBuf {
+        #[cfg(target_os = "linux")]
+        {
+            let fds = Path::new("/proc/self/fd");
+            if fds.is_dir() {
+                return fds.join(self.located.dir.as_raw_fd().to_string()).join(OsStr::from_bytes(self.located.name.to_bytes()));
+            }
+        }
+        self.located.path.clone()
+    }
+}
+
+fn outside(path: &Path, root: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, format!("{} is outside {}", path.display(), root.display()))
+}
+
+fn symlink_refused(name: &OsStr, path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied,
+        format!("Refusing to follow the symbolic link {} on the way to {}", name.to_string_lossy(), path.display()))
+}
+
+#[cfg(unix)]
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result == 0 { Ok(()) } else { Err(io::Error::last_os_error()) }
+}
+
+#[cfg(unix)]
+fn c_name(name: &OsStr) -> io::Result<CString> {
+    CString::new(name.as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+#[cfg(unix)]
+fn open_dir(dir: RawFd, name: &CStr, follow: bool) -> io::Result<OwnedFd> {
+    let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC | if follow { 0 } else { libc::O_NOFOLLOW };
+    let fd = unsafe { libc::openat(dir, name.as_ptr(), flags) };
+    if fd < 0 { Err(io::Error::last_os_error()) } else { Ok(unsafe { OwnedFd::from_raw_fd(fd) }) }
+}
+
+// 进入下一级目录，不跟随符号链接
+#[cfg(unix)]
+fn step(dir: &OwnedFd, name: &OsStr, path: &Path) -> io::Result<OwnedFd> {
+    let c = c_name(name)?;
+    open_dir(dir.as_raw_fd(), &c, false).map_err(|e| {
+        let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+        let is_link = unsafe { libc::fstatat(dir.as_raw_fd(), c.as_ptr(), &mut stat, libc::AT_SYMLINK_NOFOLLOW) } == 0
+            && stat.st_mode & libc::S_IFMT == libc::S_IFLNK;
+        if is_link { symlink_refused(name, path) } else { e }
+    })
+}
+
+#[cfg(unix)]
+fn stat_at(dir: RawFd, name: &CStr) -> io::Result<libc::stat> {
+    let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+    check(unsafe { libc::fstatat(dir, name.as_ptr(), &mut stat, libc::AT_SYMLINK_NOFOLLOW) })?;
+    Ok(stat)
+}
+
+#[cfg(unix)]
+fn is_dir_at(dir: RawFd, name: &CStr) -> io::Result<bool> {
+    Ok(stat_at(dir, name)?.st_mode & libc::S_IFMT == libc::S_IFDIR)
+}
+
+#[cfg(unix)]
+fn read_names(dir: OwnedFd) -> io::Result<Vec<OsString>> {
+    let stream = unsafe { libc::fdopendir(dir.into_raw_fd()) };
+    if stream.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    let mut names = Vec::new();
+    loop {
+        let entry = unsafe { libc::readdir(stream) };
+        if entry.is_null() {
+            break;
+        }
+        let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) };
+        if name.to_bytes() != b"." && name.to_bytes() != b".." {
+            names.push(OsStr::from_bytes(name.to_bytes()).to_os_string());
+        }
+    }
+    unsafe { libc::closedir(stream) };
+    Ok(names)
+}
+
+#[cfg(unix)]
+fn remove_all_at(dir: RawFd, name: &CStr) -> io::Result<()> {
+    if !is_dir_at(dir, name)? {
+        return check(unsafe { libc::unlinkat(dir, name.as_ptr(), 0) });
+    }
+    let inner = open_dir(dir, name, false)?;
+    for entry in read_names(inner.try_clone()?)? {
+        remove_all_at(inner.as_raw_fd(), &c_name(&entry)?)?;
+    }
+    check(unsafe { libc::unlinkat(dir, name.as_ptr(), libc::AT_REMOVEDIR) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_operations_never_follow_symlinks_out_of_the_tree() {
+        let work = std::env::temp_dir().join(format!("treenamer_anchor_{}", uuid::Uuid::new_v4()));
+        let (root, outside_dir) = (work.join("root"), work.join("outside"));
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::create_dir_all(&outside_dir).unwrap();
+        fs::write(root.join("docs/a.txt"), "a").unwrap();
+        fs::write(outside_dir.join("secret.txt"), "secret").unwrap();
+        // 计划之后目录被换成了指向树外的符号链接
+        std::os::unix::fs::symlink(&outside_dir, root.join("swapped")).unwrap();
+
+        let anchor = Anchor::open(&root).unwrap();
+        let error = anchor.rename_noreplace(&root.join("swapped/secret.txt"), &root.join("docs/secret.txt")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        assert!(anchor.create_file(&root.join("swapped/new.txt")).is_err());
+        assert!(anchor.create_dir_all(&root.join("swapped/new/dir")).is_err());
+        assert!(anchor.remove_all(&root.join("swapped/secret.txt")).is_err());
+        assert!(anchor.rename_noreplace(&root.join("docs/a.txt"), &root.join("swapped/a.txt")).is_err());
+        assert!(anchor.rename_noreplace(&root.join("docs/a.txt"), &root.join("../outside/a.txt")).is_err());
+        assert!(anchor.rename_noreplace(&root.join("docs/a.txt"), &work.join("elsewhere/a.txt")).is_err());
+        // 根目录旁边的其他条目也无法访问
+        assert_eq!(anchor.remove_all(&outside_dir).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert!(anchor.rename_noreplace(&outside_dir, &root.join("docs/outside")).is_err());
+        assert!(anchor.rename_noreplace(&root, &work.join("renamed")).is_err());
+        assert!(anchor.create_dir_all(&work.join("sibling")).is_err());
+        assert!(anchor.create_file(&work.join("sibling.txt")).is_err());
+        assert!(anchor.exchange(&root, &outside_dir).is_err());
+        assert!(outside_dir.is_dir() && !work.join("sibling").exists() && !work.join("sibling.txt").exists());
+        assert_eq!(fs::read_dir(&outside_dir).unwrap().count(), 1);
+        assert_eq!(fs::read_to_string(outside_dir.join("secret.txt")).unwrap(), "secret");
+
+        // 符号链接本身可以被重命名和删除，里面的内容不受影响
+        anchor.rename_noreplace(&root.join("swapped"), &root.join("link")).unwrap();
+        anchor.remove_all(&root.join("link")).unwrap();
+        assert!(outside_dir.join("secret.txt").exists());
+
+        // 树内的操作和根目录自身的重命名照常进行
+        anchor.create_dir_all(&root.join("docs/new/dir")).unwrap();
+        anchor.create_file(&root.join("docs/new/dir/b.txt")).unwrap();
+        assert_eq!(anchor.read_dir(&root.join("docs")).unwrap(), vec![OsString::from("a.txt"), OsString::from("new")]);
+        anchor.remove_all(&root.join("docs/new")).unwrap();
+        let anchor = Anchor::open_with_rename(&root, Some(&work.join("renamed"))).unwrap();
+        assert!(anchor.remove_all(&outside_dir).is_err());
+        anchor.rename_noreplace(&root, &work.join("renamed")).unwrap();
+        assert_eq!(fs::read_to_string(work.join("renamed/docs/a.txt")).unwrap(), "a");
+
+        let _ = fs::remove_dir_all(&work);
+    }
+}
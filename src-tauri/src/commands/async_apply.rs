@@ -0,0 +1,200 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use tauri::command;
+use serde::Serialize;
+use crate::commands::fs::{attach_node_ids, plan_for_apply, FileOperation, OperationResult, PlanOptions};
+use crate::commands::journal::{app_data_dir, journal_dir, journal_path, start_journal};
+use crate::commands::backup::backup_before_batch;
+use crate::commands::transaction::{open_anchor, run_transaction_observed, Transaction, TransactionReport};
+use crate::commands::history::record_applied_batch;
+use crate::error::{AppError, ErrorType};
+
+/// Sent after every operation of a batch running in the background.
+#[derive(Debug, Serialize, Clone)]
+pub struct ApplyProgress {
+    pub batch_id: String,
+    /// Index of the operation that just ran
+    pub index: usize,
+    pub operation: FileOperation,
+    pub result: OperationResult,
+    /// Operations run so far, out of `total`
+    pub completed: usize,
+    pub failed: usize,
+    pub total: usize,
+}
+
+/// Sent once a batch running in the background has stopped.
+#[derive(Debug, Serialize)]
+pub struct ApplyFinished {
+    pub batch_id: String,
+    pub report: TransactionReport,
+}
+
+/// What a batch running in the background reports.
+#[derive(Debug)]
+pub enum ApplyEvent {
+    Progress(ApplyProgress),
+    Finished(ApplyFinished),
+}
+
+type EventSink = Box<dyn Fn(&ApplyEvent) + Send + Sync>;
+
+static EVENT_SINK: OnceLock<EventSink> = OnceLock::new();
+
+// 正在后台执行的批次和它们的取消标志
+static RUNNING: Mutex<Vec<(String, Arc<AtomicBool>)>> = Mutex::new(Vec::new());
+
+/// Sets where batches running in the background report to. The app forwards the
+/// events to the frontend as `apply-progress` and `apply-finished`.
+pub fn set_apply_event_sink(sink: impl Fn(&ApplyEvent) + Send + Sync + 'static) {
+    let _ = EVENT_SINK.set(Box::new(sink));
+}
+
+fn emit(event: ApplyEvent) {
+    if let Some(sink) = EVENT_SINK.get() {
+        sink(&event);
+    }
+}
+
+/// Applies `operations` in `dir` like `apply_operations`, reporting every result
+/// to `observe`. Stops before the next operation once `cancelled` is set, leaving
+/// the batch in the journal. A completed batch is recorded in the history in `data_dir`.
+pub(crate) fn apply_with_progress(
+    data_dir: &Path,
+    dir: &Path,
+    transaction: Transaction,
+    batch_id: &str,
+    operations: &[FileOperation],
+    cancelled: &AtomicBool,
+    observe: &mut dyn FnMut(ApplyProgress),
+) -> TransactionReport {
+    let (mut completed, mut failed) = (0, 0);
+    let report = run_transaction_observed(transaction, operations, Vec::new(), false, &mut |index, result| {
+        completed += 1;
+        if !result.success {
+            failed += 1;
+        }
+        observe(ApplyProgress {
+            batch_id: batch_id.to_string(),
+            index,
+            operation: operations[index].clone(),
+            result: result.clone(),
+            completed,
+            failed,
+            total: operations.len(),
+        });
+        !cancelled.load(Ordering::SeqCst)
+    });
+    record_applied_batch(data_dir, &dir.to_string_lossy(), operations, &report);
+    report
+}
+
+/// Starts applying the changes between the two trees in the background and returns
+/// the batch ID right away. Planning, the backup and the journal are set up before
+/// it returns, so errors there are returned as usual. Every operation is reported
+/// with an `apply-progress` event and the outcome with `apply-finished`.
+#[command]
+pub fn start_apply(
+    #[allow(non_snake_case)] dirPath: String,
+    #[allow(non_snake_case)] originalTree: String,
+    #[allow(non_snake_case)] modifiedTree: String,
+    options: Option<PlanOptions>
+) -> Result<String, AppError> {
+    println!("start_apply called with path: {}", dirPath);
+    let (operations, node_ids) = plan_for_apply(&dirPath, &originalTree, &modifiedTree, &options.unwrap_or_default())?;
+    let data_dir = app_data_dir();
+    let anchor = open_anchor(Path::new(&dirPath), &operations)?;
+    backup_before_batch(&data_dir, Path::new(&dirPath), &operations)?;
+    let journal = start_journal(&data_dir, Path::new(&dirPath), &operations, false)?;
+    let batch_id = journal.id().to_string();
+
+    let cancelled = Arc::new(AtomicBool::new(false));
+    RUNNING.lock().map_err(|e| AppError::new(ErrorType::SystemError, e.to_string()).at(&dirPath))?.push((batch_id.clone(), cancelled.clone()));
+    let id = batch_id.clone();
+    std::thread::spawn(move || {
+        let transaction = Transaction::with_journal(anchor, journal);
+        let mut report = apply_with_progress(&data_dir, Path::new(&dirPath), transaction, &id, &operations, &cancelled,
+            &mut |mut progress| {
+                progress.result.node_id = node_ids.get(progress.index).cloned();
+                emit(ApplyEvent::Progress(progress))
+            });
+        attach_node_ids(&mut report.results, &node_ids);
+        if let Ok(mut running) = RUNNING.lock() {
+            running.retain(|(running_id, _)| *running_id != id);
+        }
+        println!("Batch {} finished in the background (cancelled: {})", id, report.cancelled);
+        emit(ApplyEvent::Finished(ApplyFinished { batch_id: id, report }));
+    });
+    Ok(batch_id)
+}
+
+/// Asks a batch running in the background to stop before its next operation. What
+/// already ran stays applied and journaled; the batch can then be resumed or rolled
+/// back like an interrupted one.
+#[command]
+pub fn cancel_apply(#[allow(non_snake_case)] batchId: String) -> Result<(), AppError> {
+    let journal = journal_path(&journal_dir(), &batchId)?;
+    let running = RUNNING.lock().map_err(|e| AppError::new(ErrorType::SystemError, e.to_string()).at(&journal))?;
+    let (_, cancelled) = running.iter()
+        .find(|(id, _)| *id == batchId)
+        .ok_or_else(|| AppError::new(ErrorType::InvalidInput, format!("Batch {} is not running", batchId)).at(&journal))?;
+    println!("Cancelling batch {}", batchId);
+    cancelled.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::commands::anchor::Anchor;
+    use crate::commands::copy_tree::CopySettings;
+    use crate::commands::journal::{find_interrupted, rollback_batch_at, Journal};
+
+    #[test]
+    fn test_cancelled_batch_stays_journaled_and_can_be_rolled_back() {
+        let work = std::env::temp_dir().join(format!("treenamer_async_{}", uuid::Uuid::new_v4()));
+        let (root, journals) = (work.join("root"), work.join("journal"));
+        fs::create_dir_all(&root).unwrap();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            fs::write(root.join(name), name).unwrap();
+        }
+        let path = |name: &str| root.join(name).to_string_lossy().to_string();
+        let operations = vec![
+            FileOperation::Rename { from: path("a.txt"), to: path("1.txt") },
+            FileOperation::Delete { path: path("b.txt") },
+            FileOperation::Rename { from: path("c.txt"), to: path("3.txt") },
+        ];
+        let journal = Journal::create(&journals, &root, &operations, false, &CopySettings::default()).unwrap();
+        let id = journal.id().to_string();
+
+        // 第二个操作执行时取消
+        let cancelled = AtomicBool::new(false);
+        let mut events = Vec::new();
+        let report = apply_with_progress(&work.join("data"), &root, Transaction::with_journal(Anchor::open(&root).unwrap(), journal), &id, &operations, &cancelled,
+            &mut |progress| {
+                if progress.index == 1 {
+                    cancelled.store(true, Ordering::SeqCst);
+                }
+                events.push((progress.index, progress.completed, progress.total, progress.result.success));
+            });
+
+        assert!(report.cancelled && !report.committed);
+        assert_eq!(events, vec![(0, 1, 3, true), (1, 2, 3, true)]);
+        assert!(root.join("1.txt").exists() && root.join("c.txt").exists() && !root.join("b.txt").exists());
+        let interrupted = find_interrupted(&journals);
+        assert_eq!(interrupted.len(), 1);
+        assert_eq!(interrupted[0].completed, 2);
+
+        // 取消后的批次可以像中断的批次一样撤销，删除的条目也会恢复
+        let rollback = rollback_batch_at(&journals, &id).unwrap();
+        assert!(rollback.rolled_back, "{:?}", rollback.rollback);
+        let mut names: Vec<String> = fs::read_dir(&root).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().to_string()).collect();
+        names.sort();
+        assert_eq!(names, vec!["a.txt", "b.txt", "c.txt"]);
+        assert!(find_interrupted(&journals).is_empty());
+
+        let _ = fs::remove_dir_all(&work);
+    }
+}
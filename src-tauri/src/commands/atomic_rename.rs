@@ -0,0 +1,237 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+#[cfg(unix)]
+use std::ffi::{CStr, CString};
+#[cfg(unix)]
+use std::os::fd::RawFd;
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+
+/// Whether this platform has an atomic exchange of two names (`RENAME_EXCHANGE` on
+/// Linux, `RENAME_SWAP` on macOS). The planner only emits exchanges where it does;
+/// a filesystem that still refuses one falls back to a temporary name.
+pub(crate) const EXCHANGE_AVAILABLE: bool = cfg!(any(target_os = "linux", target_os = "macos"));
+
+/// Renames `from` to `to`, failing with `AlreadyExists` instead of replacing an
+/// existing target. Files are hard linked to the new name and then unlinked, which
+/// fails atomically; for directories and filesystems without hard links the target
+/// is checked just before the rename. Unix goes through `rename_noreplace_at`.
+#[cfg(not(unix))]
+pub(crate) fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    fallback_noreplace(from, to)
+}
+
+/// Atomically exchanging two entries is not available here; always fails with
+/// `Unsupported`. Unix goes through `exchange_at`.
+#[cfg(not(unix))]
+pub(crate) fn exchange(a: &Path, b: &Path) -> io::Result<()> {
+    let _ = (a, b);
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+/// Renames `from` to `to` relative to open directories, failing with `AlreadyExists`
+/// instead of replacing an existing target. Uses `renameat2(RENAME_NOREPLACE)` on
+/// Linux and `renamex_np(RENAME_EXCL)` on macOS. Where those are unavailable (old
+/// kernels, filesystems without support, other platforms) files are hard linked to
+/// the new name and then unlinked, which fails atomically as well; for directories
+/// and filesystems without hard links the target is checked just before the rename.
+#[cfg(unix)]
+pub(crate) fn rename_noreplace_at(from_dir: RawFd, from: &CStr, to_dir: RawFd, to: &CStr) -> io::Result<()> {
+    match sys::rename_noreplace(from_dir, from, to_dir, to) {
+        Err(e) if is_unsupported(&e) => {
+            println!("No-replace rename unsupported for {} ({}), using fallback", to.to_string_lossy(), e);
+            fallback_noreplace_at(from_dir, from, to_dir, to)
+        },
+        result => result,
+    }
+}
+
+/// Atomically exchanges the entries at `a` and `b` relative to open directories;
+/// both must exist. Fails with `Unsupported` where the platform or filesystem
+/// cannot do it.
+#[cfg(unix)]
+pub(crate) fn exchange_at(a_dir: RawFd, a: &CStr, b_dir: RawFd, b: &CStr) -> io::Result<()> {
+    match sys::exchange(a_dir, a, b_dir, b) {
+        Err(e) if is_unsupported(&e) => Err(io::Error::new(io::ErrorKind::Unsupported, e)),
+        result => result,
+    }
+}
+
+/// A stable identity of an entry (device and inode) from metadata that was already
+/// read, matching `Anchor::identity`. `None` where there is none.
+pub(crate) fn metadata_identity(metadata: &fs::Metadata) -> Option<String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some(format!("{}:{}", metadata.dev(), metadata.ino()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+// 内核或文件系统不支持这些标志：ENOSYS（旧内核）、EINVAL（文件系统不支持）、ENOTSUP
+#[cfg(unix)]
+fn is_unsupported(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::Unsupported
+        || matches!(error.raw_os_error(), Some(libc::ENOSYS) | Some(libc::EINVAL) | Some(libc::ENOTSUP))
+}
+
+#[cfg(unix)]
+pub(crate) fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+#[cfg(unix)]
+fn fallback_noreplace_at(from_dir: RawFd, from: &CStr, to_dir: RawFd, to: &CStr) -> io::Result<()> {
+    let check = |result: libc::c_int| if result == 0 { Ok(()) } else { Err(io::Error::last_os_error()) };
+    let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+    check(unsafe { libc::fstatat(from_dir, from.as_ptr(), &mut stat, libc::AT_SYMLINK_NOFOLLOW) })?;
+    // 硬链接在目标已存在时原子地失败；之后删除原来的名称
+    if stat.st_mode & libc::S_IFMT != libc::S_IFDIR {
+        match check(unsafe { libc::linkat(from_dir, from.as_ptr(), to_dir, to.as_ptr(), 0) }) {
+            Ok(_) => {
+                if let Err(e) = check(unsafe { libc::unlinkat(from_dir, from.as_ptr(), 0) }) {
+                    let _ = unsafe { libc::unlinkat(to_dir, to.as_ptr(), 0) };
+                    return Err(e);
+                }
+                return Ok(());
+            },
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Err(e),
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => return Err(e),
+            Err(e) => println!("Hard link fallback unavailable for {}: {}", to.to_string_lossy(), e),
+        }
+    }
+    // 最后的办法：重命名之前检查目标
+    if unsafe { libc::fstatat(to_dir, to.as_ptr(), &mut stat, libc::AT_SYMLINK_NOFOLLOW) } == 0 {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", to.to_string_lossy())));
+    }
+    check(unsafe { libc::renameat(from_dir, from.as_ptr(), to_dir, to.as_ptr()) })
+}
+
+#[cfg(not(unix))]
+fn fallback_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(from)?;
+    // 硬链接在目标已存在时原子地失败；之后删除原来的名称
+    if !metadata.is_dir() {
+        match fs::hard_link(from, to) {
+            Ok(_) => {
+                if let Err(e) = fs::remove_file(from) {
+                    let _ = fs::remove_file(to);
+                    return Err(e);
+                }
+                return Ok(());
+            },
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Err(e),
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => return Err(e),
+            Err(e) => println!("Hard link fallback unavailable for {}: {}", to.display(), e),
+        }
+    }
+    // 最后的办法：重命名之前检查目标
+    if fs::symlink_metadata(to).is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", to.display())));
+    }
+    fs::rename(from, to)
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::ffi::CStr;
+    use std::io;
+    use std::os::fd::RawFd;
+
+    // 直接调用系统调用，不依赖 glibc 2.28 才提供的包装函数
+    fn renameat2(from_dir: RawFd, from: &CStr, to_dir: RawFd, to: &CStr, flags: libc::c_uint) -> io::Result<()> {
+        let result = unsafe {
+            libc::syscall(libc::SYS_renameat2, from_dir, from.as_ptr(), to_dir, to.as_ptr(), flags)
+        };
+        if result == 0 { Ok(()) } else { Err(io::Error::last_os_error()) }
+    }
+
+    pub fn rename_noreplace(from_dir: RawFd, from: &CStr, to_dir: RawFd, to: &CStr) -> io::Result<()> {
+        renameat2(from_dir, from, to_dir, to, libc::RENAME_NOREPLACE)
+    }
+
+    pub fn exchange(a_dir: RawFd, a: &CStr, b_dir: RawFd, b: &CStr) -> io::Result<()> {
+        renameat2(a_dir, a, b_dir, b, libc::RENAME_EXCHANGE)
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod sys {
+    use std::ffi::CStr;
+    use std::io;
+    use std::os::fd::RawFd;
+
+    fn renameatx(from_dir: RawFd, from: &CStr, to_dir: RawFd, to: &CStr, flags: libc::c_uint) -> io::Result<()> {
+        let result = unsafe { libc::renameatx_np(from_dir, from.as_ptr(), to_dir, to.as_ptr(), flags) };
+        if result == 0 { Ok(()) } else { Err(io::Error::last_os_error()) }
+    }
+
+    pub fn rename_noreplace(from_dir: RawFd, from: &CStr, to_dir: RawFd, to: &CStr) -> io::Result<()> {
+        renameatx(from_dir, from, to_dir, to, libc::RENAME_EXCL)
+    }
+
+    pub fn exchange(a_dir: RawFd, a: &CStr, b_dir: RawFd, b: &CStr) -> io::Result<()> {
+        renameatx(a_dir, a, b_dir, b, libc::RENAME_SWAP)
+    }
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "macos"))))]
+mod sys {
+    use std::ffi::CStr;
+    use std::io;
+    use std::os::fd::RawFd;
+
+    pub fn rename_noreplace(_: RawFd, _: &CStr, _: RawFd, _: &CStr) -> io::Result<()> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+
+    pub fn exchange(_: RawFd, _: &CStr, _: RawFd, _: &CStr) -> io::Result<()> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rename_never_replaces_and_exchange_swaps() {
+        let root = std::env::temp_dir().join(format!("treenamer_atomic_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(root.join("dir")).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("b.txt"), "b").unwrap();
+        let at = |name: &str| c_path(&root.join(name)).unwrap();
+        let rename = |from: &str, to: &str| rename_noreplace_at(libc::AT_FDCWD, &at(from), libc::AT_FDCWD, &at(to));
+        let fallback = |from: &str, to: &str| fallback_noreplace_at(libc::AT_FDCWD, &at(from), libc::AT_FDCWD, &at(to));
+
+        assert_eq!(rename("a.txt", "b.txt").unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(rename("dir", "b.txt").unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(root.join("b.txt")).unwrap(), "b");
+        assert!(root.join("a.txt").exists());
+
+        // 两种回退方式也不会覆盖
+        assert_eq!(fallback("a.txt", "b.txt").unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fallback("dir", "b.txt").unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        fallback("a.txt", "c.txt").unwrap();
+        assert!(!root.join("a.txt").exists());
+
+        rename("c.txt", "a.txt").unwrap();
+        assert_eq!(fs::read_to_string(root.join("a.txt")).unwrap(), "a");
+
+        match exchange_at(libc::AT_FDCWD, &at("a.txt"), libc::AT_FDCWD, &at("dir")) {
+            Ok(_) => {
+                assert!(root.join("a.txt").is_dir());
+                assert_eq!(fs::read_to_string(root.join("dir")).unwrap(), "a");
+            },
+            Err(e) => assert_eq!(e.kind(), io::ErrorKind::Unsupported),
+        }
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,643 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::command;
+use serde::{Serialize, Deserialize};
+use crate::commands::fs::FileOperation;
+use crate::commands::collision::overwrites;
+use crate::commands::journal::app_data_dir;
+use crate::commands::transaction::open_anchor;
+use crate::error::{AppError, ErrorType};
+
+/// How much of the affected subtree a backup keeps.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum BackupMode {
+    /// Only the layout (names, sizes, times); cannot be restored
+    Manifest,
+    /// Hard links to every file: cheap, and survives renames and deletions,
+    /// but files edited in place change in the backup too
+    HardLink,
+    /// A full copy of every file
+    Copy,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BackupSettings {
+    /// Take a backup before every batch
+    pub enabled: bool,
+    pub mode: BackupMode,
+    /// Keep at most this many backups
+    pub keep_count: Option<usize>,
+    /// Remove backups older than this many days
+    pub keep_days: Option<u64>,
+}
+
+impl Default for BackupSettings {
+    fn default() -> Self {
+        Self { enabled: true, mode: BackupMode::HardLink, keep_count: Some(20), keep_days: Some(30) }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum BackupEntryKind {
+    Dir,
+    File,
+    Symlink,
+}
+
+/// One entry of the backed up subtree.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BackupEntry {
+    /// Relative to the backup root, with `/` separators
+    pub path: String,
+    pub kind: BackupEntryKind,
+    pub size: u64,
+    /// Seconds since the Unix epoch
+    pub modified: Option<u64>,
+    /// Unix permission bits
+    #[serde(default)]
+    pub permissions: Option<u32>,
+    /// Where a symlink points
+    #[serde(default)]
+    pub target: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BackupManifest {
+    pub id: String,
+    /// Seconds since the Unix epoch
+    pub created: u64,
+    /// The directory the batch was applied to
+    pub base: String,
+    /// The subtree that was backed up
+    pub root: String,
+    /// The mode actually used
+    pub mode: BackupMode,
+    /// Why the mode differs from the configured one, if it does
+    pub note: Option<String>,
+    /// The batch the backup was taken for (empty for a backup taken before a restore)
+    pub operations: Vec<FileOperation>,
+    pub entries: Vec<BackupEntry>,
+}
+
+/// A line in the backup list.
+#[derive(Debug, Serialize, Clone)]
+pub struct BackupSummary {
+    pub id: String,
+    pub created: u64,
+    pub base: String,
+    pub root: String,
+    pub mode: BackupMode,
+    pub note: Option<String>,
+    pub operation_count: usize,
+    pub entry_count: usize,
+    pub total_size: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RestoreReport {
+    pub id: String,
+    pub root: String,
+    /// Number of entries put back
+    pub restored: usize,
+    /// The backup of the state that the restore replaced
+    pub replaced_backup: String,
+}
+
+fn backup_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("backups")
+}
+
+fn backup_path(data_dir: &Path, id: &str) -> Result<PathBuf, AppError> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(AppError::new(ErrorType::InvalidInput, format!("Invalid backup id: {}", id)).at(backup_dir(data_dir)));
+    }
+    Ok(backup_dir(data_dir).join(id))
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+pub fn load_settings(data_dir: &Path) -> Result<BackupSettings, AppError> {
+    let path = backup_dir(data_dir).join("settings.json");
+    match fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content)
+            .map_err(|e| AppError::new(ErrorType::SystemError, format!("Failed to read {}: {}", path.display(), e)).at(&path)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BackupSettings::default()),
+        Err(e) => Err(AppError::io(&e, format!("Failed to read {}: {}", path.display(), e)).at(&path)),
+    }
+}
+
+pub fn save_settings(data_dir: &Path, settings: &BackupSettings) -> Result<(), AppError> {
+    let dir = backup_dir(data_dir);
+    fs::create_dir_all(&dir).map_err(|e| AppError::io(&e, format!("Failed to create {}: {}", dir.display(), e)).at(&dir))?;
+    let path = dir.join("settings.json");
+    let content = serde_json::to_string_pretty(settings).map_err(|e| AppError::new(ErrorType::SystemError, e.to_string()).at(&path))?;
+    fs::write(&path, content).map_err(|e| AppError::io(&e, format!("Failed to save backup settings: {}", e)).at(&path))
+}
+
+// 受影响的子树：所有操作涉及的父目录的最近公共祖先，不超出 base
+fn affected_root(base: &Path, operations: &[FileOperation]) -> PathBuf {
+    let mut root: Option<PathBuf> = None;
+    for operation in operations {
+        let paths: Vec<&String> = match operation {
+            FileOperation::Rename { from, to } | FileOperation::Move { from, to } | FileOperation::Copy { from, to }
+            | FileOperation::Merge { from, to } | FileOperation::Exchange { from, to } => vec![from, to],
+            FileOperation::CreateDir { path } | FileOperation::CreateFile { path } | FileOperation::Delete { path } => vec![path],
+        };
+        for path in paths {
+            let parent = Path::new(path).parent().unwrap_or(base);
+            let parent = if parent.starts_with(base) { parent } else { base };
+            root = Some(match root {
+                None => parent.to_path_buf(),
+                Some(root) => root.ancestors().find(|ancestor| parent.starts_with(ancestor)).unwrap_or(base).to_path_buf(),
+            });
+        }
+    }
+    // 上级目录可能还不存在（如移动到新建的目录中）
+    let root = root.unwrap_or_else(|| base.to_path_buf());
+    // 不跟随符号链接：指向树外的链接不能成为备份和恢复的目录
+    root.ancestors()
+        .find(|dir| dir.starts_with(base) && fs::symlink_metadata(dir).is_ok_and(|metadata| metadata.is_dir()))
+        .unwrap_or(base)
+        .to_path_buf()
+}
+
+fn describe(path: &Path, relative: String) -> io::Result<BackupEntry> {
+    let metadata = fs::symlink_metadata(path)?;
+    let kind = if metadata.file_type().is_symlink() {
+        BackupEntryKind::Symlink
+    } else if metadata.is_dir() {
+        BackupEntryKind::Dir
+    } else {
+        BackupEntryKind::File
+    };
+    #[cfg(unix)]
+    let permissions = {
+        use std::os::unix::fs::PermissionsExt;
+        Some(metadata.permissions().mode())
+    };
+    #[cfg(not(unix))]
+    let permissions = None;
+
+    Ok(BackupEntry {
+        path: relative,
+        kind,
+        size: if kind == BackupEntryKind::File { metadata.len() } else { 0 },
+        modified: metadata.modified().ok().and_then(|time| time.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs()),
+        permissions,
+        target: if kind == BackupEntryKind::Symlink {
+            fs::read_link(path).ok().map(|target| target.to_string_lossy().to_string())
+        } else {
+            None
+        },
+    })
+}
+
+// 按先序遍历记录子树，不跟随符号链接
+fn collect_entries(dir: &Path, prefix: &str, entries: &mut Vec<BackupEntry>) -> io::Result<()> {
+    let mut children: Vec<fs::DirEntry> = fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+    children.sort_by_key(|entry| entry.file_name());
+    for child in children {
+        let name = child.file_name().to_string_lossy().to_string();
+        let relative = if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) };
+        let entry = describe(&child.path(), relative.clone())?;
+        let is_dir = entry.kind == BackupEntryKind::Dir;
+        entries.push(entry);
+        if is_dir {
+            collect_entries(&child.path(), &relative, entries)?;
+        }
+    }
+    Ok(())
+}
+
+fn relative_to(root: &Path, relative: &str) -> PathBuf {
+    relative.split('/').fold(root.to_path_buf(), |path, component| path.join(component))
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &str, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(not(unix))]
+fn create_symlink(target: &str, link: &Path) -> io::Result<()> {
+    if Path::new(target).is_dir() {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+// 按清单把 from 下的条目放到 to 下：目录新建，文件硬链接或复制，符号链接重建
+fn materialize(entries: &[BackupEntry], from: &Path, to: &Path, link: bool) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in entries {
+        let (source, target) = (relative_to(from, &entry.path), relative_to(to, &entry.path));
+        match entry.kind {
+            BackupEntryKind::Dir => fs::create_dir(&target)?,
+            BackupEntryKind::File if link => fs::hard_link(&source, &target)?,
+            BackupEntryKind::File => fs::copy(&source, &target).map(|_| ())?,
+            BackupEntryKind::Symlink => create_symlink(entry.target.as_deref().unwrap_or_default(), &target)?,
+        }
+    }
+    Ok(())
+}
+
+/// Backs up `root`. A hard link farm that cannot be created (such as when the data
+/// directory is on another volume) falls back to `fallback`.
+pub fn create_backup(
+    data_dir: &Path,
+    base: &Path,
+    root: &Path,
+    operations: &[FileOperation],
+    mode: BackupMode,
+    fallback: BackupMode,
+) -> Result<BackupManifest, AppError> {
+    let created = now();
+    // 以纳秒时间开头，同一秒内的备份也能按创建顺序排列
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    let id = format!("{:020}-{}", nanos, &uuid::Uuid::new_v4().simple().to_string()[..8]);
+    let backups = backup_dir(data_dir);
+    fs::create_dir_all(&backups).map_err(|e| AppError::io(&e, format!("Failed to create {}: {}", backups.display(), e)).at(&backups))?;
+    if let (Ok(backups), Ok(root)) = (fs::canonicalize(&backups), fs::canonicalize(root)) {
+        if backups.starts_with(&root) {
+            return Err(AppError::new(ErrorType::InvalidInput,
+                format!("The backup directory {} is inside {}", backups.display(), root.display())).at(&backups));
+        }
+    }
+    let dir = backups.join(&id);
+
+    let mut entries = Vec::new();
+    collect_entries(root, "", &mut entries).map_err(|e| AppError::io(&e, format!("Failed to read {}: {}", root.display(), e)).at(root))?;
+    fs::create_dir_all(&dir).map_err(|e| AppError::io(&e, format!("Failed to create backup {}: {}", dir.display(), e)).at(&dir))?;
+
+    let data = dir.join("data");
+    let (mode, note) = match mode {
+        BackupMode::Manifest => (mode, None),
+        BackupMode::Copy | BackupMode::HardLink => match materialize(&entries, root, &data, mode == BackupMode::HardLink) {
+            Ok(_) => (mode, None),
+            Err(e) if mode == BackupMode::HardLink && fallback != BackupMode::HardLink => {
+                println!("Unable to hard link {} into the backup, using {:?}: {}", root.display(), fallback, e);
+                let _ = fs::remove_dir_all(&data);
+                if fallback == BackupMode::Copy {
+                    if let Err(e) = materialize(&entries, root, &data, false) {
+                        let _ = fs::remove_dir_all(&dir);
+                        return Err(AppError::io(&e, format!("Failed to copy {} into the backup: {}", root.display(), e)).at(root));
+                    }
+                }
+                (fallback, Some(format!("Hard links could not be created: {}", e)))
+            },
+            Err(e) => {
+                let _ = fs::remove_dir_all(&dir);
+                return Err(AppError::io(&e, format!("Failed to back up {}: {}", root.display(), e)).at(root));
+            },
+        },
+    };
+
+    let manifest = BackupManifest {
+        id,
+        created,
+        base: base.to_string_lossy().to_string(),
+        root: root.to_string_lossy().to_string(),
+        mode,
+        note,
+        operations: operations.to_vec(),
+        entries,
+    };
+    let path = dir.join("manifest.json");
+    let content = serde_json::to_string_pretty(&manifest).map_err(|e| AppError::new(ErrorType::SystemError, e.to_string()).at(&path))?;
+    if let Err(e) = fs::write(&path, content) {
+        let _ = fs::remove_dir_all(&dir);
+        return Err(AppError::io(&e, format!("Failed to write the backup manifest: {}", e)).at(&path));
+    }
+    println!("Backup {} of {} created ({:?}, {} entries)", manifest.id, root.display(), manifest.mode, manifest.entries.len());
+    Ok(manifest)
+}
+
+/// Takes the configured backup of the subtree a batch is about to change and applies
+/// the retention policy. Returns `None` when backups are disabled, unless the batch
+/// overwrites existing entries: those are always backed up in a restorable mode.
+pub fn backup_before_batch(data_dir: &Path, base: &Path, operations: &[FileOperation]) -> Result<Option<BackupManifest>, AppError> {
+    let settings = load_settings(data_dir)?;
+    // 覆盖已有条目的批次总是备份，而且备份必须能恢复
+    let overwriting = overwrites(operations);
+    if (!settings.enabled && !overwriting) || operations.is_empty() {
+        return Ok(None);
+    }
+    let mode = match settings.mode {
+        BackupMode::Manifest if overwriting => BackupMode::HardLink,
+        mode => mode,
+    };
+    let fallback = if overwriting { BackupMode::Copy } else { BackupMode::Manifest };
+    let root = affected_root(base, operations);
+    let manifest = create_backup(data_dir, base, &root, operations, mode, fallback)?;
+    prune(data_dir, settings.keep_count, settings.keep_days, Some(&manifest.id))?;
+    Ok(Some(manifest))
+}
+
+pub fn inspect(data_dir: &Path, id: &str) -> Result<BackupManifest, AppError> {
+    let path = backup_path(data_dir, id)?.join("manifest.json");
+    let content = fs::read_to_string(&path).map_err(|e| AppError::io(&e, format!("Failed to read backup {}: {}", id, e)).at(&path))?;
+    serde_json::from_str(&content)
+        .map_err(|e| AppError::new(ErrorType::SystemError, format!("Corrupt backup manifest {}: {}", path.display(), e)).at(&path))
+}
+
+/// Lists the backups, newest first.
+pub fn list(data_dir: &Path) -> Vec<BackupSummary> {
+    let Ok(entries) = fs::read_dir(backup_dir(data_dir)) else { return Vec::new() };
+    let mut backups: Vec<BackupSummary> = entries
+        .flatten()
+        .filter(|entry| entry.path().join("manifest.json").is_file())
+        .filter_map(|entry| match inspect(data_dir, &entry.file_name().to_string_lossy()) {
+            Ok(manifest) => Some(BackupSummary {
+                total_size: manifest.entries.iter().map(|entry| entry.size).sum(),
+                entry_count: manifest.entries.len(),
+                operation_count: manifest.operations.len(),
+                id: manifest.id,
+                created: manifest.created,
+                base: manifest.base,
+                root: manifest.root,
+                mode: manifest.mode,
+                note: manifest.note,
+            }),
+            Err(e) => {
+                println!("{}", e);
+                None
+            },
+        })
+        .collect();
+    backups.sort_by(|a, b| b.created.cmp(&a.created).then_with(|| b.id.cmp(&a.id)));
+    backups
+}
+
+/// Removes backups beyond `keep_count` (newest kept) and those older than `keep_days`.
+/// `keep` is never removed. Returns the ids of the removed backups.
+pub fn prune(data_dir: &Path, keep_count: Option<usize>, keep_days: Option<u64>, keep: Option<&str>) -> Result<Vec<String>, AppError> {
+    let cutoff = keep_days.map(|days| now().saturating_sub(days * 24 * 60 * 60));
+    let mut removed = Vec::new();
+    for (position, backup) in list(data_dir).into_iter().enumerate() {
+        let too_many = keep_count.is_some_and(|count| position >= count);
+        let too_old = cutoff.is_some_and(|cutoff| backup.created < cutoff);
+        if (too_many || too_old) && keep != Some(backup.id.as_str()) {
+            let path = backup_path(data_dir, &backup.id)?;
+            fs::remove_dir_all(&path).map_err(|e| AppError::io(&e, format!("Failed to remove backup {}: {}", backup.id, e)).at(&path))?;
+            println!("Pruned backup {}", backup.id);
+            removed.push(backup.id);
+        }
+    }
+    Ok(removed)
+}
+
+/// Puts the backed up subtree back in place. The current state of the subtree is
+/// backed up first, so a restore can itself be restored. Like a batch, the restore
+/// goes through an anchor on the scanned directory and never follows a symbolic link.
+pub fn restore(data_dir: &Path, id: &str) -> Result<RestoreReport, AppError> {
+    let manifest = inspect(data_dir, id)?;
+    if manifest.mode == BackupMode::Manifest {
+        return Err(AppError::new(ErrorType::InvalidInput, format!("Backup {} only records the layout and cannot be restored", id))
+            .at(backup_path(data_dir, id)?));
+    }
+    let root = PathBuf::from(&manifest.root);
+    let anchor = open_anchor(Path::new(&manifest.base), &[])?;
+    if !anchor.is_dir(&root).unwrap_or(false) {
+        return Err(AppError::new(ErrorType::FileNotFound, format!("{} no longer exists; it may have been renamed", root.display())).at(&root));
+    }
+
+    let replaced = create_backup(data_dir, Path::new(&manifest.base), &root, &[], BackupMode::HardLink, BackupMode::Copy)?;
+
+    // 先在旁边组装好恢复后的子树，再原子地交换（不支持时用两次重命名）替换；
+    // 使用根目录旁边允许的临时名称，根目录自身也可以这样恢复
+    let name = root.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let temporary = || root.with_file_name(format!(".treenamer-tmp-{}-{}", uuid::Uuid::new_v4().simple(), name));
+    let staging = temporary();
+    let discard = |path: &Path| {
+        if let Err(e) = anchor.remove_all(path) {
+            println!("Failed to remove {}: {}", path.display(), e);
+        }
+    };
+    anchor.create_dir(&staging)
+        .map_err(|e| AppError::io(&e, format!("Failed to restore backup {}: {}", id, e)).at(&staging))?;
+    let pinned = anchor.pin(&staging)
+        .map_err(|e| AppError::io(&e, format!("Failed to restore backup {}: {}", id, e)).at(&staging))?;
+    let data = backup_path(data_dir, id)?.join("data");
+    // 硬链接备份恢复时仍然链接，复制备份恢复时复制，保持备份不变
+    let link = manifest.mode == BackupMode::HardLink;
+    if let Err(e) = materialize(&manifest.entries, &data, &pinned.path(), link).or_else(|e| {
+        // 硬链接失败（如跨卷）时改为复制
+        if !link {
+            return Err(e);
+        }
+        anchor.remove_all(&staging)
+            .and_then(|_| anchor.create_dir(&staging))
+            .and_then(|_| materialize(&manifest.entries, &data, &pinned.path(), false))
+    }) {
+        discard(&staging);
+        return Err(AppError::io(&e, format!("Failed to restore backup {}: {}", id, e)).at(&staging));
+    }
+    restore_permissions(&manifest.entries, &pinned.path());
+    drop(pinned);
+
+    let aside = match anchor.exchange(&staging, &root) {
+        // 交换之后原来的子树在 staging 中
+        Ok(_) => staging,
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+            let aside = temporary();
+            anchor.rename_noreplace(&root, &aside).map_err(|e| {
+                discard(&staging);
+                AppError::io(&e, format!("Failed to move {} aside: {}", root.display(), e)).at(&root)
+            })?;
+            if let Err(e) = anchor.rename_noreplace(&staging, &root) {
+                let _ = anchor.rename_noreplace(&aside, &root);
+                discard(&staging);
+                return Err(AppError::io(&e, format!("Failed to put the restored {} in place: {}", root.display(), e)).at(&root));
+            }
+            aside
+        },
+        Err(e) => {
+            discard(&staging);
+            return Err(AppError::io(&e, format!("Failed to put the restored {} in place: {}", root.display(), e)).at(&root));
+        },
+    };
+    discard(&aside);
+
+    println!("Restored backup {} to {}", id, root.display());
+    Ok(RestoreReport { id: id.to_string(), root: manifest.root, restored: manifest.entries.len(), replaced_backup: replaced.id })
+}
+
+// 目录是新建的，需要恢复权限；文件的权限随复制或硬链接保留
+#[cfg(unix)]
+fn restore_permissions(entries: &[BackupEntry], root: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+    // 由内向外设置，避免先去掉父目录的写权限
+    for entry in entries.iter().rev().filter(|entry| entry.kind == BackupEntryKind::Dir) {
+        if let Some(mode) = entry.permissions {
+            if let Err(e) = fs::set_permissions(relative_to(root, &entry.path), fs::Permissions::from_mode(mode)) {
+                println!("Failed to restore permissions of {}: {}", entry.path, e);
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn restore_permissions(_: &[BackupEntry], _: &Path) {}
+
+#[command]
+pub fn list_backups() -> Vec<BackupSummary> {
+    list(&app_data_dir())
+}
+
+#[command]
+pub fn inspect_backup(id: String) -> Result<BackupManifest, AppError> {
+    inspect(&app_data_dir(), &id)
+}
+
+#[command]
+pub fn restore_backup(id: String) -> Result<RestoreReport, AppError> {
+    restore(&app_data_dir(), &id)
+}
+
+/// Applies a retention policy now; without arguments the configured one is used.
+#[command]
+pub fn prune_backups(
+    #[allow(non_snake_case)] keepCount: Option<usize>,
+    #[allow(non_snake_case)] keepDays: Option<u64>,
+) -> Result<Vec<String>, AppError> {
+    let data_dir = app_data_dir();
+    let settings = load_settings(&data_dir)?;
+    if keepCount.is_none() && keepDays.is_none() {
+        return prune(&data_dir, settings.keep_count, settings.keep_days, None);
+    }
+    prune(&data_dir, keepCount, keepDays, None)
+}
+
+#[command]
+pub fn get_backup_settings() -> Result<BackupSettings, AppError> {
+    load_settings(&app_data_dir())
+}
+
+#[command]
+pub fn set_backup_settings(settings: BackupSettings) -> Result<(), AppError> {
+    save_settings(&app_data_dir(), &settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::test_support::{names, path, TempDir};
+    use crate::commands::fs::execute_operations;
+
+    #[test]
+    fn test_affected_root_is_the_common_parent() {
+        let work = TempDir::new("backup");
+        fs::create_dir_all(work.join("a/b/c")).unwrap();
+        fs::create_dir_all(work.join("a/d")).unwrap();
+
+        let rename = FileOperation::Rename { from: path(&work, "a/b/c/x"), to: path(&work, "a/b/c/y") };
+        assert_eq!(affected_root(&work, std::slice::from_ref(&rename)), work.join("a/b/c"));
+        let moved = FileOperation::Move { from: path(&work, "a/d/z"), to: path(&work, "a/new/z") };
+        assert_eq!(affected_root(&work, &[rename, moved]), work.join("a"));
+        // 重命名根目录时备份整个根目录
+        let root_rename = FileOperation::Rename { from: work.to_string_lossy().to_string(), to: format!("{}-renamed", work.display()) };
+        assert_eq!(affected_root(&work, &[root_rename]), work.to_path_buf());
+
+        // 指向树外的符号链接不会成为备份的目录
+        #[cfg(unix)]
+        {
+            let outside = TempDir::new("backup_outside");
+            std::os::unix::fs::symlink(&outside, work.join("a/link")).unwrap();
+            let linked = FileOperation::Rename { from: path(&work, "a/link/x"), to: path(&work, "a/link/y") };
+            assert_eq!(affected_root(&work, &[linked]), work.join("a"));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_restore_does_not_follow_symlinks() {
+        let work = TempDir::new("backup");
+        let (root, data, outside) = (work.join("root"), work.join("data"), work.join("outside"));
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(root.join("docs/a.txt"), "a").unwrap();
+        fs::write(outside.join("keep.txt"), "keep").unwrap();
+        save_settings(&data, &BackupSettings { mode: BackupMode::Copy, ..BackupSettings::default() }).unwrap();
+
+        let operations = vec![FileOperation::Rename { from: path(&root, "docs/a.txt"), to: path(&root, "docs/b.txt") }];
+        let manifest = backup_before_batch(&data, &root, &operations).unwrap().unwrap();
+        assert_eq!(manifest.root, root.join("docs").to_string_lossy());
+
+        // 备份的目录被换成了指向树外的符号链接
+        fs::rename(root.join("docs"), root.join("moved")).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("docs")).unwrap();
+        let error = restore(&data, &manifest.id).unwrap_err();
+        assert_eq!(error.error_type, ErrorType::FileNotFound);
+        assert_eq!(names(&outside), vec!["keep.txt"]);
+        assert_eq!(names(&root), vec!["docs", "moved"]);
+    }
+
+    #[test]
+    fn test_backups_are_restored_for_each_mode() {
+        for mode in [BackupMode::HardLink, BackupMode::Copy] {
+            let work = TempDir::new("backup");
+            let (root, data) = (work.join("root"), work.join("data"));
+            fs::create_dir_all(root.join("docs")).unwrap();
+            fs::write(root.join("docs/a.txt"), "a").unwrap();
+            fs::write(root.join("b.txt"), "b").unwrap();
+            save_settings(&data, &BackupSettings { mode, ..BackupSettings::default() }).unwrap();
+
+            let operations = vec![
+                FileOperation::Rename { from: path(&root, "docs"), to: path(&root, "notes") },
+                FileOperation::Delete { path: path(&root, "b.txt") },
+            ];
+            let manifest = backup_before_batch(&data, &root, &operations).unwrap().unwrap();
+            assert_eq!(manifest.mode, mode);
+            assert_eq!(manifest.root, root.to_string_lossy());
+            assert_eq!(manifest.entries.iter().map(|e| e.path.as_str()).collect::<Vec<_>>(), vec!["b.txt", "docs", "docs/a.txt"]);
+            execute_operations(&root, &operations);
+            assert_eq!(names(&root), vec!["notes"]);
+
+            let report = restore(&data, &manifest.id).unwrap();
+            assert_eq!(report.restored, 3);
+            assert_eq!(names(&root), vec!["b.txt", "docs"]);
+            assert_eq!(fs::read_to_string(root.join("docs/a.txt")).unwrap(), "a");
+            assert_eq!(fs::read_to_string(root.join("b.txt")).unwrap(), "b");
+
+            // 恢复前的状态也被备份
+            let replaced = inspect(&data, &report.replaced_backup).unwrap();
+            assert_eq!(replaced.entries.iter().map(|e| e.path.as_str()).collect::<Vec<_>>(), vec!["notes", "notes/a.txt"]);
+            assert_eq!(list(&data).len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_manifest_backups_and_retention() {
+        let work = TempDir::new("backup");
+        let (root, data) = (work.join("root"), work.join("data"));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        save_settings(&data, &BackupSettings { mode: BackupMode::Manifest, keep_count: Some(2), ..BackupSettings::default() }).unwrap();
+
+        let operations = vec![FileOperation::Rename { from: path(&root, "a.txt"), to: path(&root, "b.txt") }];
+        let first = backup_before_batch(&data, &root, &operations).unwrap().unwrap();
+        assert!(!backup_path(&data, &first.id).unwrap().join("data").exists());
+        assert!(restore(&data, &first.id).is_err());
+
+        for _ in 0..3 {
+            backup_before_batch(&data, &root, &operations).unwrap();
+        }
+        assert_eq!(list(&data).len(), 2);
+
+        // 按时间清理：把一个备份改成 40 天前创建
+        let mut old = inspect(&data, &list(&data)[1].id).unwrap();
+        old.created -= 40 * 24 * 60 * 60;
+        fs::write(backup_path(&data, &old.id).unwrap().join("manifest.json"), serde_json::to_string(&old).unwrap()).unwrap();
+        assert_eq!(prune(&data, None, Some(30), None).unwrap(), vec![old.id]);
+        assert_eq!(list(&data).len(), 1);
+
+        save_settings(&data, &BackupSettings { enabled: false, ..BackupSettings::default() }).unwrap();
+        assert!(backup_before_batch(&data, &root, &operations).unwrap().is_none());
+        assert!(inspect(&data, "../settings").is_err());
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+use crate::commands::conflicts::{Conflict, ConflictKind};
+use crate::commands::fs::{to_absolute_path, FileOperation, NodeEntry, PlanOptions};
+use crate::commands::plan::{compute_dependencies, source_and_target};
+
+/// What to do when the target of an operation already exists on disk without being
+/// part of the scanned tree.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CollisionPolicy {
+    /// Report a conflict; nothing is applied until it is resolved
+    #[default]
+    Fail,
+    /// Leave the existing entry alone and drop the operation, with everything that depends on it
+    Skip,
+    /// Delete the existing entry first; the batch is always backed up when it overwrites.
+    /// Only a file replaces a file and only a directory replaces an empty directory,
+    /// anything else stays a conflict
+    Overwrite,
+    /// Move the contents of the directory into the existing directory
+    Merge,
+    /// Use the first free name of the form `name (1).ext`
+    Suffix,
+}
+
+/// A collision in a plan and how it is handled, for the preview.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Collision {
+    pub node_id: String,
+    /// The existing entry the operation collides with
+    pub path: String,
+    pub policy: CollisionPolicy,
+    /// The name used instead (`Suffix`)
+    pub renamed_to: Option<String>,
+    /// Number of operations left out (`Skip`)
+    pub skipped: usize,
+}
+
+/// A plan's operations after the collision policies were applied.
+pub(crate) struct Resolved {
+    /// (node ID, operation, policy that shaped it)
+    pub operations: Vec<(String, FileOperation, Option<CollisionPolicy>)>,
+    /// Conflicts no policy resolved
+    pub conflicts: Vec<Conflict>,
+    pub collisions: Vec<Collision>,
+}
+
+fn target(operation: &FileOperation) -> Option<&str> {
+    source_and_target(operation).1
+}
+
+// 节点自己的策略优先于计划的策略
+fn policy_for(options: &PlanOptions, node_id: &str) -> CollisionPolicy {
+    options.collision_overrides.get(node_id).copied().unwrap_or(options.collision)
+}
+
+// name (1).ext、name (2).ext ……；目录和没有扩展名的名称直接加在后面
+fn suffixed_name(name: &str, is_dir: bool, counter: usize) -> String {
+    let path = Path::new(name);
+    match (is_dir, path.file_stem(), path.extension()) {
+        (false, Some(stem), Some(extension)) => format!("{} ({}).{}", stem.to_string_lossy(), counter, extension.to_string_lossy()),
+        _ => format!("{} ({})", name, counter),
+    }
+}
+
+/// Gives every node whose final name exists on disk and whose policy is `Suffix` the
+/// first free name of the form `name (1).ext`, directly in the modified tree, so the
+/// planner only ever sees the new name. Names used by the final tree and by anything
+/// on disk in that directory are not free.
+pub(crate) fn apply_suffixes(
+    base_path: &Path,
+    original: &[NodeEntry],
+    modified: &mut [NodeEntry],
+    conflicts: &[Conflict],
+    options: &PlanOptions,
+) -> Vec<Collision> {
+    let original_paths: HashMap<&str, &str> = original.iter().map(|entry| (entry.id.as_str(), entry.path.as_str())).collect();
+    let mut collisions = Vec::new();
+    for conflict in conflicts {
+        let (ConflictKind::ExistsOnDisk, [node_id]) = (&conflict.kind, conflict.node_ids.as_slice()) else { continue };
+        if policy_for(options, node_id) != CollisionPolicy::Suffix {
+            continue;
+        }
+        let final_name = Path::new(&conflict.path).file_name().map(|n| n.to_string_lossy().to_string());
+        let Some(index) = modified.iter().position(|entry| entry.id == *node_id && Some(&entry.name) == final_name.as_ref()) else { continue };
+        let entry = &modified[index];
+        let Some(parent) = entry.parent else {
+            // 根目录改名：在上级目录中找空闲名称
+            let Some(parent) = base_path.parent() else { continue };
+            let renamed = (1..).map(|counter| suffixed_name(&entry.name, true, counter))
+                .find(|candidate| fs::symlink_metadata(parent.join(candidate)).is_err())
+                .unwrap_or_default();
+            collisions.push(Collision { node_id: node_id.clone(), path: conflict.path.clone(), policy: CollisionPolicy::Suffix, renamed_to: Some(renamed.clone()), skipped: 0 });
+            rename_entry(modified, index, renamed);
+            continue;
+        };
+
+        // 扫描时该目录在磁盘上的位置
+        let disk_dir = original_paths.get(modified[parent].id.as_str()).map(|path| to_absolute_path(base_path, path));
+        let siblings: HashSet<&str> = modified.iter()
+            .filter(|other| other.parent == Some(parent))
+            .map(|other| other.name.as_str())
+            .collect();
+        let renamed = (1..).map(|counter| suffixed_name(&entry.name, entry.is_dir, counter))
+            .find(|candidate| !siblings.contains(candidate.as_str())
+                && disk_dir.as_ref().is_none_or(|dir| fs::symlink_metadata(Path::new(dir).join(candidate)).is_err()))
+            .unwrap_or_default();
+        println!("Collision at {} handled by renaming to {}", conflict.path, renamed);
+        collisions.push(Collision { node_id: node_id.clone(), path: conflict.path.clone(), policy: CollisionPolicy::Suffix, renamed_to: Some(renamed.clone()), skipped: 0 });
+        rename_entry(modified, index, renamed);
+    }
+    collisions
+}
+
+// 修改条目的名称，并重新计算它之后所有条目的路径（条目按先序排列，父节点在前）
+fn rename_entry(entries: &mut [NodeEntry], index: usize, name: String) {
+    entries[index].name = name;
+    for i in index..entries.len() {
+        entries[i].path = match entries[i].parent {
+            Some(parent) => format!("{}/{}", entries[parent].path, entries[i].name),
+            None => entries[i].name.clone(),
+        };
+    }
+}
+
+// 计划开始前条目在磁盘上的位置：倒序撤回前面的操作对它和上级目录的改名和移动
+fn path_on_disk(earlier: &[(String, FileOperation, Option<CollisionPolicy>)], path: &str) -> PathBuf {
+    let mut path = PathBuf::from(path);
+    let moved = |path: &Path, from: &str, to: &str| path.strip_prefix(to).ok().map(|rest| {
+        if rest.as_os_str().is_empty() { PathBuf::from(from) } else { Path::new(from).join(rest) }
+    });
+    for (_, operation, _) in earlier.iter().rev() {
+        let before = match operation {
+            FileOperation::Rename { from, to } | FileOperation::Move { from, to } => moved(&path, from, to),
+            FileOperation::Exchange { from, to } => moved(&path, from, to).or_else(|| moved(&path, to, from)),
+            _ => None,
+        };
+        if let Some(before) = before {
+            path = before;
+        }
+    }
+    path
+}
+
+// 只覆盖同类的条目：文件覆盖文件，目录只覆盖空目录；符号链接和其他条目不覆盖
+fn can_overwrite(path: &Path, is_dir: bool) -> bool {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if is_dir => metadata.is_dir() && fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_none()),
+        Ok(metadata) => metadata.is_file(),
+        Err(_) => false,
+    }
+}
+
+/// Applies the collision policy of each node (its override, otherwise the plan's
+/// policy) to the `ExistsOnDisk` conflicts of an ordered plan. `dirs` holds the IDs
+/// of the nodes that are directories and `root` is the scanned directory, whose own
+/// rename can only be skipped or suffixed.
+pub(crate) fn resolve_collisions(
+    root: &Path,
+    ordered: Vec<(String, FileOperation)>,
+    conflicts: Vec<Conflict>,
+    dirs: &HashSet<&str>,
+    options: &PlanOptions,
+) -> Resolved {
+    let dependencies = compute_dependencies(ordered.iter().map(|(_, op)| op));
+    let mut operations: Vec<(String, FileOperation, Option<CollisionPolicy>)> = ordered.into_iter()
+        .map(|(node_id, operation)| (node_id, operation, None))
+        .collect();
+    let mut dropped = vec![false; operations.len()];
+    let mut deletes_before: HashMap<usize, Vec<String>> = HashMap::new();
+    let mut remaining = Vec::new();
+    let mut collisions = Vec::new();
+
+    for conflict in conflicts {
+        let node_id = match (&conflict.kind, conflict.node_ids.as_slice()) {
+            (ConflictKind::ExistsOnDisk, [node_id]) => node_id.clone(),
+            _ => {
+                remaining.push(conflict);
+                continue;
+            },
+        };
+        let policy = policy_for(options, &node_id);
+        // 把节点放到冲突名称上的最后一个操作；上级目录之后可能还会改名，所以只比较名称
+        let name = Path::new(&conflict.path).file_name();
+        let index = operations.iter().rposition(|(id, op, _)| *id == node_id && target(op).is_some_and(|t| Path::new(t).file_name() == name));
+        let Some(index) = index.filter(|&index| !dropped[index]) else {
+            remaining.push(conflict);
+            continue;
+        };
+        // 执行到这个操作时已有条目所在的路径
+        let existing = target(&operations[index].1).unwrap_or_default().to_string();
+        // 根目录自身的重命名落在扫描目录之外
+        let is_root = !Path::new(&existing).starts_with(root);
+        let is_dir = dirs.contains(node_id.as_str());
+        // 规划时已有条目在磁盘上的位置，只用来检查它的类型
+        let on_disk = path_on_disk(&operations[..index], &existing);
+        let mut collision = Collision { node_id: node_id.clone(), path: conflict.path.clone(), policy, renamed_to: None, skipped: 0 };
+
+        match policy {
+            CollisionPolicy::Fail => {
+                remaining.push(conflict);
+                continue;
+            },
+            CollisionPolicy::Skip => {
+                dropped[index] = true;
+                collision.skipped = 1;
+                for later in index + 1..operations.len() {
+                    if !dropped[later] && dependencies[later].iter().any(|&dependency| dropped[dependency]) {
+                        dropped[later] = true;
+                        collision.skipped += 1;
+                    }
+                }
+            },
+            CollisionPolicy::Overwrite if !is_root => {
+                if !can_overwrite(&on_disk, is_dir) {
+                    let message = format!("{} (only a file or an empty directory of the same kind can be overwritten)", conflict.message);
+                    remaining.push(Conflict { message, ..conflict });
+                    continue;
+                }
+                let deletes = deletes_before.entry(index).or_default();
+                if !deletes.contains(&existing) {
+                    deletes.push(existing);
+                }
+                operations[index].2 = Some(policy);
+            },
+            CollisionPolicy::Merge if !is_root && is_dir && fs::symlink_metadata(&on_disk).is_ok_and(|metadata| metadata.is_dir()) => {
+                let (_, operation, applied) = &mut operations[index];
+                match operation {
+                    FileOperation::Rename { from, to } | FileOperation::Move { from, to } => {
+                        *operation = FileOperation::Merge { from: from.clone(), to: to.clone() };
+                    },
+                    // 目录已经存在时新建目录什么也不做
+                    FileOperation::CreateDir { .. } => {},
+                    _ => {
+                        remaining.push(Conflict { message: format!("{} (a copy cannot be merged)", conflict.message), ..conflict });
+                        continue;
+                    },
+                }
+                *applied = Some(policy);
+            },
+            _ => {
+                let message = format!("{} (the {:?} policy cannot be applied here)", conflict.message, policy);
+                remaining.push(Conflict { message, ..conflict });
+                continue;
+            },
+        }
+        println!("Collision at {} handled with {:?}", collision.path, policy);
+        collisions.push(collision);
+    }
+
+    let mut resolved = Vec::with_capacity(operations.len() + deletes_before.values().map(Vec::len).sum::<usize>());
+    for (index, (node_id, operation, policy)) in operations.into_iter().enumerate() {
+        if dropped[index] {
+            continue;
+        }
+        for path in deletes_before.remove(&index).unwrap_or_default() {
+            resolved.push((node_id.clone(), FileOperation::Delete { path }, Some(CollisionPolicy::Overwrite)));
+        }
+        resolved.push((node_id, operation, policy));
+    }
+    Resolved { operations: resolved, conflicts: remaining, collisions }
+}
+
+/// Whether a batch deletes an entry to put something else in its place, which
+/// is what the `Overwrite` policy produces.
+pub fn overwrites(operations: &[FileOperation]) -> bool {
+    operations.iter().enumerate().any(|(index, operation)| match operation {
+        FileOperation::Delete { path } => operations[index + 1..].iter().any(|later| target(later) == Some(path.as_str())),
+        _ => false,
+    })
+}
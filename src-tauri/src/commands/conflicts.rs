@@ -0,0 +1,221 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use crate::commands::fs::{to_absolute_path, NodeEntry, PlanOptions};
+use crate::commands::volume::is_same_file;
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub enum ConflictKind {
+    /// Two nodes end up with the same name in the same directory
+    DuplicateTarget,
+    /// The target exists on disk but was never part of the scanned tree
+    /// (hidden, excluded, beyond the depth limit or created after the scan)
+    ExistsOnDisk,
+    /// Two names differ only by case on a case-insensitive volume
+    CaseCollision,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Conflict {
+    pub kind: ConflictKind,
+    pub path: String,
+    pub node_ids: Vec<String>,
+    pub message: String,
+}
+
+/// Checks every target of a plan against the modified tree and the real disk.
+///
+/// Only the final position of each node is checked: names that are vacated by other
+/// operations of the same plan are not conflicts, the planner orders around them.
+pub(crate) fn find_conflicts(
+    base_path: &Path,
+    original: &[NodeEntry],
+    modified: &[NodeEntry],
+    options: &PlanOptions
+) -> Vec<Conflict> {
+    let mut conflicts = Vec::new();
+    let case_insensitive = is_case_insensitive(base_path);
+    println!("Checking conflicts (case-insensitive volume: {})", case_insensitive);
+
+    // 根目录改名时，上级目录中不能已经存在同名条目
+    if let (Some(orig_root), Some(new_root), Some(parent)) = (original.first(), modified.first(), base_path.parent()) {
+        let target = parent.join(&new_root.name);
+        let same_entry = fs::symlink_metadata(&target).ok()
+            .zip(fs::symlink_metadata(base_path).ok())
+            .is_some_and(|(a, b)| is_same_file(&a, &b, &target, base_path));
+        if orig_root.name != new_root.name && target.exists() && !same_entry {
+            conflicts.push(Conflict {
+                kind: ConflictKind::ExistsOnDisk,
+                path: target.to_string_lossy().to_string(),
+                node_ids: vec![new_root.id.clone()],
+                message: format!("Cannot rename the root directory: {} already exists", target.display()),
+            });
+        }
+    }
+
+    let original_by_id: HashMap<&str, &NodeEntry> = original.iter()
+        .map(|entry| (entry.id.as_str(), entry))
+        .collect();
+    let modified_ids: HashSet<&str> = modified.iter().map(|entry| entry.id.as_str()).collect();
+    let mut modified_paths: HashMap<&str, &str> = HashMap::new();
+    for entry in modified {
+        modified_paths.entry(entry.id.as_str()).or_insert(entry.path.as_str());
+    }
+
+    // 最终状态下每个目录中的条目：父目录ID -> [(名称, 节点ID, 是否目录, 是否是新位置)]
+    let mut children: HashMap<&str, Vec<(&str, &str, bool, bool)>> = HashMap::new();
+    for entry in modified {
+        let Some(parent) = entry.parent else { continue };
+        let parent_id = modified[parent].id.as_str();
+        let unchanged = original_by_id.get(entry.id.as_str()).is_some_and(|orig| {
+            orig.name == entry.name && orig.parent.map(|p| original[p].id.as_str()) == Some(parent_id)
+        });
+        children.entry(parent_id).or_default().push((&entry.name, &entry.id, entry.is_dir, !unchanged));
+    }
+
+    // 没有启用删除时，从文本中移除的节点仍然留在磁盘上
+    if !options.allow_delete {
+        for entry in original {
+            let Some(parent) = entry.parent else { continue };
+            let parent_id = original[parent].id.as_str();
+            if !modified_ids.contains(entry.id.as_str()) && modified_ids.contains(parent_id) {
+                children.entry(parent_id).or_default().push((&entry.name, &entry.id, entry.is_dir, false));
+            }
+        }
+    }
+
+    // 原始树中每个目录已扫描到的名称
+    let mut scanned_names: HashMap<&str, HashSet<&str>> = HashMap::new();
+    for entry in original {
+        if let Some(parent) = entry.parent {
+            scanned_names.entry(original[parent].id.as_str()).or_default().insert(entry.name.as_str());
+        }
+    }
+
+    let mut parent_ids: Vec<&str> = children.keys().copied().collect();
+    parent_ids.sort();
+    for parent_id in parent_ids {
+        let entries = &children[parent_id];
+        if !entries.iter().any(|&(_, _, _, incoming)| incoming) {
+            continue;
+        }
+
+        let final_path = |name: &str| {
+            to_absolute_path(base_path, &format!("{}/{}", modified_paths[parent_id], name))
+        };
+
+        // 同一目录中的重复名称；两个同名目录会被合并，不算冲突
+        let mut by_name: HashMap<&str, Vec<(&str, bool)>> = HashMap::new();
+        let mut by_folded: HashMap<String, Vec<(&str, &str)>> = HashMap::new();
+        for &(name, id, is_dir, _) in entries {
+            by_name.entry(name).or_default().push((id, is_dir));
+            by_folded.entry(name.to_lowercase()).or_default().push((name, id));
+        }
+        let mut names: Vec<&str> = by_name.keys().copied().collect();
+        names.sort();
+        for name in names {
+            let nodes = &by_name[name];
+            if nodes.len() > 1 && nodes.iter().any(|&(_, is_dir)| !is_dir) {
+                conflicts.push(Conflict {
+                    kind: ConflictKind::DuplicateTarget,
+                    path: final_path(name),
+                    node_ids: nodes.iter().map(|&(id, _)| id.to_string()).collect(),
+                    message: format!("{} nodes would be named {}", nodes.len(), final_path(name)),
+                });
+            }
+        }
+
+        if case_insensitive {
+            let mut folded_names: Vec<&String> = by_folded.keys().collect();
+            folded_names.sort();
+            for folded in folded_names {
+                let nodes = &by_folded[folded];
+                let distinct: HashSet<&str> = nodes.iter().map(|&(name, _)| name).collect();
+                if distinct.len() > 1 {
+                    conflicts.push(Conflict {
+                        kind: ConflictKind::CaseCollision,
+                        path: final_path(nodes[0].0),
+                        node_ids: nodes.iter().map(|&(_, id)| id.to_string()).collect(),
+                        message: format!("Names differ only by case: {}", nodes.iter().map(|&(name, _)| name).collect::<Vec<_>>().join(", ")),
+                    });
+                }
+            }
+        }
+
+        // 与磁盘上不在扫描结果中的条目比较（隐藏文件、被排除的目录等）
+        let Some(parent) = original_by_id.get(parent_id) else { continue };
+        let empty = HashSet::new();
+        let scanned = scanned_names.get(parent_id).unwrap_or(&empty);
+        let unseen = unseen_entries(&to_absolute_path(base_path, &parent.path), scanned);
+        if unseen.is_empty() {
+            continue;
+        }
+
+        for &(name, id, _, incoming) in entries {
+            if !incoming {
+                continue;
+            }
+            let clash = unseen.iter().find(|disk_name| {
+                disk_name.as_str() == name || (case_insensitive && disk_name.to_lowercase() == name.to_lowercase())
+            });
+            if let Some(disk_name) = clash {
+                let kind = if disk_name == name { ConflictKind::ExistsOnDisk } else { ConflictKind::CaseCollision };
+                conflicts.push(Conflict {
+                    kind,
+                    path: final_path(name),
+                    node_ids: vec![id.to_string()],
+                    message: format!("{} already exists on disk as {} but is not part of the scanned tree", final_path(name), disk_name),
+                });
+            }
+        }
+    }
+
+    for conflict in &conflicts {
+        println!("Conflict: {:?} {}", conflict.kind, conflict.message);
+    }
+    conflicts
+}
+
+// 读取目录中不在扫描结果里的条目名称
+fn unseen_entries(dir: &str, scanned: &HashSet<&str>) -> Vec<String> {
+    match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|name| !scanned.contains(name.as_str()))
+            .collect(),
+        Err(e) => {
+            println!("Failed to read directory {}: {}", dir, e);
+            Vec::new()
+        }
+    }
+}
+
+/// Probes whether the volume holding `dir` treats names case-insensitively,
+/// without writing anything: an existing name is looked up with its case flipped.
+pub fn is_case_insensitive(dir: &Path) -> bool {
+    let mut candidates: Vec<PathBuf> = vec![dir.to_path_buf()];
+    if let Ok(entries) = fs::read_dir(dir) {
+        candidates.extend(entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).take(32));
+    }
+
+    for path in candidates {
+        let Some(name) = path.file_name().map(|n| n.to_string_lossy().to_string()) else { continue };
+        let flipped: String = name.chars()
+            .map(|c| if c.is_lowercase() { c.to_uppercase().next().unwrap_or(c) } else { c.to_lowercase().next().unwrap_or(c) })
+            .collect();
+        if flipped == name {
+            continue;
+        }
+
+        let flipped_path = path.with_file_name(&flipped);
+        // 翻转大小写后的名称本身也存在，说明卷区分大小写
+        return match (fs::symlink_metadata(&path), fs::symlink_metadata(&flipped_path)) {
+            (Ok(original), Ok(other)) => is_same_file(&original, &other, &path, &flipped_path),
+            _ => false,
+        };
+    }
+
+    false
+}
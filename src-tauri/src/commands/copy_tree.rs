@@ -0,0 +1,424 @@
+use std::collections::HashMap;
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use filetime::FileTime;
+use serde::{Serialize, Deserialize};
+use tauri::command;
+use xxhash_rust::xxh3::Xxh3;
+use crate::commands::journal::app_data_dir;
+use crate::error::{AppError, ErrorType};
+
+// 每次读写的块大小，也是进度报告的粒度
+pub(crate) const CHUNK_SIZE: usize = 1 << 20;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum EntryKind {
+    Dir,
+    File,
+    Symlink(PathBuf),
+    /// FIFOs, sockets and device files, which are never copied
+    Special,
+}
+
+// 树中的一个条目，relative 为空表示根本身
+pub(crate) struct Entry {
+    pub relative: PathBuf,
+    pub kind: EntryKind,
+    pub metadata: Metadata,
+}
+
+/// What a tree copy could not carry over.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct CopyReport {
+    /// Special files (FIFOs, sockets, devices) that were skipped
+    pub skipped: Vec<String>,
+    /// Ownership or extended attributes that could not be preserved
+    pub warnings: Vec<String>,
+}
+
+/// Hash used to compare a copy with its source before the source is removed.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ChecksumAlgorithm {
+    Md5,
+    /// XXH3-128, several times faster than MD5; enough to catch a bad copy
+    #[default]
+    Xxh3,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct CopySettings {
+    #[serde(default)]
+    pub checksum: ChecksumAlgorithm,
+}
+
+pub fn load_copy_settings(data_dir: &Path) -> Result<CopySettings, AppError> {
+    let path = data_dir.join("copy-settings.json");
+    match fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content)
+            .map_err(|e| AppError::new(ErrorType::SystemError, format!("Invalid copy settings: {}", e)).at(&path)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(CopySettings::default()),
+        Err(e) => Err(AppError::io(&e, format!("Failed to read copy settings: {}", e)).at(&path)),
+    }
+}
+
+pub fn save_copy_settings(data_dir: &Path, settings: &CopySettings) -> Result<(), AppError> {
+    fs::create_dir_all(data_dir).map_err(|e| AppError::io(&e, format!("Failed to create {}: {}", data_dir.display(), e)).at(data_dir))?;
+    let path = data_dir.join("copy-settings.json");
+    let content = serde_json::to_string_pretty(settings).map_err(|e| AppError::new(ErrorType::SystemError, e.to_string()).at(&path))?;
+    fs::write(&path, content).map_err(|e| AppError::io(&e, format!("Failed to save copy settings: {}", e)).at(&path))
+}
+
+// relative 为空时就是 base 本身，避免 join 加上末尾的分隔符
+pub(crate) fn at(base: &Path, relative: &Path) -> PathBuf {
+    if relative.as_os_str().is_empty() { base.to_path_buf() } else { base.join(relative) }
+}
+
+/// Lists `root` and everything below it in pre-order, sorted by name, without
+/// following symlinks.
+pub(crate) fn collect_entries(root: &Path) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut stack = vec![PathBuf::new()];
+    while let Some(relative) = stack.pop() {
+        let path = at(root, &relative);
+        let metadata = fs::symlink_metadata(&path)?;
+        let kind = if metadata.file_type().is_symlink() {
+            EntryKind::Symlink(fs::read_link(&path)?)
+        } else if metadata.is_dir() {
+            let mut names: Vec<_> = fs::read_dir(&path)?.map(|entry| entry.map(|e| e.file_name())).collect::<io::Result<_>>()?;
+            names.sort();
+            stack.extend(names.into_iter().rev().map(|name| relative.join(name)));
+            EntryKind::Dir
+        } else if metadata.is_file() {
+            EntryKind::File
+        } else {
+            EntryKind::Special
+        };
+        entries.push(Entry { relative, kind, metadata });
+    }
+    Ok(entries)
+}
+
+pub(crate) fn total_bytes(entries: &[Entry]) -> u64 {
+    entries.iter().filter(|e| e.kind == EntryKind::File).map(|e| e.metadata.len()).sum()
+}
+
+/// Copies `from` to `to` so that nothing but the path changes: symlinks are recreated
+/// as symlinks, files hard-linked to each other stay hard-linked, and permissions,
+/// access and modification times, ownership (where permitted) and extended attributes
+/// are kept. Special files are skipped and listed in the report.
+///
+/// Like the copy it replaces, an existing directory at the target is merged into and
+/// existing files are replaced.
+pub(crate) fn copy_tree(from: &Path, to: &Path, progress: &mut dyn FnMut(&Path, u64)) -> io::Result<CopyReport> {
+    let entries = collect_entries(from)?;
+    copy_entries(from, to, &entries, progress)
+}
+
+/// Copies entries collected from `from` to `to`; see `copy_tree`. `progress` receives
+/// the file being copied and the number of bytes copied so far.
+pub(crate) fn copy_entries(from: &Path, to: &Path, entries: &[Entry], progress: &mut dyn FnMut(&Path, u64)) -> io::Result<CopyReport> {
+    let mut report = CopyReport::default();
+    // 同一个 inode 第一次复制到的位置
+    let mut linked: HashMap<(u64, u64), PathBuf> = HashMap::new();
+    let mut created_dirs = Vec::new();
+    let mut copied = 0;
+
+    for entry in entries {
+        let (source, target) = (at(from, &entry.relative), at(to, &entry.relative));
+        match &entry.kind {
+            EntryKind::Dir => {
+                // 已有的目录直接合并，保留它自己的属性
+                if !fs::symlink_metadata(&target).map(|m| m.is_dir()).unwrap_or(false) {
+                    fs::create_dir(&target)?;
+                    created_dirs.push(entry);
+                }
+            },
+            EntryKind::Special => {
+                println!("Skipping special file {}", source.display());
+                report.skipped.push(source.to_string_lossy().to_string());
+            },
+            EntryKind::File => {
+                remove_existing(&target)?;
+                let key = link_key(&entry.metadata);
+                if let Some(first) = key.and_then(|key| linked.get(&key)) {
+                    fs::hard_link(first, &target)?;
+                    continue;
+                }
+                copy_file(&source, &target, &mut copied, progress)?;
+                preserve_metadata(&source, &target, entry, &mut report)?;
+                if let Some(key) = key {
+                    linked.insert(key, target);
+                }
+            },
+            EntryKind::Symlink(link) => {
+                remove_existing(&target)?;
+                create_symlink(link, &source, &target)?;
+                preserve_metadata(&source, &target, entry, &mut report)?;
+            },
+        }
+    }
+    // 目录的时间和权限在内容复制完之后设置，从内到外
+    for entry in created_dirs.into_iter().rev() {
+        preserve_metadata(&at(from, &entry.relative), &at(to, &entry.relative), entry, &mut report)?;
+    }
+    Ok(report)
+}
+
+fn copy_file(source: &Path, target: &Path, copied: &mut u64, progress: &mut dyn FnMut(&Path, u64)) -> io::Result<()> {
+    let mut reader = File::open(source)?;
+    let mut writer = OpenOptions::new().write(true).create_new(true).open(target)?;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        writer.write_all(&buffer[..read])?;
+        *copied += read as u64;
+        progress(source, *copied);
+    }
+    writer.sync_all()
+}
+
+// 与 fs::copy 一样替换已有的文件或链接，但不替换目录
+fn remove_existing(target: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(target) {
+        Ok(metadata) if metadata.is_dir() => Err(io::Error::new(io::ErrorKind::IsADirectory,
+            format!("{} is a directory", target.display()))),
+        Ok(_) => fs::remove_file(target),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(unix)]
+fn link_key(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn link_key(_: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+// 顺序很重要：修改所有者会清除 setuid 位，所以先改所有者再设置权限，时间最后设置
+fn preserve_metadata(source: &Path, target: &Path, entry: &Entry, report: &mut CopyReport) -> io::Result<()> {
+    let metadata = &entry.metadata;
+    let (accessed, modified) = (FileTime::from_last_access_time(metadata), FileTime::from_last_modification_time(metadata));
+    preserve_ownership(target, metadata, report);
+    if let EntryKind::Symlink(_) = entry.kind {
+        return filetime::set_symlink_file_times(target, accessed, modified);
+    }
+    copy_xattrs(source, target, report);
+    fs::set_permissions(target, metadata.permissions())?;
+    filetime::set_file_times(target, accessed, modified)
+}
+
+#[cfg(unix)]
+fn preserve_ownership(target: &Path, metadata: &Metadata, report: &mut CopyReport) {
+    use std::os::unix::fs::{lchown, MetadataExt};
+    if let Err(e) = lchown(target, Some(metadata.uid()), Some(metadata.gid())) {
+        report.warnings.push(format!("Could not keep the owner of {}: {}", target.display(), e));
+    }
+}
+
+#[cfg(not(unix))]
+fn preserve_ownership(_: &Path, _: &Metadata, _: &mut CopyReport) {}
+
+#[cfg(unix)]
+fn copy_xattrs(source: &Path, target: &Path, report: &mut CopyReport) {
+    let names = match xattr::list(source) {
+        Ok(names) => names,
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => return,
+        Err(e) => {
+            report.warnings.push(format!("Could not read the extended attributes of {}: {}", source.display(), e));
+            return;
+        },
+    };
+    for name in names {
+        let copied = xattr::get(source, &name).and_then(|value| match value {
+            Some(value) => xattr::set(target, &name, &value),
+            None => Ok(()),
+        });
+        if let Err(e) = copied {
+            report.warnings.push(format!("Could not copy extended attribute {} of {}: {}",
+                name.to_string_lossy(), source.display(), e));
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn copy_xattrs(_: &Path, _: &Path, _: &mut CopyReport) {}
+
+#[cfg(unix)]
+fn create_symlink(link: &Path, _: &Path, target: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(link, target)
+}
+
+#[cfg(windows)]
+fn create_symlink(link: &Path, source: &Path, target: &Path) -> io::Result<()> {
+    if fs::metadata(source).map(|m| m.is_dir()).unwrap_or(false) {
+        std::os::windows::fs::symlink_dir(link, target)
+    } else {
+        std::os::windows::fs::symlink_file(link, target)
+    }
+}
+
+/// Hashes the content of a file.
+pub(crate) fn file_checksum(path: &Path, algorithm: ChecksumAlgorithm) -> io::Result<Vec<u8>> {
+    let mut reader = File::open(path)?;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut md5 = md5::Context::new();
+    let mut xxh3 = Xxh3::new();
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        match algorithm {
+            ChecksumAlgorithm::Md5 => md5.consume(&buffer[..read]),
+            ChecksumAlgorithm::Xxh3 => xxh3.update(&buffer[..read]),
+        }
+    }
+    Ok(match algorithm {
+        ChecksumAlgorithm::Md5 => md5.compute().0.to_vec(),
+        ChecksumAlgorithm::Xxh3 => xxh3.digest128().to_be_bytes().to_vec(),
+    })
+}
+
+/// Checks a copy entry by entry against the entries collected from `from`: each one
+/// must exist at the target with the same kind and link target, and every file must
+/// have the same size and content hash. Skipped special files are not expected, and
+/// other entries already at the target (after a merge) are ignored.
+pub(crate) fn verify_copy(from: &Path, to: &Path, entries: &[Entry], algorithm: ChecksumAlgorithm) -> io::Result<()> {
+    let mismatch = |path: &Path, what: String| io::Error::new(io::ErrorKind::InvalidData,
+        format!("verification failed: {} {}", path.display(), what));
+    for entry in entries.iter().filter(|e| e.kind != EntryKind::Special) {
+        let (source, target) = (at(from, &entry.relative), at(to, &entry.relative));
+        let metadata = fs::symlink_metadata(&target).map_err(|e| mismatch(&target, format!("is missing: {}", e)))?;
+        let kind = if metadata.file_type().is_symlink() {
+            EntryKind::Symlink(fs::read_link(&target)?)
+        } else if metadata.is_dir() {
+            EntryKind::Dir
+        } else {
+            EntryKind::File
+        };
+        if kind != entry.kind {
+            return Err(mismatch(&target, format!("is {:?}, expected {:?}", kind, entry.kind)));
+        }
+        if kind == EntryKind::File {
+            if metadata.len() != entry.metadata.len() {
+                return Err(mismatch(&target, format!("has {} bytes, expected {}", metadata.len(), entry.metadata.len())));
+            }
+            if file_checksum(&source, algorithm)? != file_checksum(&target, algorithm)? {
+                return Err(mismatch(&target, "differs from the source".to_string()));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[command]
+pub fn get_copy_settings() -> Result<CopySettings, AppError> {
+    load_copy_settings(&app_data_dir())
+}
+
+#[command]
+pub fn set_copy_settings(settings: CopySettings) -> Result<(), AppError> {
+    save_copy_settings(&app_data_dir(), &settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_keeps_links_metadata_and_skips_special_files() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        use std::os::unix::net::UnixListener;
+
+        let root = std::env::temp_dir().join(format!("treenamer_copy_{}", uuid::Uuid::new_v4()));
+        let from = root.join("from");
+        fs::create_dir_all(from.join("sub")).unwrap();
+        fs::write(from.join("a.txt"), "a").unwrap();
+        fs::hard_link(from.join("a.txt"), from.join("sub/a-link.txt")).unwrap();
+        std::os::unix::fs::symlink("../a.txt", from.join("sub/symlink")).unwrap();
+        std::os::unix::fs::symlink("missing", from.join("dangling")).unwrap();
+        let _socket = UnixListener::bind(from.join("socket")).unwrap();
+        fs::set_permissions(from.join("a.txt"), fs::Permissions::from_mode(0o604)).unwrap();
+        let old = FileTime::from_system_time(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000));
+        let older = FileTime::from_system_time(SystemTime::UNIX_EPOCH + Duration::from_secs(900_000_000));
+        filetime::set_file_times(from.join("a.txt"), older, old).unwrap();
+        filetime::set_file_times(from.join("sub"), older, old).unwrap();
+        let has_xattr = xattr::set(from.join("a.txt"), "user.treenamer", b"tag").is_ok();
+
+        let mut progress = Vec::new();
+        let report = copy_tree(&from, &root.join("to"), &mut |_, copied| progress.push(copied)).unwrap();
+        let to = root.join("to");
+
+        assert_eq!(report.skipped, vec![from.join("socket").to_string_lossy().to_string()]);
+        assert!(!to.join("socket").exists());
+        assert_eq!(progress, vec![1]);
+        // 硬链接仍然是同一个文件
+        let (a, link) = (fs::metadata(to.join("a.txt")).unwrap(), fs::metadata(to.join("sub/a-link.txt")).unwrap());
+        assert_eq!((a.ino(), a.nlink()), (link.ino(), 2));
+        // 符号链接按原样重建，不跟随
+        assert_eq!(fs::read_link(to.join("sub/symlink")).unwrap(), Path::new("../a.txt"));
+        assert_eq!(fs::read_link(to.join("dangling")).unwrap(), Path::new("missing"));
+        assert_eq!(a.permissions().mode() & 0o777, 0o604);
+        assert_eq!(FileTime::from_last_modification_time(&a), old);
+        assert_eq!(FileTime::from_last_access_time(&a), older);
+        assert_eq!(FileTime::from_last_modification_time(&fs::metadata(to.join("sub")).unwrap()), old);
+        assert_eq!((a.uid(), a.gid()), (fs::metadata(from.join("a.txt")).unwrap().uid(), fs::metadata(from.join("a.txt")).unwrap().gid()));
+        if has_xattr {
+            assert_eq!(xattr::get(to.join("a.txt"), "user.treenamer").unwrap(), Some(b"tag".to_vec()));
+        }
+        let entries = collect_entries(&from).unwrap();
+        verify_copy(&from, &to, &entries, ChecksumAlgorithm::Md5).unwrap();
+
+        // 再次复制到同一位置时合并目录、替换文件
+        fs::write(to.join("extra.txt"), "extra").unwrap();
+        fs::remove_file(to.join("sub/a-link.txt")).unwrap();
+        fs::write(to.join("sub/a-link.txt"), "stale").unwrap();
+        copy_tree(&from, &to, &mut |_, _| {}).unwrap();
+        assert_eq!(fs::read_to_string(to.join("sub/a-link.txt")).unwrap(), "a");
+        assert!(to.join("extra.txt").exists());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_verification_compares_file_contents() {
+        let root = std::env::temp_dir().join(format!("treenamer_copy_{}", uuid::Uuid::new_v4()));
+        let from = root.join("from");
+        fs::create_dir_all(from.join("sub")).unwrap();
+        fs::write(from.join("sub/data.bin"), vec![1u8; CHUNK_SIZE + 10]).unwrap();
+        let to = root.join("to");
+        copy_tree(&from, &to, &mut |_, _| {}).unwrap();
+        let entries = collect_entries(&from).unwrap();
+
+        for algorithm in [ChecksumAlgorithm::Md5, ChecksumAlgorithm::Xxh3] {
+            verify_copy(&from, &to, &entries, algorithm).unwrap();
+        }
+        assert_ne!(file_checksum(&to.join("sub/data.bin"), ChecksumAlgorithm::Md5).unwrap(),
+            file_checksum(&to.join("sub/data.bin"), ChecksumAlgorithm::Xxh3).unwrap());
+
+        // 大小相同、内容不同
+        let mut data = vec![1u8; CHUNK_SIZE + 10];
+        data[CHUNK_SIZE + 5] = 2;
+        fs::write(to.join("sub/data.bin"), data).unwrap();
+        for algorithm in [ChecksumAlgorithm::Md5, ChecksumAlgorithm::Xxh3] {
+            let error = verify_copy(&from, &to, &entries, algorithm).unwrap_err();
+            assert!(error.to_string().contains("differs from the source"), "{}", error);
+        }
+        fs::remove_file(to.join("sub/data.bin")).unwrap();
+        assert!(verify_copy(&from, &to, &entries, ChecksumAlgorithm::Xxh3).unwrap_err().to_string().contains("is missing"));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
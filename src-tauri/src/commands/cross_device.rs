@@ -0,0 +1,188 @@
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+use serde::Serialize;
+use crate::commands::anchor::Anchor;
+use crate::commands::copy_tree::{collect_entries, copy_entries, total_bytes, verify_copy, ChecksumAlgorithm, CopyReport, EntryKind};
+
+/// Progress of a move that has to copy the data to another device.
+#[derive(Debug, Clone, Serialize)]
+pub struct MoveProgress {
+    pub from: String,
+    pub to: String,
+    /// The file being copied
+    pub current: String,
+    pub bytes_copied: u64,
+    pub bytes_total: u64,
+}
+
+/// Why a cross-device move did not complete.
+#[derive(Debug)]
+pub enum MoveError {
+    /// Copying or verifying failed; the target was left untouched
+    Copy(io::Error),
+    /// The data is in place at the target, but the source could not be removed
+    RemoveSource(io::Error),
+}
+
+type ProgressSink = Box<dyn Fn(&MoveProgress) + Send + Sync>;
+
+static PROGRESS_SINK: OnceLock<ProgressSink> = OnceLock::new();
+
+/// Sets where the progress of cross-device moves is reported. The app forwards it
+/// to the frontend as `move-progress` events.
+pub fn set_progress_sink(sink: impl Fn(&MoveProgress) + Send + Sync + 'static) {
+    let _ = PROGRESS_SINK.set(Box::new(sink));
+}
+
+pub(crate) fn report_progress(progress: &MoveProgress) {
+    if let Some(sink) = PROGRESS_SINK.get() {
+        sink(progress);
+    }
+}
+
+/// Whether a rename failed because source and target are on different filesystems
+/// (`EXDEV`, or `ERROR_NOT_SAME_DEVICE` on Windows).
+pub(crate) fn is_cross_device(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::CrossesDevices
+}
+
+/// Moves `from` to `to` on another filesystem: the data is copied to a hidden name
+/// next to the target with `copy_tree`, checked against the source (sizes and
+/// content hashes), renamed into
+/// place, and only then is the source removed. A failed copy is cleaned up and leaves
+/// the target untouched. Trees containing special files are refused, since those
+/// cannot be copied and would be lost with the source. Every path is resolved
+/// through `anchor`; progress is reported with the original paths.
+pub(crate) fn move_across_devices(anchor: &Anchor, from: &Path, to: &Path, checksum: ChecksumAlgorithm, progress: &mut dyn FnMut(&MoveProgress)) -> Result<CopyReport, MoveError> {
+    let pinned_from = anchor.pin(from).map_err(MoveError::Copy)?;
+    let source = pinned_from.path();
+    let entries = collect_entries(&source).map_err(MoveError::Copy)?;
+    if let Some(special) = entries.iter().find(|e| e.kind == EntryKind::Special) {
+        return Err(MoveError::Copy(io::Error::new(io::ErrorKind::Unsupported,
+            format!("{} is a special file and cannot be moved to another device", from.join(&special.relative).display()))));
+    }
+    let name = to.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let staging = to.with_file_name(format!(".treenamer-xdev-{}-{}", uuid::Uuid::new_v4().simple(), name));
+    let pinned_staging = anchor.pin(&staging).map_err(MoveError::Copy)?;
+    println!("Moving {} to {} across devices through {}", from.display(), to.display(), staging.display());
+
+    let mut state = MoveProgress {
+        from: from.to_string_lossy().to_string(),
+        to: to.to_string_lossy().to_string(),
+        current: String::new(),
+        bytes_copied: 0,
+        bytes_total: total_bytes(&entries),
+    };
+    let copied = copy_entries(&source, &pinned_staging.path(), &entries, &mut |current, bytes_copied| {
+        state.current = current.strip_prefix(&source).map(|relative| from.join(relative)).unwrap_or_else(|_| current.to_path_buf())
+            .to_string_lossy().to_string();
+        state.bytes_copied = bytes_copied;
+        progress(&state);
+    });
+    let verified = copied
+        .and_then(|report| verify_copy(&source, &pinned_staging.path(), &entries, checksum).map(|_| report))
+        .and_then(|report| anchor.rename_noreplace(&staging, to).map(|_| report));
+    let report = match verified {
+        Ok(report) => report,
+        Err(e) => {
+            let _ = remove_entry(anchor, &staging);
+            return Err(MoveError::Copy(e));
+        },
+    };
+    progress(&state);
+    remove_entry(anchor, from).map_err(MoveError::RemoveSource)?;
+    Ok(report)
+}
+
+
+fn remove_entry(anchor: &Anchor, path: &Path) -> io::Result<()> {
+    match anchor.remove_all(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File, FileTimes};
+    use std::time::{Duration, SystemTime};
+    use crate::commands::copy_tree::CHUNK_SIZE;
+    use crate::commands::fs::{apply_operation, FileOperation};
+
+    #[test]
+    fn test_move_copies_verifies_and_removes_the_source() {
+        let root = std::env::temp_dir().join(format!("treenamer_xdev_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(root.join("src/sub")).unwrap();
+        fs::write(root.join("src/sub/big.bin"), vec![7u8; CHUNK_SIZE * 2 + 100]).unwrap();
+        fs::write(root.join("src/small.txt"), "small").unwrap();
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        File::options().write(true).open(root.join("src/small.txt")).unwrap()
+            .set_times(FileTimes::new().set_modified(old)).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::os::unix::fs::symlink("small.txt", root.join("src/link")).unwrap();
+            fs::set_permissions(root.join("src/small.txt"), fs::Permissions::from_mode(0o640)).unwrap();
+        }
+
+        let mut events = Vec::new();
+        move_across_devices(&Anchor::open(&root).unwrap(), &root.join("src"), &root.join("dst"), ChecksumAlgorithm::Md5, &mut |p| events.push(p.bytes_copied)).unwrap();
+
+        assert!(!root.join("src").exists());
+        assert_eq!(fs::read(root.join("dst/sub/big.bin")).unwrap().len(), CHUNK_SIZE * 2 + 100);
+        assert_eq!(fs::read_to_string(root.join("dst/small.txt")).unwrap(), "small");
+        assert_eq!(fs::metadata(root.join("dst/small.txt")).unwrap().modified().unwrap(), old);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::read_link(root.join("dst/link")).unwrap(), Path::new("small.txt"));
+            assert_eq!(fs::metadata(root.join("dst/small.txt")).unwrap().permissions().mode() & 0o777, 0o640);
+        }
+        // 按块报告进度，最后一次报告全部字节
+        assert!(events.len() >= 4, "{:?}", events);
+        assert!(events.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(*events.last().unwrap(), (CHUNK_SIZE * 2 + 105) as u64);
+        let names: Vec<String> = fs::read_dir(&root).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().to_string()).collect();
+        assert_eq!(names, vec!["dst"]);
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rename_to_another_device_falls_back_to_a_verified_copy() {
+        use std::os::unix::fs::MetadataExt;
+        let other = Path::new("/dev/shm");
+        let root = std::env::temp_dir().join(format!("treenamer_xdev_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(root.join("dir")).unwrap();
+        let same_device = match fs::metadata(other) {
+            Ok(metadata) => metadata.dev() == fs::metadata(&root).unwrap().dev(),
+            Err(_) => true,
+        };
+        if same_device {
+            let _ = fs::remove_dir_all(&root);
+            return;
+        }
+        fs::write(root.join("file.txt"), "file").unwrap();
+        fs::write(root.join("dir/inner.txt"), "inner").unwrap();
+        let target = other.join(format!("treenamer_xdev_{}", uuid::Uuid::new_v4()));
+
+        // 目标在所选目录之外，从文件系统的根目录开始解析
+        let anchor = Anchor::open(Path::new("/")).unwrap();
+        for name in ["file.txt", "dir"] {
+            let result = apply_operation(&anchor, &FileOperation::Move {
+                from: root.join(name).to_string_lossy().to_string(),
+                to: target.join(name).to_string_lossy().to_string(),
+            });
+            assert!(result.success, "{}", result.message);
+            assert!(!root.join(name).exists());
+        }
+        assert_eq!(fs::read_to_string(target.join("file.txt")).unwrap(), "file");
+        assert_eq!(fs::read_to_string(target.join("dir/inner.txt")).unwrap(), "inner");
+
+        let _ = fs::remove_dir_all(&root);
+        let _ = fs::remove_dir_all(&target);
+    }
+}
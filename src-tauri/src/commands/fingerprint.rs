@@ -0,0 +1,177 @@
+use std::collections::HashSet;
+use std::fs::{self, Metadata};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use serde::{Deserialize, Serialize};
+use crate::commands::atomic_rename::metadata_identity;
+use crate::commands::fs::{to_absolute_path, NodeEntry};
+
+/// What a node looked like on disk when it was scanned. Symbolic links are
+/// recorded as themselves, never as what they point to.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct NodeFingerprint {
+    /// Device and inode number ("dev:ino"); absent on platforms without them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<String>,
+    /// Last modification time in milliseconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<u64>,
+    /// Size in bytes; absent for directories
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+impl NodeFingerprint {
+    /// Fingerprint of the entry at `path`, without following a symbolic link there.
+    pub fn of(path: &Path) -> Option<Self> {
+        fs::symlink_metadata(path).ok().map(|metadata| Self::from_metadata(&metadata))
+    }
+
+    /// Fingerprint from metadata read with `symlink_metadata`.
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        let modified = metadata.modified().ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_millis() as u64);
+        Self {
+            identity: metadata_identity(metadata),
+            modified,
+            size: if metadata.is_dir() { None } else { Some(metadata.len()) },
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        self.size.is_none()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ExternalChangeKind {
+    /// The entry is gone
+    Missing,
+    /// Another entry took its place
+    Replaced,
+    /// The file is still there but its contents changed
+    Modified,
+}
+
+/// A node involved in a plan that changed on disk after the scan.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExternalChange {
+    pub node_id: String,
+    /// Path in the tree, starting with the root name
+    pub path: String,
+    pub kind: ExternalChangeKind,
+    pub scanned: NodeFingerprint,
+    /// What is on disk now; absent when the entry is missing
+    pub found: Option<NodeFingerprint>,
+    pub message: String,
+}
+
+/// Compares the nodes in `involved` against the fingerprints recorded by the scan.
+/// Directories are only compared by identity, since their size and modification
+/// time change whenever their contents do. Nodes scanned without a fingerprint
+/// are not checked.
+pub(crate) fn find_external_changes(base_path: &Path, entries: &[NodeEntry], involved: &HashSet<&str>) -> Vec<ExternalChange> {
+    let mut checked = HashSet::new();
+    let mut changes = Vec::new();
+
+    for entry in entries {
+        let Some(scanned) = &entry.fingerprint else { continue };
+        if !involved.contains(entry.id.as_str()) || !checked.insert(entry.id.as_str()) {
+            continue;
+        }
+
+        let found = NodeFingerprint::of(Path::new(&to_absolute_path(base_path, &entry.path)));
+        let change = match &found {
+            None => Some((ExternalChangeKind::Missing, format!("{} no longer exists", entry.path))),
+            Some(found) if found.is_dir() != scanned.is_dir() => Some((ExternalChangeKind::Replaced, if found.is_dir() {
+                format!("{} was a file and is now a directory", entry.path)
+            } else {
+                format!("{} was a directory and is now a file", entry.path)
+            })),
+            Some(found) if scanned.identity.is_some() && found.identity != scanned.identity => {
+                Some((ExternalChangeKind::Replaced, format!("{} was replaced by another entry", entry.path)))
+            },
+            Some(found) if found.size != scanned.size => Some((ExternalChangeKind::Modified, format!(
+                "{} was modified (size {} -> {} bytes)", entry.path, scanned.size.unwrap_or(0), found.size.unwrap_or(0)))),
+            Some(found) if !scanned.is_dir() && found.modified != scanned.modified => {
+                Some((ExternalChangeKind::Modified, format!("{} was modified (modification time changed)", entry.path)))
+            },
+            Some(_) => None,
+        };
+
+        if let Some((kind, message)) = change {
+            println!("External change: {}", message);
+            changes.push(ExternalChange {
+                node_id: entry.id.clone(),
+                path: entry.path.clone(),
+                kind,
+                scanned: scanned.clone(),
+                found,
+                message,
+            });
+        }
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::fs::{build_plan, plan_for_apply, PlanOptions};
+    use crate::commands::tree::build_tree;
+
+    #[test]
+    fn test_plan_refuses_sources_changed_since_the_scan() {
+        let root = std::env::temp_dir().join(format!("treenamer_fingerprint_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(root.join("docs")).unwrap();
+        for name in ["a.txt", "b.txt", "c.txt", "untouched.txt", "docs/d.txt"] {
+            fs::write(root.join(name), name).unwrap();
+        }
+
+        let original = serde_json::to_value(build_tree(&root).unwrap()).unwrap();
+        // 改名 a、b、c 和 docs，untouched.txt 保持不变
+        let mut modified = original.clone();
+        for child in modified["children"].as_array_mut().unwrap() {
+            let name = child["name"].as_str().unwrap().to_string();
+            if name != "untouched.txt" {
+                child["name"] = serde_json::json!(format!("new_{}", name));
+            }
+        }
+        let (original, modified) = (original.to_string(), modified.to_string());
+        let base = root.to_string_lossy().to_string();
+        let options = PlanOptions::default();
+
+        let plan = build_plan(&base, &original, &modified, &options).unwrap();
+        assert!(plan.external_changes.is_empty(), "{:?}", plan.external_changes);
+
+        // 扫描之后在外部修改、删除、替换条目；docs 里的内容变化不影响 docs 本身
+        fs::write(root.join("a.txt"), "changed contents").unwrap();
+        fs::remove_file(root.join("b.txt")).unwrap();
+        fs::remove_file(root.join("c.txt")).unwrap();
+        fs::create_dir(root.join("c.txt")).unwrap();
+        fs::write(root.join("untouched.txt"), "changed contents").unwrap();
+        fs::write(root.join("docs/d.txt"), "changed contents").unwrap();
+
+        let plan = build_plan(&base, &original, &modified, &options).unwrap();
+        let mut changes: Vec<(String, ExternalChangeKind)> = plan.external_changes.iter()
+            .map(|change| (change.path.rsplit('/').next().unwrap().to_string(), change.kind))
+            .collect();
+        changes.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(changes, vec![
+            ("a.txt".to_string(), ExternalChangeKind::Modified),
+            ("b.txt".to_string(), ExternalChangeKind::Missing),
+            ("c.txt".to_string(), ExternalChangeKind::Replaced),
+        ]);
+
+        let error = plan_for_apply(&base, &original, &modified, &options).unwrap_err();
+        assert!(error.message.starts_with("The directory changed since it was scanned:"), "{}", error);
+        assert!(error.message.contains("b.txt no longer exists"), "{}", error);
+        assert_eq!(error.error_type, crate::error::ErrorType::ExternalModification);
+        assert!(error.recoverable && error.path.is_some_and(|path| path.starts_with(&base)));
+        assert!(root.join("a.txt").exists());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
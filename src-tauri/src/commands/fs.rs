@@ -1,18 +1,258 @@
-use std::path::Path;
-use std::fs;
+use std::path::{Path, PathBuf};
+use std::fmt;
+use std::time::{Duration, Instant};
 use tauri::command;
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use crate::commands::tree::{strip_metadata_columns, TreeColumns};
+use crate::error::{AppError, ErrorType};
+use crate::commands::plan::{collapse_swaps, compute_dependencies, order_renames, plan_hash, schedule_changes, NodeChange, NodeMove, Plan, PlannedOperation, Slot, TreeState};
+use crate::commands::conflicts::{find_conflicts, Conflict};
+use crate::commands::fingerprint::{find_external_changes, NodeFingerprint};
+use crate::commands::collision::{apply_suffixes, resolve_collisions, CollisionPolicy};
+use crate::commands::volume::is_fold_only_rename;
+use crate::commands::journal::app_data_dir;
+use crate::commands::transaction::apply_batch;
+use crate::commands::simulate::dry_run;
+use crate::commands::copy_tree::{copy_tree, ChecksumAlgorithm, CopyReport, CopySettings};
+use crate::commands::atomic_rename::EXCHANGE_AVAILABLE;
+use crate::commands::anchor::Anchor;
+use crate::commands::cross_device::{is_cross_device, move_across_devices, report_progress, MoveError};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum FileOperation {
     Rename { from: String, to: String },
+    CreateDir { path: String },
+    CreateFile { path: String },
+    Delete { path: String },
+    Move { from: String, to: String },
+    Copy { from: String, to: String },
+    /// Moves the contents of the directory `from` into the existing directory `to`
+    /// and removes `from`
+    Merge { from: String, to: String },
+    /// Atomically swaps the entries at `from` and `to`
+    Exchange { from: String, to: String },
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum OperationKind {
+    Rename,
+    CreateDir,
+    CreateFile,
+    Delete,
+    Move,
+    Copy,
+    Merge,
+    Exchange,
+}
+
+impl FileOperation {
+    pub fn kind(&self) -> OperationKind {
+        match self {
+            FileOperation::Rename { .. } => OperationKind::Rename,
+            FileOperation::CreateDir { .. } => OperationKind::CreateDir,
+            FileOperation::CreateFile { .. } => OperationKind::CreateFile,
+            FileOperation::Delete { .. } => OperationKind::Delete,
+            FileOperation::Move { .. } => OperationKind::Move,
+            FileOperation::Copy { .. } => OperationKind::Copy,
+            FileOperation::Merge { .. } => OperationKind::Merge,
+            FileOperation::Exchange { .. } => OperationKind::Exchange,
+        }
+    }
+    
+    /// Returns the same operation with every path passed through `f`.
+    pub fn map_paths(&self, f: impl Fn(&str) -> String) -> FileOperation {
+        match self {
+            FileOperation::Rename { from, to } => FileOperation::Rename { from: f(from), to: f(to) },
+            FileOperation::CreateDir { path } => FileOperation::CreateDir { path: f(path) },
+            FileOperation::CreateFile { path } => FileOperation::CreateFile { path: f(path) },
+            FileOperation::Delete { path } => FileOperation::Delete { path: f(path) },
+            FileOperation::Move { from, to } => FileOperation::Move { from: f(from), to: f(to) },
+            FileOperation::Copy { from, to } => FileOperation::Copy { from: f(from), to: f(to) },
+            FileOperation::Merge { from, to } => FileOperation::Merge { from: f(from), to: f(to) },
+            FileOperation::Exchange { from, to } => FileOperation::Exchange { from: f(from), to: f(to) },
+        }
+    }
+}
+
+impl fmt::Display for FileOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileOperation::Rename { from, to } => write!(f, "Rename {} -> {}", from, to),
+            FileOperation::CreateDir { path } => write!(f, "CreateDir {}", path),
+            FileOperation::CreateFile { path } => write!(f, "CreateFile {}", path),
+            FileOperation::Delete { path } => write!(f, "Delete {}", path),
+            FileOperation::Move { from, to } => write!(f, "Move {} -> {}", from, to),
+            FileOperation::Copy { from, to } => write!(f, "Copy {} -> {}", from, to),
+            FileOperation::Merge { from, to } => write!(f, "Merge {} -> {}", from, to),
+            FileOperation::Exchange { from, to } => write!(f, "Exchange {} <-> {}", from, to),
+        }
+    }
+}
+
+/// How an operation ended.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum OperationStatus {
+    Ok,
+    /// There was nothing to do, e.g. the directory to create already existed
+    Skipped,
+    Failed,
+    /// The operation took effect but left something behind, see `needs_attention`
+    PartiallyDone,
+}
+
+/// A way of carrying out an operation used when the direct one is not possible.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Fallback {
+    /// Moved to another file system by copying, verifying and removing the source
+    CrossDeviceCopy,
+    /// Renamed in two steps through a temporary name
+    TemporaryName,
+}
+
+#[derive(Debug, Serialize, Clone)]
 pub struct OperationResult {
     pub success: bool,
+    /// Something was left for the user to sort out, e.g. the source of a copied
+    /// directory could not be removed, or a copy could not be verified. Always set for
+    /// `PartiallyDone`; a `Failed` operation sets it when it could not put back what
+    /// it had already moved, such as an exchange that left an entry under a temporary name
+    pub needs_attention: bool,
     pub message: String,
+    pub status: OperationStatus,
+    pub kind: Option<OperationKind>,
+    /// The node the operation belongs to, when it was planned from a tree edit
+    pub node_id: Option<String>,
+    /// Source of the operation (the deleted path for `Delete`)
+    pub from: Option<String>,
+    /// Target of the operation (the created path for `CreateDir` and `CreateFile`)
+    pub to: Option<String>,
+    /// Why the operation failed or only partly took effect
+    pub error: Option<AppError>,
+    pub duration_ms: u64,
+    pub fallback: Option<Fallback>,
+}
+
+impl OperationResult {
+    fn with_status(status: OperationStatus, message: String, error: Option<AppError>) -> Self {
+        OperationResult {
+            success: status != OperationStatus::Failed,
+            needs_attention: status == OperationStatus::PartiallyDone,
+            message,
+            status,
+            kind: None,
+            node_id: None,
+            from: None,
+            to: None,
+            error,
+            duration_ms: 0,
+            fallback: None,
+        }
+    }
+    
+    pub(crate) fn ok(message: String) -> Self {
+        Self::with_status(OperationStatus::Ok, message, None)
+    }
+    
+    pub(crate) fn skipped(message: String) -> Self {
+        Self::with_status(OperationStatus::Skipped, message, None)
+    }
+    
+    pub(crate) fn failed(error: AppError) -> Self {
+        Self::with_status(OperationStatus::Failed, error.message.clone(), Some(error))
+    }
+    
+    /// The operation took effect, but `error` left something for the user to sort out.
+    pub(crate) fn partially_done(error: AppError) -> Self {
+        Self::with_status(OperationStatus::PartiallyDone, error.message.clone(), Some(error))
+    }
+    
+    pub(crate) fn using(self, fallback: Fallback) -> Self {
+        OperationResult { fallback: Some(fallback), ..self }
+    }
+    
+    /// Fills in which operation the result is about and how long it took.
+    pub(crate) fn describe(self, operation: &FileOperation, duration: Duration) -> Self {
+        let (from, to) = match operation {
+            FileOperation::Rename { from, to } | FileOperation::Move { from, to } | FileOperation::Copy { from, to }
+            | FileOperation::Merge { from, to } | FileOperation::Exchange { from, to } => (Some(from.clone()), Some(to.clone())),
+            FileOperation::CreateDir { path } | FileOperation::CreateFile { path } => (None, Some(path.clone())),
+            FileOperation::Delete { path } => (Some(path.clone()), None),
+        };
+        OperationResult {
+            kind: Some(operation.kind()),
+            from,
+            to,
+            duration_ms: duration.as_millis() as u64,
+            ..self
+        }
+    }
+}
+
+/// Totals over the results of a batch.
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct BatchSummary {
+    /// Operations that ran
+    pub total: usize,
+    pub ok: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub partially_done: usize,
+    /// Operations carried out with a fallback
+    pub fallbacks: usize,
+    pub duration_ms: u64,
+}
+
+impl BatchSummary {
+    pub fn of(results: &[OperationResult]) -> Self {
+        let mut summary = BatchSummary { total: results.len(), ..Default::default() };
+        for result in results {
+            match result.status {
+                OperationStatus::Ok => summary.ok += 1,
+                OperationStatus::Skipped => summary.skipped += 1,
+                OperationStatus::Failed => summary.failed += 1,
+                OperationStatus::PartiallyDone => summary.partially_done += 1,
+            }
+            if result.fallback.is_some() {
+                summary.fallbacks += 1;
+            }
+            summary.duration_ms += result.duration_ms;
+        }
+        summary
+    }
+}
+
+/// Fills in the node IDs of the planned operations on their results.
+pub(crate) fn attach_node_ids(results: &mut [OperationResult], node_ids: &[String]) {
+    for (result, node_id) in results.iter_mut().zip(node_ids) {
+        result.node_id = Some(node_id.clone());
+    }
+}
+
+/// What `apply_operations` did, or in a dry run what it would have done.
+#[derive(Debug, Serialize)]
+pub struct ApplyReport {
+    pub dry_run: bool,
+    pub results: Vec<OperationResult>,
+    pub summary: BatchSummary,
+    /// The directory tree after the operations; only filled in by a dry run
+    pub tree: Option<crate::commands::tree::TreeNode>,
+    /// Entries of the modified tree that the operations would not produce
+    pub mismatches: Vec<String>,
+}
+
+/// Options controlling which kinds of operations the planner may emit.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PlanOptions {
+    /// Nodes removed from the text are only deleted when this is set
+    #[serde(default)]
+    pub allow_delete: bool,
+    /// What to do when a target already exists on disk outside the scanned tree
+    #[serde(default)]
+    pub collision: CollisionPolicy,
+    /// Per-node policies (by node ID) that take precedence over `collision`
+    #[serde(default)]
+    pub collision_overrides: HashMap<String, CollisionPolicy>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -25,27 +265,41 @@ pub struct TreeNode {
     old_path: Option<String>,
     #[serde(rename = "newPath")]
     new_path: Option<String>,
+    #[serde(default)]
+    fingerprint: Option<NodeFingerprint>,
 }
 
-// 将TreeNode转换为HashMap<id -> (path, is_dir)>
-fn tree_node_to_id_paths(node: &TreeNode, parent_path: &str) -> HashMap<String, (String, bool)> {
-    let mut id_paths = HashMap::new();
-    
-    let current_path = if parent_path.is_empty() {
-        node.name.clone()
-    } else {
-        format!("{}/{}", parent_path, node.name)
+// 树中一个节点出现的位置
+#[derive(Debug, Clone)]
+pub(crate) struct NodeEntry {
+    pub id: String,
+    pub name: String,
+    pub path: String,
+    pub is_dir: bool,
+    pub parent: Option<usize>,
+    pub fingerprint: Option<NodeFingerprint>,
+}
+
+// 按深度优先顺序收集所有节点，同一个ID可能出现多次（复制）
+fn collect_node_entries(node: &TreeNode, parent: Option<usize>, entries: &mut Vec<NodeEntry>) {
+    let path = match parent {
+        Some(index) => format!("{}/{}", entries[index].path, node.name),
+        None => node.name.clone(),
     };
     
-    // 存储节点ID -> (路径, 是否是目录)
-    id_paths.insert(node.id.clone(), (current_path.clone(), node.is_dir));
+    let index = entries.len();
+    entries.push(NodeEntry {
+        id: node.id.clone(),
+        name: node.name.clone(),
+        path,
+        is_dir: node.is_dir,
+        parent,
+        fingerprint: node.fingerprint.clone(),
+    });
     
     for child in &node.children {
-        let child_id_paths = tree_node_to_id_paths(child, &current_path);
-        id_paths.extend(child_id_paths);
+        collect_node_entries(child, Some(index), entries);
     }
-    
-    id_paths
 }
 
 // 将TreeNode转换为HashMap<path -> id>
@@ -89,27 +343,15 @@ fn tree_node_to_paths(node: &TreeNode, parent_path: &str) -> HashMap<String, boo
     paths
 }
 
-// 新函数：仅生成操作但不执行，用于测试
-pub fn generate_operations_from_json(
-    base_path: &str, 
-    original_tree: &str, 
-    modified_tree: &str
-) -> Result<Vec<FileOperation>, String> {
-    println!("generate_operations_from_json called with path: {}", base_path);
-    
-    let base_path = Path::new(base_path);
-    if !base_path.exists() {
-        println!("Error: Path does not exist: {}", base_path.display());
-        return Err(format!("Path does not exist: {}", base_path.display()));
-    }
-    
+// 解析原始树和修改后的树，并收集其中所有节点的位置
+pub(crate) fn parse_tree_entries(base_path: &Path, original_tree: &str, modified_tree: &str) -> Result<(Vec<NodeEntry>, Vec<NodeEntry>), AppError> {
     println!("Parsing tree JSON...");
     // Parse the original and modified trees from JSON
     let original_node: TreeNode = match serde_json::from_str(original_tree) {
         Ok(tree) => tree,
         Err(e) => {
             println!("Error parsing original tree JSON: {}", e);
-            return Err(format!("Invalid original tree JSON: {}", e));
+            return Err(AppError::new(ErrorType::InvalidInput, format!("Invalid original tree JSON: {}", e)).at(base_path));
         }
     };
     
@@ -117,282 +359,836 @@ pub fn generate_operations_from_json(
         Ok(tree) => tree,
         Err(e) => {
             println!("Error parsing modified tree JSON: {}", e);
-            return Err(format!("Invalid modified tree JSON: {}", e));
+            return Err(AppError::new(ErrorType::InvalidInput, format!("Invalid modified tree JSON: {}", e)).at(base_path));
         }
     };
     
-    // 构建每个ID对应的路径映射
-    let original_id_paths = tree_node_to_id_paths(&original_node, "");
-    let modified_id_paths = tree_node_to_id_paths(&modified_node, "");
-    
-    println!("Original IDs: {}, Modified IDs: {}", 
-             original_id_paths.len(), modified_id_paths.len());
-    
-    // 输出所有ID信息进行调试
-    println!("Original ID paths:");
-    for (id, (path, is_dir)) in &original_id_paths {
-        println!("  ID: {}, Path: {}, IsDir: {}", id, path, is_dir);
-    }
-    
-    println!("Modified ID paths:");
-    for (id, (path, is_dir)) in &modified_id_paths {
-        println!("  ID: {}, Path: {}, IsDir: {}", id, path, is_dir);
-    }
-    
-    // 获取基本路径的文件名，用于创建绝对路径
-    let base_dir_name = base_path.file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("");
-    
-    // 生成需要重命名的操作
-    let mut operations = Vec::new();
-    
-    // 遍历所有ID，检查是否有路径变化
-    for (id, (mod_path, _)) in &modified_id_paths {
-        // 查找原始树中相同ID的节点
-        if let Some((orig_path, _)) = original_id_paths.get(id) {
-            // 如果路径变了，就是重命名操作
-            if orig_path != mod_path {
-                println!("Found rename: {} -> {}", orig_path, mod_path);
-                
-                // 将相对路径转换为绝对路径
-                let from_absolute = base_path.join(orig_path.trim_start_matches(&format!("{}/", base_dir_name)));
-                let to_absolute = base_path.join(mod_path.trim_start_matches(&format!("{}/", base_dir_name)));
-                
-                operations.push(FileOperation::Rename {
-                    from: from_absolute.to_string_lossy().to_string(),
-                    to: to_absolute.to_string_lossy().to_string(),
-                });
+    // 收集两棵树中所有节点的位置
+    let mut original_entries = Vec::new();
+    collect_node_entries(&original_node, None, &mut original_entries);
+    let mut modified_entries = Vec::new();
+    collect_node_entries(&modified_node, None, &mut modified_entries);
+    
+    Ok((original_entries, modified_entries))
+}
+
+// 收集一棵刚扫描的树中所有节点的位置
+pub(crate) fn scanned_tree_entries(root: &crate::commands::tree::TreeNode) -> Vec<NodeEntry> {
+    fn collect(node: &crate::commands::tree::TreeNode, parent: Option<usize>, entries: &mut Vec<NodeEntry>) {
+        let path = match parent {
+            Some(index) => format!("{}/{}", entries[index].path, node.name),
+            None => node.name.clone(),
+        };
+        let index = entries.len();
+        entries.push(NodeEntry {
+            id: node.id.clone(),
+            name: node.name.clone(),
+            path,
+            is_dir: node.is_dir,
+            parent,
+            fingerprint: node.fingerprint.clone(),
+        });
+        for child in &node.children {
+            collect(child, Some(index), entries);
+        }
+    }
+
+    let mut entries = Vec::new();
+    collect(root, None, &mut entries);
+    entries
+}
+
+// 将树中的路径（以根目录名开头）转换为绝对路径
+// 第一段是根目录的名称（可能已被修改），其余各段依次拼接到基础路径上
+pub(crate) fn to_absolute_path(base_path: &Path, tree_path: &str) -> String {
+    let mut path = base_path.to_path_buf();
+    for component in tree_path.split('/').skip(1) {
+        path.push(component);
+    }
+    path.to_string_lossy().to_string()
+}
+
+/// Checks that a new name for the scanned root can be used as a single path component.
+pub(crate) fn validate_root_name(name: &str) -> Result<(), AppError> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') || name.contains('\\') {
+        return Err(AppError::new(ErrorType::InvalidFilename, format!("Invalid root directory name: {:?}", name)).at(name));
+    }
+    Ok(())
+}
+
+// 新函数：仅生成操作但不执行，用于测试
+pub fn generate_operations_from_json(
+    base_path: &str, 
+    original_tree: &str, 
+    modified_tree: &str
+) -> Result<Vec<FileOperation>, AppError> {
+    generate_operations_with_options(base_path, original_tree, modified_tree, &PlanOptions::default())
+}
+
+pub fn generate_operations_with_options(
+    base_path: &str,
+    original_tree: &str,
+    modified_tree: &str,
+    options: &PlanOptions
+) -> Result<Vec<FileOperation>, AppError> {
+    let plan = build_plan(base_path, original_tree, modified_tree, options)?;
+    Ok(plan.operations.into_iter().map(|planned| planned.operation).collect())
+}
+
+/// Builds the complete plan for turning the original tree into the modified one,
+/// without touching the disk: ordered operations with their node IDs and
+/// dependencies, conflicts, warnings and a stable hash.
+pub fn build_plan(
+    base_path: &str,
+    original_tree: &str,
+    modified_tree: &str,
+    options: &PlanOptions
+) -> Result<Plan, AppError> {
+    println!("build_plan called with path: {}", base_path);
+    
+    let base_path = Path::new(base_path);
+    if !base_path.exists() {
+        println!("Error: Path does not exist: {}", base_path.display());
+        return Err(AppError::new(ErrorType::FileNotFound, format!("Path does not exist: {}", base_path.display())).at(base_path));
+    }
+    
+    let (original_entries, mut modified_entries) = parse_tree_entries(base_path, original_tree, modified_tree)?;
+    
+    // 采用“加后缀”策略的冲突直接在修改后的树中改名
+    let conflicts = find_conflicts(base_path, &original_entries, &modified_entries, options);
+    let mut collisions = apply_suffixes(base_path, &original_entries, &mut modified_entries, &conflicts, options);
+    let conflicts = if collisions.is_empty() {
+        conflicts
+    } else {
+        find_conflicts(base_path, &original_entries, &modified_entries, options)
+    };
+    let modified_entries = modified_entries;
+    
+    // 根目录改名：在上级目录中重命名，最后执行
+    let root_rename = match (original_entries.first(), modified_entries.first()) {
+        (Some(orig_root), Some(new_root)) if orig_root.name != new_root.name => {
+            validate_root_name(&new_root.name)?;
+            let parent = base_path.parent()
+                .ok_or_else(|| AppError::new(ErrorType::InvalidInput,
+                    format!("Cannot rename {} because it has no parent directory", base_path.display())).at(base_path))?;
+            Some((new_root.id.clone(), parent.join(&new_root.name).to_string_lossy().to_string()))
+        },
+        _ => None,
+    };
+    
+    println!("Original nodes: {}, Modified nodes: {}",
+             original_entries.len(), modified_entries.len());
+    
+    let original_by_id: HashMap<&str, &NodeEntry> = original_entries.iter()
+        .map(|entry| (entry.id.as_str(), entry))
+        .collect();
+    
+    // 同一ID在修改后的树中出现多次时，保持原路径的那一次（否则第一次）是节点本身，其余是复制
+    let mut primary_by_id: HashMap<&str, usize> = HashMap::new();
+    for (index, entry) in modified_entries.iter().enumerate() {
+        let keeps_original_path = original_by_id.get(entry.id.as_str())
+            .is_some_and(|orig| orig.path == entry.path);
+        match primary_by_id.get(entry.id.as_str()) {
+            Some(_) if !keeps_original_path => {}
+            _ => {
+                primary_by_id.insert(entry.id.as_str(), index);
             }
+        }
+    }
+    
+    let to_absolute = |tree_path: &str| to_absolute_path(base_path, tree_path);
+    
+    // 节点在树状态中的键：同一ID的其他出现位置（复制）使用单独的键
+    let key = |index: usize| -> String {
+        let id = &modified_entries[index].id;
+        if primary_by_id[id.as_str()] == index {
+            id.clone()
         } else {
-            // ID在修改后的树中存在，但在原始树中不存在
-            println!("Warning: ID {} in modified tree not found in original tree.", id);
+            format!("{}#{}", id, index)
+        }
+    };
+    
+    let mut changes = Vec::new();
+    let mut warnings = Vec::new();
+    let mut moves = Vec::new();
+    // 位于复制目录内部的条目所属的复制操作，这些条目会随目录一起复制
+    let mut copied: Vec<Option<usize>> = vec![None; modified_entries.len()];
+    
+    for (index, entry) in modified_entries.iter().enumerate() {
+        // 根节点不参与操作
+        let Some(parent) = entry.parent else { continue };
+        
+        let Some(orig) = original_by_id.get(entry.id.as_str()) else {
+            // ID在修改后的树中存在，但在原始树中不存在：新建
+            copied[index] = copied[parent];
+            changes.push(NodeChange::Create {
+                node_id: entry.id.clone(),
+                key: key(index),
+                parent: key(parent),
+                name: entry.name.clone(),
+                is_dir: entry.is_dir,
+            });
+            continue;
+        };
+        
+        if primary_by_id[entry.id.as_str()] != index {
+            if let Some(copy) = copied[parent] {
+                copied[index] = Some(copy);
+                if let NodeChange::Copy { nested, .. } = &mut changes[copy] {
+                    nested.push((key(index), key(parent), entry.name.clone()));
+                }
+            } else {
+                copied[index] = Some(changes.len());
+                changes.push(NodeChange::Copy {
+                    node_id: entry.id.clone(),
+                    key: key(index),
+                    source: entry.id.clone(),
+                    parent: key(parent),
+                    name: entry.name.clone(),
+                    nested: Vec::new(),
+                });
+            }
+            continue;
+        }
+        
+        // 只有节点自身的父目录或名称变化时才需要操作，子节点会随父目录一起移动
+        let orig_parent_id = orig.parent.map(|p| original_entries[p].id.as_str()).unwrap_or_default();
+        let new_parent_id = key(parent);
+        if orig_parent_id == new_parent_id && orig.name == entry.name {
+            continue;
         }
+        
+        moves.push(NodeMove {
+            node_id: entry.id.clone(),
+            from: Slot { parent_id: orig_parent_id.to_string(), name: orig.name.clone() },
+            changes_parent: orig_parent_id != new_parent_id,
+            to: Slot { parent_id: new_parent_id, name: entry.name.clone() },
+        });
     }
     
-    // 对重命名操作按照路径深度排序，深度更大的路径（文件）先处理
-    operations.sort_by(|a, b| {
-        let (FileOperation::Rename { from: from_a, .. }, FileOperation::Rename { from: from_b, .. }) = (a, b);
-        // 先按照是否为目录排序（文件优先）
-        let a_is_dir = Path::new(from_a).is_dir();
-        let b_is_dir = Path::new(from_b).is_dir();
+    // 在修改后的树中消失的节点：只处理最上层被删除的节点
+    let modified_ids: HashSet<&str> = modified_entries.iter().map(|entry| entry.id.as_str()).collect();
+    // 原始树中每个条目所在的删除操作（条目按先序排列，父节点总在子节点之前）
+    let mut deleted_in: Vec<Option<usize>> = vec![None; original_entries.len()];
+    for (index, entry) in original_entries.iter().enumerate() {
+        let Some(parent) = entry.parent else { continue };
+        if let Some(delete) = deleted_in[parent] {
+            deleted_in[index] = Some(delete);
+            if let NodeChange::Delete { contains, .. } = &mut changes[delete] {
+                contains.insert(entry.id.clone());
+            }
+            continue;
+        }
+        
+        let parent_id = original_entries[parent].id.as_str();
+        if modified_ids.contains(entry.id.as_str()) || !modified_ids.contains(parent_id) {
+            continue;
+        }
         
-        if a_is_dir != b_is_dir {
-            return if a_is_dir { std::cmp::Ordering::Greater } else { std::cmp::Ordering::Less };
+        if !options.allow_delete {
+            let warning = format!("{} was removed from the tree, but deletion is not enabled. Skipping.", entry.path);
+            println!("Warning: {}", warning);
+            warnings.push(warning);
+            continue;
         }
         
-        // 然后按照路径深度排序（深度大的优先）
-        let a_components = Path::new(from_a).components().count();
-        let b_components = Path::new(from_b).components().count();
-        return b_components.cmp(&a_components);
+        deleted_in[index] = Some(changes.len());
+        changes.push(NodeChange::Delete { node_id: entry.id.clone(), contains: HashSet::new() });
+    }
+    
+    // 同一目录中的同名目录会被合并
+    let mut dir_names: HashMap<(usize, &str), usize> = HashMap::new();
+    for entry in &modified_entries {
+        if let (Some(parent), true) = (entry.parent, entry.is_dir) {
+            *dir_names.entry((parent, entry.name.as_str())).or_default() += 1;
+        }
+    }
+    let mut merges: Vec<String> = dir_names.iter()
+        .filter(|(_, &count)| count > 1)
+        .map(|((parent, name), count)| format!("{} directories named {} will be merged in {}", count, name, modified_entries[*parent].path))
+        .collect();
+    merges.sort();
+    warnings.extend(merges);
+    
+    // 先移动文件，再由深到浅移动目录；只使用树模型中的信息，不访问磁盘
+    moves.sort_by_cached_key(|node_move| {
+        let orig = original_by_id[node_move.node_id.as_str()];
+        (orig.is_dir, std::cmp::Reverse(orig.path.matches('/').count()))
     });
     
-    println!("Generated {} operations", operations.len());
+    // 树中已有的名称和磁盘上的隐藏条目都不能用作临时名称
+    let initial_state = TreeState::new(&original_entries);
+    let mut taken_names: HashSet<(&str, &str)> = HashSet::new();
+    for entries in [&original_entries, &modified_entries] {
+        for entry in entries.iter() {
+            if let Some(parent) = entry.parent {
+                taken_names.insert((entries[parent].id.as_str(), entry.name.as_str()));
+            }
+        }
+    }
+    // 临时名称只放在被移动的条目原来所在的目录中：这些目录在排序之前各读取一次，
+    // 排序时不再访问磁盘。名称按小写比较，在不区分大小写的文件系统上也不会撞上已有条目
+    let mut on_disk: HashMap<String, HashSet<String>> = HashMap::new();
+    for node_move in &moves {
+        let parent_id = node_move.from.parent_id.as_str();
+        if on_disk.contains_key(parent_id) || !initial_state.contains(parent_id) {
+            continue;
+        }
+        let names = std::fs::read_dir(to_absolute(&initial_state.path(parent_id)))
+            .map(|entries| entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.file_name().to_string_lossy().to_lowercase())
+                .collect())
+            .unwrap_or_default();
+        on_disk.insert(parent_id.to_string(), names);
+    }
+    let is_taken = |slot: &Slot| {
+        taken_names.contains(&(slot.parent_id.as_str(), slot.name.as_str()))
+            || on_disk.get(slot.parent_id.as_str()).is_some_and(|names| names.contains(&slot.name.to_lowercase()))
+    };
     
-    // 显示所有生成的操作
-    for (i, op) in operations.iter().enumerate() {
-        let FileOperation::Rename { from, to } = op;
-        println!("Operation {}: {} -> {}", i+1, from, to);
+    // 交换合并为一次操作后只带其中一个节点，检查外部变化时需要所有移动的节点
+    let moved_ids: Vec<String> = moves.iter().map(|node_move| node_move.node_id.clone()).collect();
+    
+    // 重命名/移动按依赖关系排序，交换和循环通过临时名称打破
+    let steps = order_renames(moves, is_taken);
+    
+    // 所有变更按拓扑顺序排列，每一步都基于前面的变更执行后的状态计算路径
+    let mut all_changes: Vec<NodeChange> = steps.into_iter().map(NodeChange::Move).collect();
+    all_changes.extend(changes);
+    let mut ordered: Vec<(String, FileOperation)> = schedule_changes(TreeState::new(&original_entries), &all_changes, is_taken)
+        .into_iter()
+        .map(|(node_id, operation)| (node_id, operation.map_paths(to_absolute)))
+        .collect();
+    
+    // 通过临时名称的交换改为一次原子交换
+    if EXCHANGE_AVAILABLE {
+        ordered = collapse_swaps(ordered);
+    }
+    
+    // 其他所有路径都位于根目录之下，所以根目录最后改名
+    if let Some((root_id, to)) = root_rename {
+        ordered.push((root_id, FileOperation::Rename { from: base_path.to_string_lossy().to_string(), to }));
     }
     
-    Ok(operations)
+    // 其余与磁盘上已有条目的冲突按各节点的策略处理：跳过、覆盖或合并
+    let dirs: HashSet<&str> = modified_entries.iter().filter(|entry| entry.is_dir).map(|entry| entry.id.as_str()).collect();
+    let resolved = resolve_collisions(base_path, ordered, conflicts, &dirs, options);
+    for collision in resolved.collisions.iter().filter(|collision| collision.skipped > 0) {
+        let warning = format!("{} already exists, skipping {} operations", collision.path, collision.skipped);
+        println!("Warning: {}", warning);
+        warnings.push(warning);
+    }
+    collisions.extend(resolved.collisions);
+    
+    // 操作在执行时逐条输出，这里只输出数量，避免大型计划被日志拖慢
+    println!("Generated {} operations", resolved.operations.len());
+    
+    let dependencies = compute_dependencies(resolved.operations.iter().map(|(_, op, _)| op));
+    let operations: Vec<PlannedOperation> = resolved.operations.into_iter()
+        .zip(dependencies)
+        .enumerate()
+        .map(|(index, ((node_id, operation, collision), depends_on))| PlannedOperation {
+            index,
+            node_id,
+            kind: operation.kind(),
+            operation,
+            depends_on,
+            collision,
+        })
+        .collect();
+    
+    // 参与计划的节点与扫描时的指纹比较，发现扫描后在外部发生的变化
+    let involved: HashSet<&str> = operations.iter()
+        .filter(|planned| !matches!(planned.kind, OperationKind::CreateDir | OperationKind::CreateFile))
+        .map(|planned| planned.node_id.as_str())
+        .chain(moved_ids.iter().map(String::as_str))
+        .collect();
+    let external_changes = find_external_changes(base_path, &original_entries, &involved);
+    
+    let hash = plan_hash(base_path, &operations);
+    
+    Ok(Plan { operations, conflicts: resolved.conflicts, collisions, warnings, external_changes, hash })
+}
+
+/// Checks a plan for conflicts with the modified tree itself and with the real disk.
+/// Collision policies are not applied here; `build_plan` reports what they leave.
+pub fn detect_conflicts(
+    base_path: &str,
+    original_tree: &str,
+    modified_tree: &str,
+    options: &PlanOptions
+) -> Result<Vec<Conflict>, AppError> {
+    let base = Path::new(base_path);
+    if !base.exists() {
+        return Err(AppError::new(ErrorType::FileNotFound, format!("Path does not exist: {}", base.display())).at(base));
+    }
+    
+    let (original_entries, modified_entries) = parse_tree_entries(base, original_tree, modified_tree)?;
+    Ok(find_conflicts(base, &original_entries, &modified_entries, options))
+}
+
+#[command]
+pub fn check_conflicts(
+    #[allow(non_snake_case)] dirPath: String, 
+    #[allow(non_snake_case)] originalTree: String, 
+    #[allow(non_snake_case)] modifiedTree: String,
+    options: Option<PlanOptions>
+) -> Result<Vec<Conflict>, AppError> {
+    detect_conflicts(&dirPath, &originalTree, &modifiedTree, &options.unwrap_or_default())
+}
+
+/// Returns what `apply_operations` would do, without touching the disk.
+#[command]
+pub fn generate_operations(
+    #[allow(non_snake_case)] dirPath: String, 
+    #[allow(non_snake_case)] originalTree: String, 
+    #[allow(non_snake_case)] modifiedTree: String,
+    options: Option<PlanOptions>
+) -> Result<Plan, AppError> {
+    build_plan(&dirPath, &originalTree, &modifiedTree, &options.unwrap_or_default())
 }
 
 #[command]
 pub fn apply_operations(
     #[allow(non_snake_case)] dirPath: String, 
     #[allow(non_snake_case)] originalTree: String, 
-    #[allow(non_snake_case)] modifiedTree: String
-) -> Result<Vec<OperationResult>, String> {
+    #[allow(non_snake_case)] modifiedTree: String,
+    options: Option<PlanOptions>,
+    #[allow(non_snake_case)] dryRun: Option<bool>
+) -> Result<ApplyReport, AppError> {
     println!("apply_operations called with path: {}", dirPath);
+    apply_operations_at(&app_data_dir(), &dirPath, &originalTree, &modifiedTree, &options.unwrap_or_default(), dryRun.unwrap_or(false))
+}
+
+/// Applies the changes between the two trees, keeping the backup, journal and
+/// history of the batch in `data_dir`.
+pub(crate) fn apply_operations_at(
+    data_dir: &Path,
+    dir_path: &str,
+    original_tree: &str,
+    modified_tree: &str,
+    options: &PlanOptions,
+    simulate: bool,
+) -> Result<ApplyReport, AppError> {
+    let (operations, node_ids) = plan_for_apply(dir_path, original_tree, modified_tree, options)?;
+    
+    // 演练：在内存中的文件系统上执行，不写磁盘，也不记录备份、日志和历史
+    if simulate {
+        let mut report = dry_run(dir_path, original_tree, modified_tree, &operations)?;
+        attach_node_ids(&mut report.results, &node_ids);
+        return Ok(report);
+    }
     
-    // 首先生成操作 - 现在只会生成重命名操作
-    let operations = generate_operations_from_json(&dirPath, &originalTree, &modifiedTree)?;
+    // 记录到日志中，崩溃后可以继续或撤销；失败的操作不会中止其余操作
+    let mut report = apply_batch(data_dir, Path::new(dir_path), &operations, false)?;
+    attach_node_ids(&mut report.results, &node_ids);
+    Ok(ApplyReport { dry_run: false, results: report.results, summary: report.summary, tree: None, mismatches: Vec::new() })
+}
+
+// 生成计划，存在未解决的冲突时拒绝执行；同时返回每个操作所属的节点ID
+pub(crate) fn plan_for_apply(
+    dir_path: &str,
+    original_tree: &str,
+    modified_tree: &str,
+    options: &PlanOptions,
+) -> Result<(Vec<FileOperation>, Vec<String>), AppError> {
+    let plan = build_plan(dir_path, original_tree, modified_tree, options)?;
     
-    // 应用操作
+    if !plan.external_changes.is_empty() {
+        let messages: Vec<String> = plan.external_changes.iter().map(|c| c.message.clone()).collect();
+        println!("Refusing to apply operations: {} entries changed since the scan", plan.external_changes.len());
+        let first = to_absolute_path(Path::new(dir_path), &plan.external_changes[0].path);
+        return Err(AppError::new(ErrorType::ExternalModification,
+            format!("The directory changed since it was scanned:\n{}", messages.join("\n"))).at(first));
+    }
+    
+    if !plan.conflicts.is_empty() {
+        let messages: Vec<String> = plan.conflicts.iter().map(|c| c.message.clone()).collect();
+        println!("Refusing to apply operations: {} unresolved conflicts", plan.conflicts.len());
+        return Err(AppError::new(ErrorType::UnresolvedConflict,
+            format!("Unresolved conflicts:\n{}", messages.join("\n"))).at(&plan.conflicts[0].path));
+    }
+    Ok(plan.operations.into_iter().map(|planned| (planned.operation, planned.node_id)).unzip())
+}
+
+// 按顺序执行操作，操作已经按照执行顺序排序
+pub(crate) fn execute_operations(root: &Path, operations: &[FileOperation]) -> Vec<OperationResult> {
     let mut results = Vec::new();
     
-    // 重命名操作已经按照从文件到目录、从深到浅的顺序排序
-    println!("Applying rename operations:");
+    // 根目录只打开一次，所有操作都相对于它进行
+    let anchor = match Anchor::open(root) {
+        Ok(anchor) => anchor,
+        Err(e) => {
+            println!("Failed to open {}: {}", root.display(), e);
+            return operations.iter().map(|op| {
+                OperationResult::failed(AppError::io(&e, format!("Failed to open {}: {}", root.display(), e)).at(root))
+                    .describe(op, Duration::ZERO)
+            }).collect();
+        },
+    };
+    
+    println!("Applying operations:");
     for (i, op) in operations.iter().enumerate() {
-        let FileOperation::Rename { from, to } = op;
-        println!("  {}. {} -> {}", i+1, from, to);
+        println!("  {}. {}", i+1, op);
         
-        let result = apply_operation(op);
+        let result = apply_operation(&anchor, op);
         results.push(result);
     }
     
     println!("All operations applied successfully");
-    Ok(results)
+    results
 }
 
-// 应用操作的函数
-fn apply_operation(operation: &FileOperation) -> OperationResult {
-    match operation {
-        FileOperation::Rename { from, to } => {
-            // 转换路径分隔符，在Windows系统上转换为反斜杠
-            let from_normalized = if cfg!(windows) {
-                from.replace('/', "\\")
-            } else {
-                from.clone()
-            };
-            
-            let to_normalized = if cfg!(windows) {
-                to.replace('/', "\\")
-            } else {
-                to.clone()
-            };
-            
-            let from_path = Path::new(&from_normalized);
-            let to_path = Path::new(&to_normalized);
-            
-            println!("Applying rename operation: from '{}' to '{}'", from_normalized, to_normalized);
-            
-            // Check if paths exist
-            println!("From path exists: {}", from_path.exists());
-            if let Some(parent) = to_path.parent() {
-                println!("To parent exists: {}", parent.exists());
-            }
-            
-            // Create parent directories if they don't exist
-            if let Some(parent) = to_path.parent() {
-                if !parent.exists() {
-                    println!("Creating parent directory: {}", parent.display());
-                    if let Err(e) = fs::create_dir_all(parent) {
-                        println!("Failed to create parent directory: {}", e);
-                        return OperationResult {
-                            success: false,
-                            message: format!("Failed to create parent directory: {}", e),
-                        };
-                    }
-                    println!("Parent directory created successfully");
-                }
-            }
-            
-            // 如果源路径不存在，报告错误
-            if !from_path.exists() {
-                println!("Source path does not exist: {}", from_path.display());
-                return OperationResult {
-                    success: false,
-                    message: format!("Source path does not exist: {}", from_path.display()),
+// 转换路径分隔符，在Windows系统上转换为反斜杠
+fn normalize_path(path: &str) -> String {
+    if cfg!(windows) {
+        path.replace('/', "\\")
+    } else {
+        path.to_string()
+    }
+}
+
+// 应用操作的函数；每一步都通过打开的根目录进行，不会离开所选的目录树
+pub(crate) fn apply_operation(anchor: &Anchor, operation: &FileOperation) -> OperationResult {
+    apply_operation_with_settings(anchor, operation, &CopySettings::default())
+}
+
+// 跨设备移动按批次的复制设置校验
+pub(crate) fn apply_operation_with_settings(anchor: &Anchor, operation: &FileOperation, copy_settings: &CopySettings) -> OperationResult {
+    let started = Instant::now();
+    let result = match operation {
+        FileOperation::Rename { from, to } | FileOperation::Move { from, to } => apply_rename(anchor, from, to, copy_settings.checksum),
+        FileOperation::CreateDir { path } => apply_create_dir(anchor, path),
+        FileOperation::CreateFile { path } => apply_create_file(anchor, path),
+        FileOperation::Delete { path } => apply_delete(anchor, path),
+        FileOperation::Copy { from, to } => apply_copy(anchor, from, to),
+        FileOperation::Merge { from, to } => apply_merge(anchor, from, to),
+        FileOperation::Exchange { from, to } => apply_exchange(anchor, from, to),
+    };
+    result.describe(operation, started.elapsed())
+}
+
+fn apply_rename(anchor: &Anchor, from: &str, to: &str, checksum: ChecksumAlgorithm) -> OperationResult {
+    let from_normalized = normalize_path(from);
+    let to_normalized = normalize_path(to);
+    
+    let from_path = Path::new(&from_normalized);
+    let to_path = Path::new(&to_normalized);
+    
+    println!("Applying rename operation: from '{}' to '{}'", from_normalized, to_normalized);
+    
+    // Create parent directories if they don't exist
+    if let Err(error) = ensure_parent_dir(anchor, to_path) {
+        return OperationResult::failed(error);
+    }
+    
+    // 如果源路径不存在，报告错误
+    if let Err(e) = anchor.is_dir(from_path) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            return OperationResult::failed(AppError::io(&e, format!("Failed to rename {} to {}: {}", from_normalized, to_normalized, e)).at(from_path));
+        }
+        println!("Source path does not exist: {}", from_path.display());
+        return OperationResult::failed(AppError::new(ErrorType::FileNotFound,
+            format!("Source path does not exist: {}", from_path.display())).at(from_path));
+    }
+    
+    // 只有大小写或Unicode规范化形式不同：目标就是源本身，需要经过临时名称
+    if is_fold_only_rename(anchor, from_path, to_path) {
+        return apply_fold_only_rename(anchor, from_path, to_path);
+    }
+    
+    // 不覆盖已有的目标：覆盖和合并由计划中的冲突策略决定，分别生成删除和合并操作
+    if anchor.exists(to_path) {
+        println!("Target already exists: {}", to_path.display());
+        return OperationResult::failed(AppError::new(ErrorType::PathAlreadyExists,
+            format!("Cannot rename {} to {}: the target already exists", from_normalized, to_normalized)).at(to_path));
+    }
+    
+    println!("Attempting to rename file");
+    match anchor.rename_noreplace(from_path, to_path) {
+        Ok(_) => {
+            println!("Rename successful: {} to {}", from_normalized, to_normalized);
+            OperationResult::ok(format!("Renamed {} to {}", from_normalized, to_normalized))
+        },
+        Err(e) => {
+            if e.kind() == std::io::ErrorKind::AlreadyExists {
+                // 检查之后才出现的目标：不覆盖
+                println!("Target appeared before the rename: {}", to_path.display());
+                OperationResult::failed(AppError::new(ErrorType::PathAlreadyExists,
+                    format!("Cannot rename {} to {}: the target already exists", from_normalized, to_normalized)).at(to_path))
+            } else if is_cross_device(&e) {
+                // 源和目标在不同的文件系统上：复制、校验后再删除源
+                println!("Rename crosses devices, moving by copying...");
+                let result = match move_across_devices(anchor, from_path, to_path, checksum, &mut report_progress) {
+                    Ok(report) => OperationResult::ok(format!("Moved {} to {} across devices{}", from_normalized, to_normalized,
+                        describe_copy_report(&report))),
+                    Err(MoveError::Copy(copy_err)) => OperationResult::failed(AppError::io(&copy_err,
+                        format!("Failed to move {} to {} across devices: {}", from_normalized, to_normalized, copy_err)).at(from_path)),
+                    Err(MoveError::RemoveSource(rm_err)) => OperationResult::partially_done(AppError::io(&rm_err,
+                        format!("Moved {} to {} across devices but could not remove the original completely: {}",
+                            from_normalized, to_normalized, rm_err)).at(from_path)),
                 };
-            }
-            
-            // 对目录的特殊处理
-            if from_path.is_dir() {
-                // 如果目标路径已经存在，先尝试删除（如果是空目录）
-                if to_path.exists() && to_path.is_dir() {
-                    println!("Target directory already exists, checking if it's empty...");
-                    match fs::read_dir(to_path) {
-                        Ok(entries) => {
-                            if entries.count() == 0 {
-                                println!("Target directory is empty, removing it");
-                                if let Err(e) = fs::remove_dir(to_path) {
-                                    println!("Failed to remove empty target directory: {}", e);
-                                    // 继续尝试重命名，可能会失败
-                                }
-                            } else {
-                                println!("Target directory is not empty, rename operation will likely fail");
-                            }
-                        },
-                        Err(e) => {
-                            println!("Failed to read target directory: {}", e);
-                            // 继续尝试重命名，可能会失败
-                        }
-                    }
-                }
-            }
-            
-            println!("Attempting to rename file");
-            match fs::rename(from_path, to_path) {
-                Ok(_) => {
-                    println!("Rename successful: {} to {}", from_normalized, to_normalized);
-                    OperationResult {
-                        success: true,
-                        message: format!("Renamed {} to {}", from_normalized, to_normalized),
-                    }
-                },
-                Err(e) => {
-                    // 如果重命名失败，尝试手动实现目录复制和删除
-                    if from_path.is_dir() && e.kind() == std::io::ErrorKind::DirectoryNotEmpty {
-                        println!("Directory rename failed because directory not empty, trying manual copy...");
-                        if let Err(copy_err) = copy_dir_recursively(from_path, to_path) {
-                            println!("Failed to manually copy directory: {}", copy_err);
-                            return OperationResult {
-                                success: false,
-                                message: format!("Failed to rename directory: {}. Manual copy also failed: {}", e, copy_err),
-                            };
-                        }
-                        
-                        // 复制成功后，删除源目录 (如果可能)
-                        match fs::remove_dir_all(from_path) {
-                            Ok(_) => {
-                                println!("Successfully copied directory and removed original");
-                                OperationResult {
-                                    success: true,
-                                    message: format!("Manually copied directory from {} to {}", from_normalized, to_normalized),
-                                }
-                            },
-                            Err(rm_err) => {
-                                println!("Directory copied but failed to remove original: {}", rm_err);
-                                OperationResult {
-                                    success: true, // 仍然认为是成功的，因为内容已经复制
-                                    message: format!("Copied directory from {} to {} but could not remove original: {}", 
-                                        from_normalized, to_normalized, rm_err),
-                                }
-                            }
-                        }
-                    } else {
-                        println!("Rename failed: {} to {}: {}", from_normalized, to_normalized, e);
-                        OperationResult {
-                            success: false,
-                            message: format!("Failed to rename {} to {}: {}", from_normalized, to_normalized, e),
-                        }
-                    }
-                },
+                result.using(Fallback::CrossDeviceCopy)
+            } else {
+                println!("Rename failed: {} to {}: {}", from_normalized, to_normalized, e);
+                OperationResult::failed(AppError::io(&e, format!("Failed to rename {} to {}: {}", from_normalized, to_normalized, e)).at(from_path))
             }
         },
     }
 }
 
-// 递归复制目录及其内容
-fn copy_dir_recursively(from: &Path, to: &Path) -> std::io::Result<()> {
-    // 确保目标目录存在
-    if !to.exists() {
-        fs::create_dir_all(to)?;
+/// One step of merging a directory into an existing one.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum MergeStep {
+    /// An entry missing from the target directory is moved there
+    Move { from: PathBuf, to: PathBuf },
+    /// A source directory whose contents were all moved is removed
+    RemoveDir(PathBuf),
+}
+
+/// The steps of merging the directory `from` into the existing directory `to`, in
+/// the order they are carried out: entries missing from `to` are moved into it,
+/// directories present on both sides are merged recursively and then removed.
+/// Fails without planning anything when an entry other than a directory exists on
+/// both sides.
+pub(crate) fn merge_steps(anchor: &Anchor, from: &Path, to: &Path) -> std::io::Result<Vec<MergeStep>> {
+    let mut steps = Vec::new();
+    collect_merge_steps(anchor, from, to, &mut steps)?;
+    Ok(steps)
+}
+
+fn collect_merge_steps(anchor: &Anchor, from: &Path, to: &Path, steps: &mut Vec<MergeStep>) -> std::io::Result<()> {
+    for name in anchor.read_dir(from)? {
+        let (source, target) = (from.join(&name), to.join(&name));
+        match anchor.is_dir(&target) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => steps.push(MergeStep::Move { from: source, to: target }),
+            Ok(true) if anchor.is_dir(&source)? => collect_merge_steps(anchor, &source, &target, steps)?,
+            Ok(_) => return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists,
+                format!("{} already exists", target.display()))),
+            Err(e) => return Err(e),
+        }
     }
+    steps.push(MergeStep::RemoveDir(from.to_path_buf()));
+    Ok(())
+}
+
+fn apply_merge(anchor: &Anchor, from: &str, to: &str) -> OperationResult {
+    let from_normalized = normalize_path(from);
+    let to_normalized = normalize_path(to);
+    let from_path = Path::new(&from_normalized);
+    let to_path = Path::new(&to_normalized);
+    println!("Applying merge operation: from '{}' into '{}'", from_normalized, to_normalized);
     
-    // 遍历源目录中的所有条目
-    for entry in fs::read_dir(from)? {
-        let entry = entry?;
-        let from_path = entry.path();
-        let to_path = to.join(entry.file_name());
-        
-        if from_path.is_dir() {
-            // 递归复制子目录
-            copy_dir_recursively(&from_path, &to_path)?;
-        } else {
-            // 复制文件
-            fs::copy(&from_path, &to_path)?;
+    if !anchor.is_dir(from_path).unwrap_or(false) || !anchor.is_dir(to_path).unwrap_or(false) {
+        return OperationResult::failed(AppError::new(ErrorType::SystemError,
+            format!("Cannot merge {} into {}: both must be directories", from_normalized, to_normalized)).at(from_path));
+    }
+    
+    // 先检查所有条目，有冲突时什么都不移动
+    let steps = match merge_steps(anchor, from_path, to_path) {
+        Ok(steps) => steps,
+        Err(e) => return OperationResult::failed(AppError::io(&e,
+            format!("Cannot merge {} into {}: {}", from_normalized, to_normalized, e)).at(from_path)),
+    };
+    
+    for step in &steps {
+        let result = match step {
+            MergeStep::Move { from, to } => anchor.rename_noreplace(from, to),
+            MergeStep::RemoveDir(dir) => anchor.remove_dir(dir),
+        };
+        // 已经完成的步骤由事务的撤销记录恢复
+        if let Err(e) = result {
+            println!("Merge step {:?} failed: {}", step, e);
+            let path = match step {
+                MergeStep::Move { from, .. } => from,
+                MergeStep::RemoveDir(dir) => dir,
+            };
+            return OperationResult::failed(AppError::io(&e,
+                format!("Failed to merge {} into {}: {}", from_normalized, to_normalized, e)).at(path));
         }
     }
     
+    OperationResult::ok(format!("Merged {} into {}", from_normalized, to_normalized))
+}
+
+fn apply_exchange(anchor: &Anchor, from: &str, to: &str) -> OperationResult {
+    let from_normalized = normalize_path(from);
+    let to_normalized = normalize_path(to);
+    let from_path = Path::new(&from_normalized);
+    let to_path = Path::new(&to_normalized);
+    println!("Applying exchange operation: '{}' <-> '{}'", from_normalized, to_normalized);
+    
+    if anchor.is_dir(from_path).is_err() || anchor.is_dir(to_path).is_err() {
+        let missing = if anchor.is_dir(from_path).is_err() { from_path } else { to_path };
+        return OperationResult::failed(AppError::new(ErrorType::FileNotFound,
+            format!("Cannot exchange {} and {}: both must exist", from_normalized, to_normalized)).at(missing));
+    }
+    
+    match anchor.exchange(from_path, to_path) {
+        Ok(_) => OperationResult::ok(format!("Exchanged {} and {}", from_normalized, to_normalized)),
+        // 文件系统不支持原子交换：经过临时名称分三步完成
+        Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+            exchange_through_temporary_name(anchor, from_path, to_path).using(Fallback::TemporaryName)
+        },
+        Err(e) => OperationResult::failed(AppError::io(&e,
+            format!("Failed to exchange {} and {}: {}", from_normalized, to_normalized, e)).at(from_path)),
+    }
+}
+
+fn exchange_through_temporary_name(anchor: &Anchor, from_path: &Path, to_path: &Path) -> OperationResult {
+    let name = from_path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let temp_path = from_path.with_file_name(format!(".treenamer-tmp-{}-{}", uuid::Uuid::new_v4().simple(), name));
+    println!("Exchanging {} and {} through temporary name {}", from_path.display(), to_path.display(), temp_path.display());
+    let fail = |e: std::io::Error, left: Option<&Path>| OperationResult {
+        needs_attention: left.is_some(),
+        ..OperationResult::failed(AppError::io(&e, format!("Failed to exchange {} and {}: {}{}", from_path.display(), to_path.display(), e,
+            left.map(|path| format!(" (left at {})", path.display())).unwrap_or_default())).at(left.unwrap_or(from_path)))
+    };
+    
+    if let Err(e) = anchor.rename_noreplace(from_path, &temp_path) {
+        return fail(e, None);
+    }
+    if let Err(e) = anchor.rename_noreplace(to_path, from_path) {
+        let restored = anchor.rename_noreplace(&temp_path, from_path).is_ok();
+        return fail(e, (!restored).then_some(temp_path.as_path()));
+    }
+    if let Err(e) = anchor.rename_noreplace(&temp_path, to_path) {
+        // 恢复原来的状态
+        let restored = anchor.rename_noreplace(from_path, to_path).is_ok() && anchor.rename_noreplace(&temp_path, from_path).is_ok();
+        return fail(e, (!restored).then_some(temp_path.as_path()));
+    }
+    OperationResult::ok(format!("Exchanged {} and {}", from_path.display(), to_path.display()))
+}
+
+// 分两步重命名：先改为临时名称，再改为目标名称
+pub(crate) fn apply_fold_only_rename(anchor: &Anchor, from_path: &Path, to_path: &Path) -> OperationResult {
+    let name = from_path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let temp_path = from_path.with_file_name(format!(".treenamer-tmp-{}-{}", uuid::Uuid::new_v4().simple(), name));
+    println!("Renaming {} to {} through temporary name {}", from_path.display(), to_path.display(), temp_path.display());
+    
+    if let Err(e) = anchor.rename_noreplace(from_path, &temp_path) {
+        return OperationResult::failed(AppError::io(&e,
+            format!("Failed to rename {} to a temporary name: {}", from_path.display(), e)).at(from_path)).using(Fallback::TemporaryName);
+    }
+    
+    let result = match anchor.rename_noreplace(&temp_path, to_path) {
+        Ok(_) => OperationResult::ok(format!("Renamed {} to {}", from_path.display(), to_path.display())),
+        Err(e) => {
+            // 恢复原名称
+            let restored = anchor.rename_noreplace(&temp_path, from_path).is_ok();
+            OperationResult::failed(AppError::io(&e, format!("Failed to rename {} to {}: {}{}", from_path.display(), to_path.display(), e,
+                if restored { String::new() } else { format!(" (left at {})", temp_path.display()) }))
+                .at(if restored { from_path } else { temp_path.as_path() }))
+        },
+    };
+    result.using(Fallback::TemporaryName)
+}
+
+// 确保目标的父目录存在
+fn ensure_parent_dir(anchor: &Anchor, path: &Path) -> Result<(), AppError> {
+    if let Some(parent) = path.parent() {
+        if !anchor.is_dir(parent).unwrap_or(false) {
+            println!("Creating parent directory: {}", parent.display());
+            if let Err(e) = anchor.create_dir_all(parent) {
+                println!("Failed to create parent directory: {}", e);
+                return Err(AppError::io(&e, format!("Failed to create parent directory: {}", e)).at(parent));
+            }
+            println!("Parent directory created successfully");
+        }
+    }
     Ok(())
 }
 
+fn apply_create_dir(anchor: &Anchor, path: &str) -> OperationResult {
+    let normalized = normalize_path(path);
+    let dir_path = Path::new(&normalized);
+    
+    // 目录可能已经因为移动操作而被创建
+    if anchor.is_dir(dir_path).unwrap_or(false) {
+        return OperationResult::skipped(format!("Directory already exists: {}", normalized));
+    }
+    
+    match anchor.create_dir_all(dir_path) {
+        Ok(_) => OperationResult::ok(format!("Created directory {}", normalized)),
+        Err(e) => OperationResult::failed(AppError::io(&e, format!("Failed to create directory {}: {}", normalized, e)).at(dir_path)),
+    }
+}
+
+fn apply_create_file(anchor: &Anchor, path: &str) -> OperationResult {
+    let normalized = normalize_path(path);
+    let file_path = Path::new(&normalized);
+    
+    if let Err(error) = ensure_parent_dir(anchor, file_path) {
+        return OperationResult::failed(error);
+    }
+    
+    // 不覆盖已经存在的文件
+    match anchor.create_file(file_path) {
+        Ok(_) => OperationResult::ok(format!("Created file {}", normalized)),
+        Err(e) => OperationResult::failed(AppError::io(&e, format!("Failed to create file {}: {}", normalized, e)).at(file_path)),
+    }
+}
+
+fn apply_delete(anchor: &Anchor, path: &str) -> OperationResult {
+    let normalized = normalize_path(path);
+    let target = Path::new(&normalized);
+    
+    match anchor.remove_all(target) {
+        Ok(_) => OperationResult::ok(format!("Deleted {}", normalized)),
+        Err(e) => OperationResult::failed(AppError::io(&e, format!("Failed to delete {}: {}", normalized, e)).at(target)),
+    }
+}
+
+fn apply_copy(anchor: &Anchor, from: &str, to: &str) -> OperationResult {
+    let from_normalized = normalize_path(from);
+    let to_normalized = normalize_path(to);
+    
+    let from_path = Path::new(&from_normalized);
+    let to_path = Path::new(&to_normalized);
+    
+    if anchor.exists(to_path) {
+        return OperationResult::failed(AppError::new(ErrorType::PathAlreadyExists,
+            format!("Copy target already exists: {}", to_normalized)).at(to_path));
+    }
+    
+    if let Err(error) = ensure_parent_dir(anchor, to_path) {
+        return OperationResult::failed(error);
+    }
+    
+    // 复制通过打开的上级目录进行
+    let copied = anchor.pin(from_path)
+        .and_then(|from| anchor.pin(to_path).map(|to| (from, to)))
+        .and_then(|(from, to)| copy_tree(&from.path(), &to.path(), &mut |_, _| {}));
+    match copied {
+        Ok(report) => OperationResult::ok(format!("Copied {} to {}{}", from_normalized, to_normalized, describe_copy_report(&report))),
+        Err(e) => OperationResult::failed(AppError::io(&e, format!("Failed to copy {} to {}: {}", from_normalized, to_normalized, e)).at(from_path)),
+    }
+}
+
+// 复制时没有保留下来的内容，附加在结果消息后面
+fn describe_copy_report(report: &CopyReport) -> String {
+    let mut notes = Vec::new();
+    if !report.skipped.is_empty() {
+        notes.push(format!("skipped special files: {}", report.skipped.join(", ")));
+    }
+    notes.extend(report.warnings.iter().cloned());
+    if notes.is_empty() { String::new() } else { format!(" ({})", notes.join("; ")) }
+}
+
 // 以下函数保持不变，但在新算法中可能不再需要
-pub fn parse_tree_text(tree_text: &str) -> Result<HashMap<String, bool>, String> {
+pub fn parse_tree_text(base_path: &Path, tree_text: &str, columns: &TreeColumns) -> Result<HashMap<String, bool>, AppError> {
     let mut paths = HashMap::new();
     let lines: Vec<&str> = tree_text.split('\n').collect();
     
     if lines.is_empty() {
-        return Err("Empty tree text".to_string());
+        return Err(AppError::new(ErrorType::InvalidInput, "Empty tree text").at(base_path));
     }
     
-    // Parse the root directory name
+    // Parse the root directory name (the root line carries no columns)
     let root_line = lines[0].trim();
     if !root_line.ends_with('/') {
-        return Err("Root line must be a directory".to_string());
+        return Err(AppError::new(ErrorType::InvalidInput, "Root line must be a directory").at(base_path));
     }
     
     let root_name = root_line.trim_end_matches('/');
@@ -402,6 +1198,8 @@ pub fn parse_tree_text(tree_text: &str) -> Result<HashMap<String, bool>, String>
     current_path.push(root_name.to_string());
     
     for line in lines.iter().skip(1) {
+        // 元数据列是只读的，解析时直接丢弃
+        let line = strip_metadata_columns(line, columns.count());
         if line.trim().is_empty() {
             continue;
         }
@@ -459,7 +1257,9 @@ pub fn is_protected_path(path: &str) -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs;
     use crate::commands::tree::TreeNode;
+    use crate::commands::conflicts::ConflictKind;
 
     #[test]
     fn test_protected_path_detection() {
@@ -511,6 +1311,8 @@ mod tests {
             name: "root".to_string(),
             is_dir: true,
             children: Vec::new(),
+            metadata: None,
+            fingerprint: None,
         };
 
         let file1 = TreeNode {
@@ -518,6 +1320,8 @@ mod tests {
             name: "file1.txt".to_string(),
             is_dir: false,
             children: Vec::new(),
+            metadata: None,
+            fingerprint: None,
         };
 
         let dir1 = TreeNode {
@@ -525,6 +1329,8 @@ mod tests {
             name: "dir1".to_string(),
             is_dir: true,
             children: Vec::new(),
+            metadata: None,
+            fingerprint: None,
         };
 
         original_tree.children.push(file1);
@@ -536,6 +1342,8 @@ mod tests {
             name: "root".to_string(),
             is_dir: true,
             children: Vec::new(),
+            metadata: None,
+            fingerprint: None,
         };
 
         let file1_renamed = TreeNode {
@@ -543,6 +1351,8 @@ mod tests {
             name: "file2.txt".to_string(), // 新名称
             is_dir: false,
             children: Vec::new(),
+            metadata: None,
+            fingerprint: None,
         };
 
         // 保存值以供后续断言
@@ -554,6 +1364,8 @@ mod tests {
             name: "dir1".to_string(),
             is_dir: true,
             children: Vec::new(),
+            metadata: None,
+            fingerprint: None,
         };
 
         let new_file = TreeNode {
@@ -561,6 +1373,8 @@ mod tests {
             name: "new_file.txt".to_string(),
             is_dir: false,
             children: Vec::new(),
+            metadata: None,
+            fingerprint: None,
         };
 
         modified_tree.children.push(file1_renamed);
@@ -597,4 +1411,689 @@ mod tests {
         assert_eq!(file1_new_name, "file2.txt", "名称应已更新");
         assert_eq!(modified_tree.children.len(), 3, "修改后的树应有3个子节点");
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_parse_tree_text_ignores_metadata_columns() {
+        let plain = "root/\n├── photos/\n│   └── img_0001.jpg\n└── notes.txt\n";
+        let with_columns = "root/\n\
+├── photos/         ┊          ┊ 2024-05-01 12:00 ┊ 1 items\n\
+│   └── img_0001.jpg ┊   2.0 KB ┊ 2024-05-01 12:00 ┊\n\
+└── notes.txt       ┊     12 B ┊ 2023-11-14 22:13 ┊\n";
+        // 用户修改了只读列的内容
+        let edited_columns = with_columns.replace("2.0 KB", "9 GB").replace("2023-11-14", "1999-01-01");
+        let malformed_columns = with_columns.replace("2.0 KB", "2.0KB").replace("1 items", "abc").replace("2023-11-14 22:13", "yesterday");
+        let columns = TreeColumns { size: true, modified: true, item_count: true };
+
+        let base = std::env::temp_dir().join("root");
+        let expected = parse_tree_text(&base, plain, &TreeColumns::default()).unwrap();
+        assert_eq!(parse_tree_text(&base, with_columns, &columns).unwrap(), expected);
+        assert_eq!(parse_tree_text(&base, &edited_columns, &columns).unwrap(), expected);
+        assert_eq!(parse_tree_text(&base, &malformed_columns, &columns).unwrap(), expected);
+        assert_eq!(expected.len(), 4);
+    }
+
+    fn json_node(id: &str, name: &str, is_dir: bool, children: Vec<serde_json::Value>) -> serde_json::Value {
+        serde_json::json!({ "id": id, "name": name, "is_dir": is_dir, "children": children })
+    }
+
+    #[test]
+    fn test_structural_operations() {
+        let root_name = format!("treenamer_ops_{}", uuid::Uuid::new_v4());
+        let root = std::env::temp_dir().join(&root_name);
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::create_dir_all(root.join("old")).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("docs/b.txt"), "b").unwrap();
+        fs::write(root.join("old/c.txt"), "c").unwrap();
+
+        let original = json_node("root", &root_name, true, vec![
+            json_node("docs", "docs", true, vec![json_node("b", "b.txt", false, vec![])]),
+            json_node("old", "old", true, vec![json_node("c", "c.txt", false, vec![])]),
+            json_node("a", "a.txt", false, vec![]),
+        ]).to_string();
+        // 重命名a.txt，移动c.txt，复制b.txt，新建目录和文件，删除old/
+        let modified = json_node("root", &root_name, true, vec![
+            json_node("docs", "docs", true, vec![
+                json_node("new", "new", true, vec![json_node("new-file", "new.txt", false, vec![])]),
+                json_node("b", "b.txt", false, vec![]),
+                json_node("c", "c.txt", false, vec![]),
+            ]),
+            json_node("a", "a2.txt", false, vec![]),
+            json_node("b", "b.txt", false, vec![]),
+        ]).to_string();
+        let base = root.to_string_lossy().to_string();
+
+        // 默认不生成删除操作
+        let operations = generate_operations_from_json(&base, &original, &modified).unwrap();
+        assert!(!operations.iter().any(|op| matches!(op, FileOperation::Delete { .. })));
+
+        let options = PlanOptions { allow_delete: true, ..PlanOptions::default() };
+        let operations = generate_operations_with_options(&base, &original, &modified, &options).unwrap();
+        let kinds: Vec<&str> = operations.iter().map(|op| match op {
+            FileOperation::Rename { .. } => "rename",
+            FileOperation::Move { .. } => "move",
+            FileOperation::Copy { .. } => "copy",
+            FileOperation::CreateDir { .. } => "create_dir",
+            FileOperation::CreateFile { .. } => "create_file",
+            FileOperation::Delete { .. } => "delete",
+            FileOperation::Merge { .. } => "merge",
+            FileOperation::Exchange { .. } => "exchange",
+        }).collect();
+        assert_eq!(kinds.len(), 6, "unexpected operations: {:?}", operations);
+        for kind in ["rename", "move", "copy", "create_dir", "create_file", "delete"] {
+            assert!(kinds.contains(&kind), "missing {} in {:?}", kind, operations);
+        }
+        assert_eq!(kinds.last(), Some(&"delete"), "删除应该最后执行");
+
+        let anchor = Anchor::open(&root).unwrap();
+        for op in &operations {
+            let result = apply_operation(&anchor, op);
+            assert!(result.success, "{}: {}", op, result.message);
+        }
+
+        assert_eq!(fs::read_to_string(root.join("a2.txt")).unwrap(), "a");
+        assert_eq!(fs::read_to_string(root.join("b.txt")).unwrap(), "b");
+        assert_eq!(fs::read_to_string(root.join("docs/b.txt")).unwrap(), "b");
+        assert_eq!(fs::read_to_string(root.join("docs/c.txt")).unwrap(), "c");
+        assert!(root.join("docs/new/new.txt").is_file());
+        assert!(!root.join("a.txt").exists());
+        assert!(!root.join("old").exists());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_swap_and_rotation_are_applied_without_data_loss() {
+        let root_name = format!("treenamer_swap_{}", uuid::Uuid::new_v4());
+        let root = std::env::temp_dir().join(&root_name);
+        fs::create_dir_all(&root).unwrap();
+        for name in ["a.txt", "b.txt", "1", "2", "3"] {
+            fs::write(root.join(name), name).unwrap();
+        }
+
+        let original = json_node("root", &root_name, true, vec![
+            json_node("1", "1", false, vec![]),
+            json_node("2", "2", false, vec![]),
+            json_node("3", "3", false, vec![]),
+            json_node("a", "a.txt", false, vec![]),
+            json_node("b", "b.txt", false, vec![]),
+        ]).to_string();
+        // 交换 a.txt 和 b.txt，并轮换 1→2→3→1
+        let modified = json_node("root", &root_name, true, vec![
+            json_node("1", "2", false, vec![]),
+            json_node("2", "3", false, vec![]),
+            json_node("3", "1", false, vec![]),
+            json_node("a", "b.txt", false, vec![]),
+            json_node("b", "a.txt", false, vec![]),
+        ]).to_string();
+
+        let operations = generate_operations_from_json(&root.to_string_lossy(), &original, &modified).unwrap();
+        if EXCHANGE_AVAILABLE {
+            // 交换是一次原子交换，轮换仍然经过临时名称
+            assert_eq!(operations.len(), 5, "{:?}", operations);
+            assert!(operations.contains(&FileOperation::Exchange {
+                from: root.join("a.txt").to_string_lossy().to_string(),
+                to: root.join("b.txt").to_string_lossy().to_string(),
+            }), "{:?}", operations);
+        } else {
+            assert_eq!(operations.len(), 7, "两个环各需要一次临时重命名: {:?}", operations);
+        }
+        let anchor = Anchor::open(&root).unwrap();
+        for op in &operations {
+            let result = apply_operation(&anchor, op);
+            assert!(result.success, "{}: {}", op, result.message);
+        }
+
+        assert_eq!(fs::read_to_string(root.join("a.txt")).unwrap(), "b.txt");
+        assert_eq!(fs::read_to_string(root.join("b.txt")).unwrap(), "a.txt");
+        assert_eq!(fs::read_to_string(root.join("1")).unwrap(), "3");
+        assert_eq!(fs::read_to_string(root.join("2")).unwrap(), "1");
+        assert_eq!(fs::read_to_string(root.join("3")).unwrap(), "2");
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 5, "不应残留临时文件");
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_nested_renames_only_touch_changed_nodes() {
+        let root_name = format!("treenamer_nested_{}", uuid::Uuid::new_v4());
+        let root = std::env::temp_dir().join(&root_name);
+        fs::create_dir_all(root.join("dir1")).unwrap();
+        fs::create_dir_all(root.join("dir2/subdir")).unwrap();
+        fs::create_dir_all(root.join("left/inner")).unwrap();
+        fs::create_dir_all(root.join("right")).unwrap();
+        fs::write(root.join("dir1/file1.txt"), "1").unwrap();
+        fs::write(root.join("dir1/file2.txt"), "2").unwrap();
+        fs::write(root.join("dir2/subdir/file3.txt"), "3").unwrap();
+        fs::write(root.join("left/inner/x.txt"), "x").unwrap();
+        fs::write(root.join("right/y.txt"), "y").unwrap();
+
+        let original = json_node("root", &root_name, true, vec![
+            json_node("dir1", "dir1", true, vec![
+                json_node("file1", "file1.txt", false, vec![]),
+                json_node("file2", "file2.txt", false, vec![]),
+            ]),
+            json_node("dir2", "dir2", true, vec![
+                json_node("subdir", "subdir", true, vec![json_node("file3", "file3.txt", false, vec![])]),
+            ]),
+            json_node("left", "left", true, vec![
+                json_node("inner", "inner", true, vec![json_node("x", "x.txt", false, vec![])]),
+            ]),
+            json_node("right", "right", true, vec![json_node("y", "y.txt", false, vec![])]),
+        ]).to_string();
+        // 目录和其中的文件同时改名，并交换两个目录的名称
+        let modified = json_node("root", &root_name, true, vec![
+            json_node("dir1", "renamed_dir1", true, vec![
+                json_node("file1", "file1.txt", false, vec![]),
+                json_node("file2", "file2_renamed.txt", false, vec![]),
+            ]),
+            json_node("dir2", "dir2", true, vec![
+                json_node("subdir", "renamed_subdir", true, vec![json_node("file3", "file3.txt", false, vec![])]),
+            ]),
+            json_node("left", "right", true, vec![
+                json_node("inner", "inner2", true, vec![json_node("x", "x2.txt", false, vec![])]),
+            ]),
+            json_node("right", "left", true, vec![json_node("y", "y.txt", false, vec![])]),
+        ]).to_string();
+
+        let operations = generate_operations_from_json(&root.to_string_lossy(), &original, &modified).unwrap();
+        // dir1, file2, subdir, inner, x, 以及交换 left/right（一次原子交换，或者经过临时名称的三步）
+        let swap_steps = if EXCHANGE_AVAILABLE { 1 } else { 3 };
+        assert_eq!(operations.len(), 5 + swap_steps, "unexpected operations: {:?}", operations);
+        let anchor = Anchor::open(&root).unwrap();
+        for op in &operations {
+            let result = apply_operation(&anchor, op);
+            assert!(result.success, "{}: {}", op, result.message);
+        }
+
+        assert_eq!(fs::read_to_string(root.join("renamed_dir1/file1.txt")).unwrap(), "1");
+        assert_eq!(fs::read_to_string(root.join("renamed_dir1/file2_renamed.txt")).unwrap(), "2");
+        assert_eq!(fs::read_to_string(root.join("dir2/renamed_subdir/file3.txt")).unwrap(), "3");
+        assert_eq!(fs::read_to_string(root.join("right/inner2/x2.txt")).unwrap(), "x");
+        assert_eq!(fs::read_to_string(root.join("left/y.txt")).unwrap(), "y");
+        assert!(!root.join("dir1").exists());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_conflicts_with_unscanned_entries_and_duplicates() {
+        let root_name = format!("treenamer_conflicts_{}", uuid::Uuid::new_v4());
+        let root = std::env::temp_dir().join(&root_name);
+        fs::create_dir_all(root.join("node_modules")).unwrap();
+        fs::write(root.join(".env"), "secret").unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("b.txt"), "b").unwrap();
+        fs::write(root.join("c.txt"), "c").unwrap();
+
+        // 扫描结果中不包含 .env 和 node_modules
+        let original = json_node("root", &root_name, true, vec![
+            json_node("a", "a.txt", false, vec![]),
+            json_node("b", "b.txt", false, vec![]),
+            json_node("c", "c.txt", false, vec![]),
+        ]).to_string();
+        let modified = json_node("root", &root_name, true, vec![
+            json_node("a", ".env", false, vec![]),
+            json_node("b", "same.txt", false, vec![]),
+            json_node("c", "same.txt", false, vec![]),
+        ]).to_string();
+        let base = root.to_string_lossy().to_string();
+
+        let conflicts = detect_conflicts(&base, &original, &modified, &PlanOptions::default()).unwrap();
+        assert_eq!(conflicts.len(), 2, "unexpected conflicts: {:?}", conflicts);
+        let on_disk = conflicts.iter().find(|c| c.kind == ConflictKind::ExistsOnDisk).unwrap();
+        assert_eq!(on_disk.node_ids, vec!["a".to_string()]);
+        let duplicate = conflicts.iter().find(|c| c.kind == ConflictKind::DuplicateTarget).unwrap();
+        assert_eq!(duplicate.node_ids, vec!["b".to_string(), "c".to_string()]);
+
+        // 存在冲突时不执行任何操作
+        let data = std::env::temp_dir().join(format!("treenamer_conflicts_data_{}", uuid::Uuid::new_v4()));
+        let result = apply_operations_at(&data, &base, &original, &modified, &PlanOptions::default(), false);
+        assert!(result.is_err());
+        assert!(!data.exists());
+        assert_eq!(fs::read_to_string(root.join(".env")).unwrap(), "secret");
+        assert!(root.join("a.txt").exists() && root.join("b.txt").exists());
+
+        // 没有启用删除时，被移除的节点仍然占用它的名称
+        let modified = json_node("root", &root_name, true, vec![
+            json_node("a", "b.txt", false, vec![]),
+            json_node("c", "c.txt", false, vec![]),
+        ]).to_string();
+        let conflicts = detect_conflicts(&base, &original, &modified, &PlanOptions::default()).unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].node_ids, vec!["a".to_string(), "b".to_string()]);
+        let conflicts = detect_conflicts(&base, &original, &modified, &PlanOptions { allow_delete: true, ..PlanOptions::default() }).unwrap();
+        assert!(conflicts.is_empty(), "{:?}", conflicts);
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_plan_preview_is_structured_and_stable() {
+        let make_dir = || {
+            let root_name = format!("treenamer_plan_{}", uuid::Uuid::new_v4());
+            let root = std::env::temp_dir().join(&root_name);
+            fs::create_dir_all(root.join("docs")).unwrap();
+            fs::write(root.join("a.txt"), "a").unwrap();
+            fs::write(root.join("b.txt"), "b").unwrap();
+            let original = json_node("root", &root_name, true, vec![
+                json_node("docs", "docs", true, vec![]),
+                json_node("a", "a.txt", false, vec![]),
+                json_node("b", "b.txt", false, vec![]),
+            ]).to_string();
+            // 交换两个文件，并把其中一个移动到新目录中
+            let modified = json_node("root", &root_name, true, vec![
+                json_node("docs", "docs", true, vec![
+                    json_node("new", "new", true, vec![json_node("a", "b.txt", false, vec![])]),
+                ]),
+                json_node("b", "a.txt", false, vec![]),
+            ]).to_string();
+            (root, original, modified)
+        };
+
+        let (root, original, modified) = make_dir();
+        let base = root.to_string_lossy().to_string();
+        let plan = generate_operations(base.clone(), original.clone(), modified.clone(), None).unwrap();
+
+        assert!(plan.conflicts.is_empty(), "{:?}", plan.conflicts);
+        assert_eq!(plan.operations.len(), 3, "{:?}", plan.operations);
+        for (index, planned) in plan.operations.iter().enumerate() {
+            assert_eq!(planned.index, index);
+            assert_eq!(planned.kind, planned.operation.kind());
+            assert!(planned.depends_on.iter().all(|&dep| dep < index));
+        }
+
+        let moved = plan.operations.iter().find(|p| p.kind == OperationKind::Move).unwrap();
+        assert_eq!(moved.node_id, "a");
+        let renamed = plan.operations.iter().find(|p| p.kind == OperationKind::Rename).unwrap();
+        assert_eq!(renamed.node_id, "b");
+        // b.txt 只能在 a.txt 腾出位置之后改名
+        assert_eq!(renamed.depends_on, vec![moved.index]);
+        let created = plan.operations.iter().find(|p| p.kind == OperationKind::CreateDir).unwrap();
+        assert_eq!(created.node_id, "new");
+
+        // 预览不修改磁盘
+        assert!(root.join("a.txt").exists() && root.join("b.txt").exists());
+        assert!(!root.join("docs/new").exists());
+
+        // 同样的修改在其他目录中得到同样的哈希
+        let again = generate_operations(base, original, modified, None).unwrap();
+        assert_eq!(again.hash, plan.hash);
+        let (other_root, other_original, other_modified) = make_dir();
+        let other_plan = generate_operations(other_root.to_string_lossy().to_string(), other_original, other_modified, None).unwrap();
+        assert_eq!(other_plan.hash, plan.hash);
+
+        let _ = fs::remove_dir_all(&root);
+        let _ = fs::remove_dir_all(&other_root);
+    }
+
+    #[test]
+    fn test_plan_order_follows_the_tree_model() {
+        let root_name = format!("treenamer_order_{}", uuid::Uuid::new_v4());
+        let root = std::env::temp_dir().join(&root_name);
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("docs/a.txt"), "a").unwrap();
+        fs::write(root.join("old.txt"), "old").unwrap();
+        fs::write(root.join("b.txt"), "b").unwrap();
+
+        let original = json_node("root", &root_name, true, vec![
+            json_node("docs", "docs", true, vec![json_node("a", "a.txt", false, vec![])]),
+            json_node("old", "old.txt", false, vec![]),
+            json_node("b", "b.txt", false, vec![]),
+        ]).to_string();
+        // docs 移动到同名的新目录中；删除 old.txt 后由 b.txt 使用它的名称
+        let modified = json_node("root", &root_name, true, vec![
+            json_node("new-docs", "docs", true, vec![
+                json_node("docs", "archive", true, vec![json_node("a", "a.txt", false, vec![])]),
+            ]),
+            json_node("b", "old.txt", false, vec![]),
+        ]).to_string();
+        let base = root.to_string_lossy().to_string();
+        let options = PlanOptions { allow_delete: true, ..PlanOptions::default() };
+
+        // 计划只取决于树模型：在不存在这些文件的目录中得到同样的计划
+        let plan = build_plan(&base, &original, &modified, &options).unwrap();
+        let empty_root = std::env::temp_dir().join(format!("treenamer_order_empty_{}", uuid::Uuid::new_v4())).join(&root_name);
+        fs::create_dir_all(&empty_root).unwrap();
+        let empty_plan = build_plan(&empty_root.to_string_lossy(), &original, &modified, &options).unwrap();
+        assert_eq!(plan.hash, empty_plan.hash);
+
+        for (index, planned) in plan.operations.iter().enumerate() {
+            assert!(planned.depends_on.iter().all(|&dep| dep < index));
+        }
+        let position = |kind: OperationKind, node_id: &str| plan.operations.iter()
+            .position(|p| p.kind == kind && p.node_id == node_id)
+            .unwrap_or_else(|| panic!("missing {:?} {} in {:?}", kind, node_id, plan.operations));
+        assert!(position(OperationKind::Delete, "old") < position(OperationKind::Rename, "b"));
+        assert!(position(OperationKind::CreateDir, "new-docs") < position(OperationKind::Move, "docs"));
+
+        let operations: Vec<FileOperation> = plan.operations.into_iter().map(|p| p.operation).collect();
+        for (op, result) in operations.iter().zip(execute_operations(&root, &operations)) {
+            assert!(result.success, "{}: {}", op, result.message);
+        }
+        assert_eq!(fs::read_to_string(root.join("docs/archive/a.txt")).unwrap(), "a");
+        assert_eq!(fs::read_to_string(root.join("old.txt")).unwrap(), "b");
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 2, "不应残留临时文件");
+
+        let _ = fs::remove_dir_all(&root);
+        let _ = fs::remove_dir_all(empty_root.parent().unwrap());
+    }
+
+    #[test]
+    fn test_large_plans_are_built_from_the_model() {
+        let root_name = format!("treenamer_large_{}", uuid::Uuid::new_v4());
+        let root = std::env::temp_dir().join(&root_name);
+        fs::create_dir_all(&root).unwrap();
+
+        // 100 个目录，每个目录 files 个文件，全部重命名
+        let tree = |files: usize, suffix: &str| {
+            let dirs: Vec<serde_json::Value> = (0..100).map(|d| {
+                let files: Vec<serde_json::Value> = (0..files)
+                    .map(|f| json_node(&format!("f{}-{}", d, f), &format!("file{}{}.txt", f, suffix), false, vec![]))
+                    .collect();
+                json_node(&format!("d{}", d), &format!("dir{}{}", d, suffix), true, files)
+            }).collect();
+            json_node("root", &root_name, true, dirs).to_string()
+        };
+        let base = root.to_string_lossy().to_string();
+        let build = |files: usize| {
+            let (original, modified) = (tree(files, ""), tree(files, "_renamed"));
+            let started = std::time::Instant::now();
+            let plan = build_plan(&base, &original, &modified, &PlanOptions::default()).unwrap();
+            (plan, started.elapsed(), original, modified)
+        };
+
+        let (_, small_elapsed, _, _) = build(100);
+        let (plan, elapsed, original, modified) = build(1000);
+        // 节点数是十倍：线性的构建大约慢十倍，平方级的构建会慢一百倍
+        assert!(elapsed < small_elapsed * 40 + Duration::from_millis(500),
+            "10k nodes took {:?}, 100k nodes took {:?}", small_elapsed, elapsed);
+
+        assert_eq!(plan.operations.len(), 100_100);
+        assert_eq!(build_plan(&base, &original, &modified, &PlanOptions::default()).unwrap().hash, plan.hash);
+        // 文件先于它们所在的目录重命名
+        assert!(plan.operations[..100_000].iter().all(|p| p.node_id.starts_with('f')));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_root_rename_and_children_named_like_the_root() {
+        let root_name = format!("treenamer_root_{}", uuid::Uuid::new_v4());
+        let parent = std::env::temp_dir().join(format!("treenamer_root_parent_{}", uuid::Uuid::new_v4()));
+        let root = parent.join(&root_name);
+        // 子目录与根目录同名，不能被当作路径前缀去掉
+        fs::create_dir_all(root.join(&root_name)).unwrap();
+        fs::write(root.join(&root_name).join("a.txt"), "a").unwrap();
+
+        let original = json_node("root", &root_name, true, vec![
+            json_node("inner", &root_name, true, vec![json_node("a", "a.txt", false, vec![])]),
+        ]).to_string();
+        let modified = json_node("root", "renamed", true, vec![
+            json_node("inner", &root_name, true, vec![json_node("a", "b.txt", false, vec![])]),
+        ]).to_string();
+        let base = root.to_string_lossy().to_string();
+
+        let plan = build_plan(&base, &original, &modified, &PlanOptions::default()).unwrap();
+        assert!(plan.conflicts.is_empty(), "{:?}", plan.conflicts);
+        assert_eq!(plan.operations.len(), 2, "{:?}", plan.operations);
+        assert_eq!(plan.operations[0].operation, FileOperation::Rename {
+            from: root.join(&root_name).join("a.txt").to_string_lossy().to_string(),
+            to: root.join(&root_name).join("b.txt").to_string_lossy().to_string(),
+        });
+        // 根目录最后改名
+        assert_eq!(plan.operations[1].node_id, "root");
+        assert_eq!(plan.operations[1].operation, FileOperation::Rename {
+            from: base.clone(),
+            to: parent.join("renamed").to_string_lossy().to_string(),
+        });
+
+        // 新名称已存在时报告冲突
+        fs::create_dir_all(parent.join("renamed")).unwrap();
+        let data = parent.join("data");
+        let result = apply_operations_at(&data, &base, &original, &modified, &PlanOptions::default(), false);
+        let error = result.unwrap_err();
+        assert!(error.message.contains("already exists"), "{}", error);
+        assert_eq!(error.error_type, ErrorType::UnresolvedConflict);
+        fs::remove_dir(parent.join("renamed")).unwrap();
+
+        let results = apply_operations_at(&data, &base, &original, &modified, &PlanOptions::default(), false).unwrap().results;
+        assert!(results.iter().all(|r| r.success), "{:?}", results);
+        assert_eq!(fs::read_to_string(parent.join("renamed").join(&root_name).join("b.txt")).unwrap(), "a");
+        assert!(!root.exists());
+
+        let invalid = json_node("root", "../escape", true, vec![]).to_string();
+        assert!(build_plan(&parent.join("renamed").to_string_lossy(), &original, &invalid, &PlanOptions::default()).is_err());
+
+        let _ = fs::remove_dir_all(&parent);
+    }
+
+    #[test]
+    fn test_merge_moves_missing_entries_into_the_existing_directory() {
+        let root = std::env::temp_dir().join(format!("treenamer_merge_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(root.join("from/sub")).unwrap();
+        fs::create_dir_all(root.join("to/sub")).unwrap();
+        fs::write(root.join("from/sub/a.txt"), "a").unwrap();
+        fs::write(root.join("from/b.txt"), "b").unwrap();
+        fs::write(root.join("to/sub/c.txt"), "c").unwrap();
+        fs::write(root.join("to/existing.txt"), "existing").unwrap();
+        let path = |relative: &str| root.join(relative).to_string_lossy().to_string();
+
+        // 重命名不会覆盖或合并已有的目标
+        let anchor = Anchor::open(&root).unwrap();
+        let result = apply_operation(&anchor, &FileOperation::Rename { from: path("from"), to: path("to") });
+        assert!(!result.success && result.message.contains("already exists"), "{}", result.message);
+        assert!(root.join("from/sub/a.txt").exists());
+
+        let result = apply_operation(&anchor, &FileOperation::Merge { from: path("from"), to: path("to") });
+        assert!(result.success, "{}", result.message);
+        assert!(!root.join("from").exists());
+        assert_eq!(fs::read_to_string(root.join("to/sub/a.txt")).unwrap(), "a");
+        assert_eq!(fs::read_to_string(root.join("to/sub/c.txt")).unwrap(), "c");
+        assert_eq!(fs::read_to_string(root.join("to/b.txt")).unwrap(), "b");
+
+        // 两边都有同名文件时什么都不移动
+        fs::create_dir_all(root.join("other")).unwrap();
+        fs::write(root.join("other/existing.txt"), "other").unwrap();
+        fs::write(root.join("other/d.txt"), "d").unwrap();
+        let result = apply_operation(&anchor, &FileOperation::Merge { from: path("other"), to: path("to") });
+        assert!(!result.success && result.message.contains("already exists"), "{}", result.message);
+        assert!(root.join("other/d.txt").exists() && !root.join("to/d.txt").exists());
+        assert_eq!(fs::read_to_string(root.join("to/existing.txt")).unwrap(), "existing");
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_same_named_directories_in_the_tree_are_merged() {
+        let root_name = format!("treenamer_merge_{}", uuid::Uuid::new_v4());
+        let root = std::env::temp_dir().join(&root_name);
+        fs::create_dir_all(root.join("photos")).unwrap();
+        fs::create_dir_all(root.join("pictures")).unwrap();
+        fs::write(root.join("photos/a.jpg"), "a").unwrap();
+        fs::write(root.join("pictures/b.jpg"), "b").unwrap();
+
+        let original = json_node("root", &root_name, true, vec![
+            json_node("photos", "photos", true, vec![json_node("a", "a.jpg", false, vec![])]),
+            json_node("pictures", "pictures", true, vec![json_node("b", "b.jpg", false, vec![])]),
+        ]).to_string();
+        let modified = json_node("root", &root_name, true, vec![
+            json_node("photos", "photos", true, vec![json_node("a", "a.jpg", false, vec![])]),
+            json_node("pictures", "photos", true, vec![json_node("b", "b.jpg", false, vec![])]),
+        ]).to_string();
+        let base = root.to_string_lossy().to_string();
+
+        let plan = build_plan(&base, &original, &modified, &PlanOptions::default()).unwrap();
+        let kinds: Vec<OperationKind> = plan.operations.iter().map(|planned| planned.kind).collect();
+        assert_eq!(kinds, vec![OperationKind::Merge], "{:?}", plan.operations);
+        let anchor = Anchor::open(&root).unwrap();
+        for planned in &plan.operations {
+            let result = apply_operation(&anchor, &planned.operation);
+            assert!(result.success, "{}", result.message);
+        }
+        assert!(!root.join("pictures").exists());
+        assert!(root.join("photos/a.jpg").exists() && root.join("photos/b.jpg").exists());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_collision_policies() {
+        let root_name = format!("treenamer_collision_{}", uuid::Uuid::new_v4());
+        let root = std::env::temp_dir().join(&root_name);
+        fs::create_dir_all(root.join("dir")).unwrap();
+        fs::create_dir_all(root.join("archive")).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("b.txt"), "b").unwrap();
+        fs::write(root.join("dir/x.txt"), "x").unwrap();
+        // 不在扫描结果中的条目
+        fs::write(root.join("taken.txt"), "taken").unwrap();
+        fs::write(root.join("c.txt"), "old").unwrap();
+        fs::write(root.join("archive/y.txt"), "y").unwrap();
+
+        let original = json_node("root", &root_name, true, vec![
+            json_node("a", "a.txt", false, vec![]),
+            json_node("b", "b.txt", false, vec![]),
+            json_node("dir", "dir", true, vec![json_node("x", "x.txt", false, vec![])]),
+        ]).to_string();
+        let modified = json_node("root", &root_name, true, vec![
+            json_node("a", "taken.txt", false, vec![]),
+            json_node("b", "c.txt", false, vec![]),
+            json_node("dir", "archive", true, vec![json_node("x", "x.txt", false, vec![])]),
+        ]).to_string();
+        let base = root.to_string_lossy().to_string();
+
+        // 默认策略：报告冲突
+        let plan = build_plan(&base, &original, &modified, &PlanOptions::default()).unwrap();
+        assert_eq!(plan.conflicts.len(), 3, "{:?}", plan.conflicts);
+        assert!(plan.collisions.is_empty());
+
+        // 跳过：不生成任何操作
+        let options = PlanOptions { collision: CollisionPolicy::Skip, ..PlanOptions::default() };
+        let plan = build_plan(&base, &original, &modified, &options).unwrap();
+        assert!(plan.conflicts.is_empty() && plan.operations.is_empty(), "{:?}", plan);
+        assert_eq!(plan.warnings.iter().filter(|w| w.contains("skipping")).count(), 3);
+
+        // 计划的策略是加后缀，单个节点可以覆盖它
+        let options = PlanOptions {
+            collision: CollisionPolicy::Suffix,
+            collision_overrides: HashMap::from([
+                ("b".to_string(), CollisionPolicy::Overwrite),
+                ("dir".to_string(), CollisionPolicy::Merge),
+            ]),
+            ..PlanOptions::default()
+        };
+        let plan = build_plan(&base, &original, &modified, &options).unwrap();
+        assert!(plan.conflicts.is_empty(), "{:?}", plan.conflicts);
+        let mut policies: Vec<(String, CollisionPolicy)> = plan.collisions.iter().map(|c| (c.node_id.clone(), c.policy)).collect();
+        policies.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(policies, vec![
+            ("a".to_string(), CollisionPolicy::Suffix),
+            ("b".to_string(), CollisionPolicy::Overwrite),
+            ("dir".to_string(), CollisionPolicy::Merge),
+        ]);
+        let suffixed = plan.collisions.iter().find(|c| c.node_id == "a").unwrap();
+        assert_eq!(suffixed.renamed_to.as_deref(), Some("taken (1).txt"));
+        let kinds: Vec<(OperationKind, Option<CollisionPolicy>)> = plan.operations.iter().map(|p| (p.kind, p.collision)).collect();
+        assert!(kinds.contains(&(OperationKind::Delete, Some(CollisionPolicy::Overwrite))), "{:?}", kinds);
+        assert!(kinds.contains(&(OperationKind::Merge, Some(CollisionPolicy::Merge))), "{:?}", kinds);
+
+        let anchor = Anchor::open(&root).unwrap();
+        for planned in &plan.operations {
+            let result = apply_operation(&anchor, &planned.operation);
+            assert!(result.success, "{}: {}", planned.operation, result.message);
+        }
+        assert_eq!(fs::read_to_string(root.join("taken.txt")).unwrap(), "taken");
+        assert_eq!(fs::read_to_string(root.join("taken (1).txt")).unwrap(), "a");
+        assert_eq!(fs::read_to_string(root.join("c.txt")).unwrap(), "b");
+        assert!(root.join("archive/x.txt").exists() && root.join("archive/y.txt").exists());
+        assert!(!root.join("dir").exists() && !root.join("a.txt").exists() && !root.join("b.txt").exists());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_overwrite_only_replaces_entries_of_the_same_kind() {
+        let root_name = format!("treenamer_collision_{}", uuid::Uuid::new_v4());
+        let root = std::env::temp_dir().join(&root_name);
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("b.txt"), "b").unwrap();
+        fs::write(root.join("src/x.txt"), "x").unwrap();
+        // 不在扫描结果中的条目
+        fs::create_dir_all(root.join("node_modules/pkg")).unwrap();
+        fs::write(root.join("node_modules/pkg/index.js"), "js").unwrap();
+        fs::create_dir_all(root.join("empty")).unwrap();
+        fs::write(root.join("target.txt"), "target").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(root.join("target.txt"), root.join("link")).unwrap();
+
+        let original = json_node("root", &root_name, true, vec![
+            json_node("a", "a.txt", false, vec![]),
+            json_node("b", "b.txt", false, vec![]),
+            json_node("src", "src", true, vec![json_node("x", "x.txt", false, vec![])]),
+        ]).to_string();
+        let modified = json_node("root", &root_name, true, vec![
+            json_node("a", "node_modules", false, vec![]),
+            json_node("b", if cfg!(unix) { "link" } else { "b.txt" }, false, vec![]),
+            json_node("src", "empty", true, vec![json_node("x", "x.txt", false, vec![])]),
+        ]).to_string();
+        let base = root.to_string_lossy().to_string();
+
+        let options = PlanOptions { collision: CollisionPolicy::Overwrite, ..PlanOptions::default() };
+        let plan = build_plan(&base, &original, &modified, &options).unwrap();
+        // 文件不会覆盖目录，也不会覆盖符号链接
+        let refused: HashSet<&str> = plan.conflicts.iter().flat_map(|c| c.node_ids.iter().map(String::as_str)).collect();
+        let expected: HashSet<&str> = if cfg!(unix) { HashSet::from(["a", "b"]) } else { HashSet::from(["a"]) };
+        assert_eq!(refused, expected, "{:?}", plan.conflicts);
+        assert!(plan.conflicts.iter().all(|c| c.message.contains("same kind")));
+        // 目录可以覆盖空目录
+        let deletes: Vec<&FileOperation> = plan.operations.iter()
+            .filter(|p| p.kind == OperationKind::Delete)
+            .map(|p| &p.operation)
+            .collect();
+        assert_eq!(deletes, vec![&FileOperation::Delete { path: root.join("empty").to_string_lossy().to_string() }]);
+        assert!(root.join("node_modules/pkg/index.js").exists());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_results_are_structured_and_summarized() {
+        let root = std::env::temp_dir().join(format!("treenamer_results_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("b.txt"), "b").unwrap();
+        let path = |relative: &str| root.join(relative).to_string_lossy().to_string();
+
+        let operations = vec![
+            FileOperation::Rename { from: path("a.txt"), to: path("c.txt") },
+            FileOperation::CreateDir { path: path("docs") },
+            FileOperation::Rename { from: path("missing.txt"), to: path("d.txt") },
+            FileOperation::Move { from: path("b.txt"), to: path("c.txt") },
+        ];
+        let mut results = execute_operations(&root, &operations);
+        attach_node_ids(&mut results, &["a".to_string(), "docs".to_string(), "missing".to_string(), "b".to_string()]);
+
+        let statuses: Vec<OperationStatus> = results.iter().map(|r| r.status).collect();
+        assert_eq!(statuses, vec![OperationStatus::Ok, OperationStatus::Skipped, OperationStatus::Failed, OperationStatus::Failed]);
+        assert_eq!(results[0].kind, Some(OperationKind::Rename));
+        assert_eq!(results[0].node_id.as_deref(), Some("a"));
+        assert_eq!((results[0].from.clone(), results[0].to.clone()), (Some(path("a.txt")), Some(path("c.txt"))));
+        assert!(results[0].error.is_none() && results[0].fallback.is_none());
+        assert_eq!((results[1].from.clone(), results[1].to.clone()), (None, Some(path("docs"))));
+
+        let missing = results[2].error.as_ref().unwrap();
+        assert_eq!(missing.error_type, ErrorType::FileNotFound);
+        assert_eq!(missing.path.as_deref(), Some(path("missing.txt").as_str()));
+        let existing = results[3].error.as_ref().unwrap();
+        assert_eq!(existing.error_type, ErrorType::PathAlreadyExists);
+        assert_eq!(existing.path.as_deref(), Some(path("c.txt").as_str()));
+        assert!(existing.recoverable && !results[3].success);
+
+        let summary = BatchSummary::of(&results);
+        assert_eq!((summary.total, summary.ok, summary.skipped, summary.failed, summary.partially_done), (4, 1, 1, 2, 0));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
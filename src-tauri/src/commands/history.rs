@@ -0,0 +1,435 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::command;
+use serde::{Serialize, Deserialize};
+use crate::commands::fs::FileOperation;
+use crate::commands::anchor::Anchor;
+use crate::commands::journal::{app_data_dir, start_journal};
+use crate::commands::transaction::{open_anchor, run_transaction, Transaction, TransactionReport, UndoAction};
+use crate::error::{AppError, ErrorType};
+
+/// How many applied batches are kept for undo.
+pub const MAX_HISTORY: usize = 50;
+
+// 同一时间只允许一个命令读写历史文件
+static HISTORY_LOCK: Mutex<()> = Mutex::new(());
+
+fn lock_history(dir: &Path) -> Result<MutexGuard<'static, ()>, AppError> {
+    HISTORY_LOCK.lock().map_err(|e| AppError::new(ErrorType::SystemError, e.to_string()).at(history_path(dir)))
+}
+
+/// What a path looked like right after a batch was applied or undone.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum EntryState {
+    Missing,
+    File { size: u64, modified: Option<u128> },
+    /// A directory and the names directly inside it
+    Dir { children: Vec<String> },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PathState {
+    pub path: String,
+    pub state: EntryState,
+}
+
+/// An applied batch that can be undone, or an undone batch that can be redone.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HistoryEntry {
+    pub id: String,
+    pub base: String,
+    /// When the batch was applied, in seconds since the Unix epoch
+    pub applied: u64,
+    pub operations: Vec<FileOperation>,
+    /// The operations that reverse the batch, in the order they run
+    pub inverse: Vec<FileOperation>,
+    /// Why the batch cannot be undone, if it cannot
+    pub irreversible: Option<String>,
+    /// The paths the batch touched, as they were after the last apply or undo
+    pub expected: Vec<PathState>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct History {
+    pub undo: Vec<HistoryEntry>,
+    pub redo: Vec<HistoryEntry>,
+}
+
+/// The outcome of `undo_last_batch` or `redo_batch`.
+#[derive(Debug, Serialize)]
+pub struct HistoryReport {
+    pub id: String,
+    /// The directory to reopen afterwards (the scanned root may have been renamed)
+    pub directory: String,
+    /// How the disk differs from the state the batch left behind; nothing is changed when not empty
+    pub divergences: Vec<String>,
+    pub report: Option<TransactionReport>,
+}
+
+fn history_path(dir: &Path) -> PathBuf {
+    dir.join("history.json")
+}
+
+pub fn load_history(dir: &Path) -> Result<History, AppError> {
+    let path = history_path(dir);
+    match fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content)
+            .map_err(|e| AppError::new(ErrorType::SystemError, format!("Failed to read history {}: {}", path.display(), e)).at(&path)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(History::default()),
+        Err(e) => Err(AppError::io(&e, format!("Failed to read history {}: {}", path.display(), e)).at(&path)),
+    }
+}
+
+// 先写临时文件再替换，避免崩溃时留下不完整的历史
+fn save_history(dir: &Path, history: &History) -> Result<(), AppError> {
+    fs::create_dir_all(dir).map_err(|e| AppError::io(&e, format!("Failed to create {}: {}", dir.display(), e)).at(dir))?;
+    let path = history_path(dir);
+    let temp = dir.join("history.json.tmp");
+    let content = serde_json::to_string_pretty(history)
+        .map_err(|e| AppError::new(ErrorType::SystemError, format!("Failed to serialize history: {}", e)).at(&path))?;
+    fs::write(&temp, content)
+        .and_then(|_| fs::File::open(&temp)?.sync_all())
+        .and_then(|_| fs::rename(&temp, &path))
+        .map_err(|e| AppError::io(&e, format!("Failed to write history {}: {}", path.display(), e)).at(&path))
+}
+
+// 撤销动作对应的反向操作；被删除的条目已经不存在，无法撤销
+fn inverse_operations(undo: &[UndoAction]) -> Result<Vec<FileOperation>, String> {
+    undo.iter().rev().map(|action| match action {
+        UndoAction::Rename { from, to } => Ok(if Path::new(from).parent() == Path::new(to).parent() {
+            FileOperation::Rename { from: from.clone(), to: to.clone() }
+        } else {
+            FileOperation::Move { from: from.clone(), to: to.clone() }
+        }),
+        UndoAction::Exchange { from, to, .. } => Ok(FileOperation::Exchange { from: from.clone(), to: to.clone() }),
+        UndoAction::RecreateDir { path } => Ok(FileOperation::CreateDir { path: path.clone() }),
+        UndoAction::RemoveDir { path } | UndoAction::RemoveFile { path } | UndoAction::RemoveCopy { path } => {
+            Ok(FileOperation::Delete { path: path.clone() })
+        },
+        UndoAction::Restore { path, .. } => Err(format!("{} was deleted", path)),
+    }).collect()
+}
+
+// 批次及其反向操作涉及的所有路径（包括自动创建的上级目录）
+fn touched_paths(operations: &[FileOperation], inverse: &[FileOperation]) -> BTreeSet<String> {
+    let mut paths = BTreeSet::new();
+    for operation in operations.iter().chain(inverse) {
+        match operation {
+            FileOperation::Rename { from, to } | FileOperation::Move { from, to } | FileOperation::Copy { from, to }
+            | FileOperation::Merge { from, to } | FileOperation::Exchange { from, to } => {
+                paths.insert(from.clone());
+                paths.insert(to.clone());
+            },
+            FileOperation::CreateDir { path } | FileOperation::CreateFile { path } | FileOperation::Delete { path } => {
+                paths.insert(path.clone());
+            },
+        }
+    }
+    paths
+}
+
+fn capture(anchor: &Anchor, path: &str) -> EntryState {
+    let target = Path::new(path);
+    // 按名称精确比较，在不区分大小写的卷上也能区分只改了大小写的名称
+    if !anchor.is_listed(target) {
+        return EntryState::Missing;
+    }
+    match anchor.metadata(target) {
+        Ok(metadata) if metadata.is_dir => {
+            let children = anchor.read_dir(target).map(|names| names.iter()
+                .map(|name| name.to_string_lossy().to_string())
+                .collect()).unwrap_or_default();
+            EntryState::Dir { children }
+        },
+        Ok(metadata) => EntryState::File { size: metadata.len, modified: metadata.modified },
+        Err(_) => EntryState::Missing,
+    }
+}
+
+fn capture_all(anchor: &Anchor, paths: &BTreeSet<String>) -> Vec<PathState> {
+    paths.iter().map(|path| PathState { path: path.clone(), state: capture(anchor, path) }).collect()
+}
+
+fn describe_state(state: &EntryState) -> String {
+    match state {
+        EntryState::Missing => "missing".to_string(),
+        EntryState::File { size, .. } => format!("a file of {} bytes", size),
+        EntryState::Dir { children } => format!("a directory with {} entries", children.len()),
+    }
+}
+
+/// Lists how the touched paths differ from the state recorded in the entry.
+pub(crate) fn find_divergences(anchor: &Anchor, expected: &[PathState]) -> Vec<String> {
+    expected.iter().filter_map(|expected| {
+        let actual = capture(anchor, &expected.path);
+        if actual == expected.state {
+            return None;
+        }
+        Some(match (&expected.state, &actual) {
+            (EntryState::Dir { children: before }, EntryState::Dir { children: after }) => {
+                let added: Vec<&String> = after.iter().filter(|name| !before.contains(name)).collect();
+                let removed: Vec<&String> = before.iter().filter(|name| !after.contains(name)).collect();
+                format!("{}: entries added {:?}, removed {:?}", expected.path, added, removed)
+            },
+            (EntryState::File { size: before, .. }, EntryState::File { size: after, .. }) if before == after => {
+                format!("{}: modified", expected.path)
+            },
+            _ => format!("{}: expected {}, found {}", expected.path, describe_state(&expected.state), describe_state(&actual)),
+        })
+    }).collect()
+}
+
+// 根目录被重命名时，批次的最后一个操作把 base 重命名到新的路径
+fn final_directory(base: &str, operations: &[FileOperation]) -> String {
+    match operations.last() {
+        Some(FileOperation::Rename { from, to }) if from == base => to.clone(),
+        _ => base.to_string(),
+    }
+}
+
+/// Records a batch that was applied successfully, with what reverses it.
+/// Recording a new batch clears the redo stack.
+pub fn record_batch(dir: &Path, base: &str, operations: &[FileOperation], undo: &[UndoAction]) -> Result<(), AppError> {
+    let _guard = lock_history(dir)?;
+    let mut history = load_history(dir)?;
+    let anchor = open_anchor(Path::new(base), operations)?;
+
+    let (inverse, irreversible) = match inverse_operations(undo) {
+        Ok(inverse) => (inverse, None),
+        Err(reason) => (Vec::new(), Some(reason)),
+    };
+    let entry = HistoryEntry {
+        id: uuid::Uuid::new_v4().simple().to_string(),
+        base: base.to_string(),
+        applied: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        operations: operations.to_vec(),
+        expected: capture_all(&anchor, &touched_paths(operations, &inverse)),
+        inverse,
+        irreversible,
+    };
+    println!("Recording batch {} with {} operations in the history", entry.id, operations.len());
+
+    history.redo.clear();
+    history.undo.push(entry);
+    if history.undo.len() > MAX_HISTORY {
+        let excess = history.undo.len() - MAX_HISTORY;
+        history.undo.drain(..excess);
+    }
+    save_history(dir, &history)
+}
+
+// 先检查磁盘是否仍是批次留下的状态，再作为一个事务执行；检查和执行都通过 anchor 进行
+fn replay(dir: &Path, entry: &HistoryEntry, anchor: &Anchor, operations: &[FileOperation], directory: String) -> Result<HistoryReport, AppError> {
+    let divergences = find_divergences(anchor, &entry.expected);
+    if !divergences.is_empty() {
+        println!("Batch {} no longer matches the disk: {:?}", entry.id, divergences);
+        return Ok(HistoryReport { id: entry.id.clone(), directory: entry.base.clone(), divergences, report: None });
+    }
+
+    let anchor = anchor.try_clone()
+        .map_err(|e| AppError::io(&e, format!("Failed to open {}: {}", entry.base, e)).at(&entry.base))?;
+    let journal = start_journal(dir, Path::new(&entry.base), operations, true)?;
+    let report = run_transaction(Transaction::with_journal(anchor, journal), operations, Vec::new(), true);
+    Ok(HistoryReport { id: entry.id.clone(), directory, divergences, report: Some(report) })
+}
+
+/// Reverses the most recent batch that has not been undone yet.
+pub fn undo_last_batch_at(dir: &Path) -> Result<HistoryReport, AppError> {
+    let _guard = lock_history(dir)?;
+    let mut history = load_history(dir)?;
+    let entry = history.undo.last().cloned()
+        .ok_or_else(|| AppError::new(ErrorType::InvalidInput, "Nothing to undo").at(history_path(dir)))?;
+    if let Some(reason) = &entry.irreversible {
+        return Err(AppError::new(ErrorType::Irreversible, format!("The last batch cannot be undone: {}", reason)).at(&entry.base));
+    }
+
+    println!("Undoing batch {}", entry.id);
+    let anchor = open_anchor(Path::new(&entry.base), &entry.inverse)?;
+    let result = replay(dir, &entry, &anchor, &entry.inverse, entry.base.clone())?;
+    if result.report.as_ref().is_some_and(|report| report.committed) {
+        history.undo.pop();
+        history.redo.push(HistoryEntry { expected: capture_all(&anchor, &touched_paths(&entry.operations, &entry.inverse)), ..entry });
+        save_history(dir, &history)?;
+    }
+    Ok(result)
+}
+
+/// Applies again the most recently undone batch.
+pub fn redo_batch_at(dir: &Path) -> Result<HistoryReport, AppError> {
+    let _guard = lock_history(dir)?;
+    let mut history = load_history(dir)?;
+    let entry = history.redo.last().cloned()
+        .ok_or_else(|| AppError::new(ErrorType::InvalidInput, "Nothing to redo").at(history_path(dir)))?;
+
+    println!("Redoing batch {}", entry.id);
+    let directory = final_directory(&entry.base, &entry.operations);
+    let anchor = open_anchor(Path::new(&entry.base), &entry.operations)?;
+    let result = replay(dir, &entry, &anchor, &entry.operations, directory)?;
+    if let Some(report) = result.report.as_ref().filter(|report| report.committed) {
+        // 重新执行时的撤销信息（如自动创建的上级目录）可能与第一次不同，重新计算
+        let (inverse, irreversible) = match inverse_operations(&report.undo) {
+            Ok(inverse) => (inverse, None),
+            Err(reason) => (Vec::new(), Some(reason)),
+        };
+        history.redo.pop();
+        history.undo.push(HistoryEntry { expected: capture_all(&anchor, &touched_paths(&entry.operations, &inverse)), inverse, irreversible, ..entry });
+        save_history(dir, &history)?;
+    }
+    Ok(result)
+}
+
+/// Records a successful batch in the history kept in `data_dir`.
+pub(crate) fn record_applied_batch(data_dir: &Path, base: &str, operations: &[FileOperation], report: &TransactionReport) {
+    if !report.committed || report.results.iter().any(|result| !result.success) {
+        return;
+    }
+    if let Err(e) = record_batch(data_dir, base, operations, &report.undo) {
+        println!("Failed to record the batch in the history: {}", e);
+    }
+}
+
+#[command]
+pub fn get_history() -> Result<History, AppError> {
+    let dir = app_data_dir();
+    let _guard = lock_history(&dir)?;
+    load_history(&dir)
+}
+
+/// Undoes the last applied batch after checking that the disk still matches what it left behind.
+#[command]
+pub fn undo_last_batch() -> Result<HistoryReport, AppError> {
+    undo_last_batch_at(&app_data_dir())
+}
+
+/// Redoes the last undone batch after checking that the disk still matches the undone state.
+#[command]
+pub fn redo_batch() -> Result<HistoryReport, AppError> {
+    redo_batch_at(&app_data_dir())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::test_support::{names, path, TempDir};
+    use crate::commands::transaction::execute_transaction;
+
+    fn apply(data: &Path, root: &Path, operations: &[FileOperation]) {
+        let report = execute_transaction(root, operations).unwrap();
+        assert!(report.committed, "{:?}", report.results);
+        record_batch(data, &root.to_string_lossy(), operations, &report.undo).unwrap();
+    }
+
+    #[test]
+    fn test_batches_are_undone_and_redone_in_order() {
+        let work = TempDir::new("history");
+        let (root, data) = (work.join("root"), work.join("data"));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+
+        apply(&data, &root, &[
+            FileOperation::Move { from: path(&root, "a.txt"), to: path(&root, "docs/b.txt") },
+            FileOperation::CreateFile { path: path(&root, "new.txt") },
+        ]);
+        apply(&data, &root, &[
+            FileOperation::Rename { from: path(&root, "docs"), to: path(&root, "notes") },
+        ]);
+        assert_eq!(names(&root), vec!["new.txt", "notes"]);
+
+        // 历史保存在磁盘上，重新加载后仍然可用
+        assert_eq!(load_history(&data).unwrap().undo.len(), 2);
+
+        let first = undo_last_batch_at(&data).unwrap();
+        assert!(first.divergences.is_empty());
+        assert!(first.report.unwrap().committed);
+        assert_eq!(names(&root), vec!["docs", "new.txt"]);
+
+        let second = undo_last_batch_at(&data).unwrap();
+        assert!(second.report.unwrap().committed);
+        assert_eq!(names(&root), vec!["a.txt"]);
+        assert!(undo_last_batch_at(&data).is_err());
+
+        let redone = redo_batch_at(&data).unwrap();
+        assert!(redone.report.unwrap().committed);
+        assert_eq!(names(&root), vec!["docs", "new.txt"]);
+        assert_eq!(fs::read_to_string(root.join("docs/b.txt")).unwrap(), "a");
+
+        let history = load_history(&data).unwrap();
+        assert_eq!((history.undo.len(), history.redo.len()), (1, 1));
+
+        // 新的批次清空重做记录
+        apply(&data, &root, &[FileOperation::CreateDir { path: path(&root, "other") }]);
+        assert!(load_history(&data).unwrap().redo.is_empty());
+        assert!(redo_batch_at(&data).is_err());
+    }
+
+    #[test]
+    fn test_batches_renaming_the_root_are_undone_and_redone() {
+        let work = TempDir::new("history");
+        let (root, renamed, data) = (work.join("root"), work.join("renamed"), work.join("data"));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+
+        apply(&data, &root, &[
+            FileOperation::Rename { from: path(&root, "a.txt"), to: path(&root, "b.txt") },
+            FileOperation::Rename { from: root.to_string_lossy().to_string(), to: renamed.to_string_lossy().to_string() },
+        ]);
+        assert!(!root.exists());
+        assert_eq!(names(&renamed), vec!["b.txt"]);
+
+        // 撤销时目录位于新名称下
+        let undone = undo_last_batch_at(&data).unwrap();
+        assert!(undone.divergences.is_empty(), "{:?}", undone.divergences);
+        let report = undone.report.unwrap();
+        assert!(report.committed, "{:?}", report.results);
+        assert_eq!(undone.directory, root.to_string_lossy());
+        assert!(!renamed.exists());
+        assert_eq!(names(&root), vec!["a.txt"]);
+
+        let redone = redo_batch_at(&data).unwrap();
+        let report = redone.report.unwrap();
+        assert!(report.committed, "{:?}", report.results);
+        assert_eq!(redone.directory, renamed.to_string_lossy());
+        assert!(!root.exists());
+        assert_eq!(fs::read_to_string(renamed.join("b.txt")).unwrap(), "a");
+    }
+
+    #[test]
+    fn test_divergence_blocks_undo() {
+        let work = TempDir::new("history");
+        let (root, data) = (work.join("root"), work.join("data"));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+
+        apply(&data, &root, &[
+            FileOperation::CreateDir { path: path(&root, "made") },
+            FileOperation::Rename { from: path(&root, "a.txt"), to: path(&root, "b.txt") },
+        ]);
+        fs::write(root.join("b.txt"), "changed after the batch").unwrap();
+        fs::write(root.join("made/added.txt"), "user data").unwrap();
+
+        let result = undo_last_batch_at(&data).unwrap();
+        assert!(result.report.is_none());
+        assert_eq!(result.divergences.len(), 2, "{:?}", result.divergences);
+        assert!(result.divergences.iter().any(|d| d.contains("added.txt")));
+        // 什么都没有改变，批次仍然可以撤销
+        assert_eq!(names(&root), vec!["b.txt", "made"]);
+        assert_eq!(load_history(&data).unwrap().undo.len(), 1);
+    }
+
+    #[test]
+    fn test_batches_with_deletions_cannot_be_undone() {
+        let work = TempDir::new("history");
+        let (root, data) = (work.join("root"), work.join("data"));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+
+        apply(&data, &root, &[FileOperation::Delete { path: path(&root, "a.txt") }]);
+        let error = undo_last_batch_at(&data).unwrap_err();
+        assert_eq!(error.error_type, ErrorType::Irreversible);
+        assert!(error.message.contains("was deleted"), "{}", error);
+        assert_eq!(error.path.as_deref(), Some(root.to_string_lossy().as_ref()));
+    }
+}
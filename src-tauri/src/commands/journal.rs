@@ -0,0 +1,583 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tauri::command;
+use serde::{Serialize, Deserialize};
+use crate::commands::copy_tree::{load_copy_settings, CopySettings};
+use crate::commands::fs::{BatchSummary, FileOperation, OperationResult};
+use crate::commands::history::record_applied_batch;
+use crate::commands::anchor::Anchor;
+use crate::commands::transaction::{is_partial_effect, open_anchor, run_transaction, undo, Transaction, TransactionReport, UndoAction};
+use crate::error::{AppError, ErrorType};
+
+/// Overrides the application data directory (mainly for tests and portable installs).
+pub const DATA_DIR_ENV: &str = "TREENAMER_DATA_DIR";
+
+/// One line of a journal file.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "record")]
+pub enum JournalRecord {
+    /// The batch about to run, written before any operation. A resumed batch copies
+    /// and verifies with the settings it started with
+    Begin {
+        id: String,
+        base: String,
+        atomic: bool,
+        started: u64,
+        operations: Vec<FileOperation>,
+        #[serde(default)]
+        copy_settings: CopySettings,
+    },
+    /// Written before an operation runs, with what reverses it
+    Intent { index: usize, undo: Vec<UndoAction> },
+    /// Written after an operation ran. A failure also keeps its error type and the
+    /// path it is about, so a resumed batch reports it as it happened
+    Done {
+        index: usize,
+        success: bool,
+        message: String,
+        #[serde(default)]
+        error_type: Option<ErrorType>,
+        #[serde(default)]
+        path: Option<String>,
+    },
+    /// Written after a rollback step succeeded
+    Undone { index: usize, action: UndoAction },
+    /// Every operation ran; only the cleanup of deleted entries may be left
+    Commit,
+}
+
+/// A write-ahead journal of one batch of operations. Every record is flushed to disk
+/// before the call returns, so after a crash the journal tells which operations ran.
+pub struct Journal {
+    id: String,
+    path: PathBuf,
+    file: File,
+    copy_settings: CopySettings,
+}
+
+impl Journal {
+    /// Starts a journal for the operations in `dir`, which must not be inside `base`.
+    pub fn create(dir: &Path, base: &Path, operations: &[FileOperation], atomic: bool, copy_settings: &CopySettings) -> io::Result<Journal> {
+        fs::create_dir_all(dir)?;
+        if let (Ok(dir), Ok(base)) = (fs::canonicalize(dir), fs::canonicalize(base)) {
+            if dir.starts_with(&base) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                    format!("The journal directory {} is inside {}", dir.display(), base.display())));
+            }
+        }
+
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let path = dir.join(format!("{}.journal", id));
+        let file = OpenOptions::new().append(true).create_new(true).open(&path)?;
+        sync_dir(dir);
+
+        let mut journal = Journal { id: id.clone(), path, file, copy_settings: copy_settings.clone() };
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        journal.record(&JournalRecord::Begin {
+            id,
+            base: base.to_string_lossy().to_string(),
+            atomic,
+            started,
+            operations: operations.to_vec(),
+            copy_settings: copy_settings.clone(),
+        })?;
+        println!("Journal {} started at {}", journal.id, journal.path.display());
+        Ok(journal)
+    }
+
+    // 继续写入之前去掉写了一半的最后一条记录
+    fn open(path: &Path, id: &str, copy_settings: &CopySettings) -> io::Result<Journal> {
+        let content = fs::read(path)?;
+        let complete = content.iter().rposition(|&byte| byte == b'\n').map_or(0, |position| position + 1);
+        let file = OpenOptions::new().append(true).open(path)?;
+        if complete < content.len() {
+            file.set_len(complete as u64)?;
+        }
+        Ok(Journal { id: id.to_string(), path: path.to_path_buf(), file, copy_settings: copy_settings.clone() })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// How entries moved to another device are verified in this batch.
+    pub fn copy_settings(&self) -> &CopySettings {
+        &self.copy_settings
+    }
+
+    pub fn record(&mut self, record: &JournalRecord) -> io::Result<()> {
+        let mut line = serde_json::to_string(record).map_err(io::Error::other)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()
+    }
+
+    /// Removes the journal once the batch no longer needs to be recovered.
+    pub fn finish(self) {
+        drop(self.file);
+        match fs::remove_file(&self.path) {
+            Ok(_) => println!("Journal {} finished", self.id),
+            Err(e) => println!("Failed to remove journal {}: {}", self.path.display(), e),
+        }
+    }
+}
+
+// 让新建的日志文件本身在目录中持久化
+#[cfg(unix)]
+fn sync_dir(dir: &Path) {
+    if let Err(e) = File::open(dir).and_then(|dir| dir.sync_all()) {
+        println!("Failed to sync {}: {}", dir.display(), e);
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_: &Path) {}
+
+/// The application data directory. Journals and the batch history are kept here,
+/// never in the directory being changed.
+pub fn app_data_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os(DATA_DIR_ENV) {
+        return PathBuf::from(dir);
+    }
+    let data_dir = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        std::env::var_os("XDG_DATA_HOME").map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+    };
+    data_dir.unwrap_or_else(std::env::temp_dir).join("treenamer")
+}
+
+pub fn journal_dir() -> PathBuf {
+    app_data_dir().join("journal")
+}
+
+/// Starts the journal of a batch in the journal directory under `data_dir`, with the
+/// copy settings stored there. They are read once here and used for the whole batch.
+pub(crate) fn start_journal(data_dir: &Path, base: &Path, operations: &[FileOperation], atomic: bool) -> Result<Journal, AppError> {
+    let dir = data_dir.join("journal");
+    let copy_settings = load_copy_settings(data_dir).unwrap_or_else(|e| {
+        println!("Using the default copy settings: {}", e);
+        CopySettings::default()
+    });
+    Journal::create(&dir, base, operations, atomic, &copy_settings)
+        .map_err(|e| AppError::io(&e, format!("Failed to start the operation journal: {}", e)).at(&dir))
+}
+
+/// A batch whose journal was left behind by a crash or a failed rollback.
+#[derive(Debug, Serialize, Clone)]
+pub struct InterruptedBatch {
+    pub id: String,
+    pub base: String,
+    /// Seconds since the Unix epoch
+    pub started: u64,
+    /// Whether a failure rolls back the whole batch
+    pub atomic: bool,
+    pub operations: Vec<FileOperation>,
+    /// Number of operations that finished running
+    pub completed: usize,
+    /// The operation that was running when the batch stopped
+    pub interrupted: Option<usize>,
+    /// Every operation ran; only deleted entries are left to clean up
+    pub committed: bool,
+    /// A rollback was started and did not finish
+    pub rolling_back: bool,
+}
+
+#[derive(Debug)]
+struct Step {
+    undo: Vec<UndoAction>,
+    done: Option<OperationResult>,
+}
+
+#[derive(Debug)]
+struct LoadedJournal {
+    batch: InterruptedBatch,
+    steps: BTreeMap<usize, Step>,
+    undone: Vec<(usize, UndoAction)>,
+    copy_settings: CopySettings,
+}
+
+fn load(path: &Path) -> Result<LoadedJournal, AppError> {
+    let corrupt = |message: String| AppError::new(ErrorType::SystemError, message).at(path);
+    let file = File::open(path).map_err(|e| AppError::io(&e, format!("Failed to open journal {}: {}", path.display(), e)).at(path))?;
+    let lines: Vec<String> = BufReader::new(file).lines()
+        .collect::<Result<_, _>>()
+        .map_err(|e| AppError::io(&e, format!("Failed to read journal {}: {}", path.display(), e)).at(path))?;
+
+    let mut records = Vec::with_capacity(lines.len());
+    for (number, line) in lines.iter().enumerate() {
+        match serde_json::from_str::<JournalRecord>(line) {
+            Ok(record) => records.push(record),
+            // 最后一行可能在写入时被中断
+            Err(_) if number + 1 == lines.len() => println!("Ignoring incomplete last record in {}", path.display()),
+            Err(e) => return Err(corrupt(format!("Corrupt journal {} at line {}: {}", path.display(), number + 1, e))),
+        }
+    }
+
+    let mut records = records.into_iter();
+    let Some(JournalRecord::Begin { id, base, atomic, started, operations, copy_settings }) = records.next() else {
+        return Err(corrupt(format!("Journal {} does not start with the batch", path.display())));
+    };
+    let mut batch = InterruptedBatch {
+        id, base, started, atomic, operations,
+        completed: 0, interrupted: None, committed: false, rolling_back: false,
+    };
+    let mut steps = BTreeMap::new();
+    let mut undone = Vec::new();
+    for record in records {
+        match record {
+            // 重新执行的操作会写入新的意图记录，取代之前的记录
+            JournalRecord::Intent { index, undo } => { steps.insert(index, Step { undo, done: None }); },
+            JournalRecord::Done { index, success, message, error_type, path } => {
+                if let Some(step) = steps.get_mut(&index) {
+                    step.done = Some(if success {
+                        OperationResult::ok(message)
+                    } else {
+                        // 旧的日志没有记录路径，退回到批次的目录
+                        let path = path.unwrap_or_else(|| batch.base.clone());
+                        OperationResult::failed(AppError::new(error_type.unwrap_or(ErrorType::SystemError), message).at(path))
+                    });
+                }
+            },
+            JournalRecord::Undone { index, action } => undone.push((index, action)),
+            JournalRecord::Commit => batch.committed = true,
+            JournalRecord::Begin { .. } => return Err(corrupt(format!("Journal {} contains more than one batch", path.display()))),
+        }
+    }
+
+    batch.completed = steps.values().filter(|step| step.done.is_some()).count();
+    batch.interrupted = steps.iter().find(|(_, step)| step.done.is_none()).map(|(index, _)| *index);
+    batch.rolling_back = !undone.is_empty();
+    Ok(LoadedJournal { batch, steps, undone, copy_settings })
+}
+
+pub(crate) fn journal_path(dir: &Path, id: &str) -> Result<PathBuf, AppError> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(AppError::new(ErrorType::InvalidInput, format!("Invalid batch id: {}", id)).at(dir));
+    }
+    Ok(dir.join(format!("{}.journal", id)))
+}
+
+/// Lists the batches in `dir` that did not finish.
+pub fn find_interrupted(dir: &Path) -> Vec<InterruptedBatch> {
+    let Ok(entries) = fs::read_dir(dir) else { return Vec::new() };
+    let mut batches: Vec<InterruptedBatch> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "journal"))
+        .filter_map(|path| match load(&path) {
+            Ok(loaded) => Some(loaded.batch),
+            Err(e) => {
+                println!("{}", e.message);
+                None
+            },
+        })
+        .collect();
+    batches.sort_by_key(|batch| batch.started);
+    batches
+}
+
+// 是否能从磁盘上看出中断的操作已经完成；名称按精确的拼写比较，在不区分大小写的卷上
+// 也能区分只改了大小写的名称
+fn operation_completed(anchor: &Anchor, operation: &FileOperation) -> bool {
+    let listed = |path: &str| anchor.is_listed(Path::new(path));
+    match operation {
+        FileOperation::Rename { from, to } | FileOperation::Move { from, to } => listed(to) && !listed(from),
+        FileOperation::Merge { from, to } => !listed(from) && anchor.is_dir(Path::new(to)).unwrap_or(false),
+        // 交换前后两个名称都存在，由撤销记录中的条目身份判断
+        FileOperation::Exchange { .. } => false,
+        FileOperation::Delete { path } => !listed(path),
+        FileOperation::CreateDir { path } => anchor.is_dir(Path::new(path)).unwrap_or(false),
+        FileOperation::CreateFile { path } => anchor.metadata(Path::new(path)).is_ok_and(|metadata| metadata.is_file),
+        // 无法判断复制是否完整，总是重新复制
+        FileOperation::Copy { .. } => false,
+    }
+}
+
+// 交换完成后，原来位于 from 的条目出现在 to
+fn exchange_completed(anchor: &Anchor, undo: &[UndoAction]) -> Option<bool> {
+    undo.iter().find_map(|action| match action {
+        UndoAction::Exchange { to, identity: Some(identity), .. } => Some(anchor.identity(Path::new(to)).as_ref() == Some(identity)),
+        _ => None,
+    })
+}
+
+struct Recovered {
+    undo_log: Vec<(usize, UndoAction)>,
+    results: Vec<OperationResult>,
+    /// The operation that was running, and whether the disk shows it completed
+    interrupted: Option<(usize, bool)>,
+}
+
+// 根据日志重建撤销记录
+fn recover(anchor: &Anchor, loaded: &LoadedJournal) -> Recovered {
+    let mut undo_log = Vec::new();
+    let mut results = Vec::new();
+    let mut interrupted = None;
+
+    for (&index, step) in &loaded.steps {
+        let completed = match &step.done {
+            Some(result) => {
+                results.push(result.clone().describe(&loaded.batch.operations[index], Duration::ZERO));
+                result.success
+            },
+            None => {
+                let completed = exchange_completed(anchor, &step.undo)
+                    .unwrap_or_else(|| operation_completed(anchor, &loaded.batch.operations[index]));
+                interrupted = Some((index, completed));
+                completed
+            },
+        };
+        for action in &step.undo {
+            if completed || is_partial_effect(anchor, action) {
+                undo_log.push((index, action.clone()));
+            }
+        }
+    }
+
+    // 去掉回滚时已经撤销的步骤
+    for done in &loaded.undone {
+        if let Some(position) = undo_log.iter().rposition(|entry| entry == done) {
+            undo_log.remove(position);
+        }
+    }
+    Recovered { undo_log, results, interrupted }
+}
+
+fn open_batch(dir: &Path, id: &str) -> Result<(LoadedJournal, Journal), AppError> {
+    let path = journal_path(dir, id)?;
+    let loaded = load(&path)?;
+    if loaded.steps.keys().any(|&index| index >= loaded.batch.operations.len()) {
+        return Err(AppError::new(ErrorType::SystemError,
+            format!("Journal {} refers to operations that are not in the batch", path.display())).at(&path));
+    }
+    let journal = Journal::open(&path, id, &loaded.copy_settings).map_err(|e| AppError::io(&e, format!("Failed to open journal {}: {}", path.display(), e)).at(&path))?;
+    Ok((loaded, journal))
+}
+
+/// Continues an interrupted batch from where it stopped. An operation that was running
+/// when the batch stopped is checked against the disk: if it did not complete, what it
+/// left behind is cleaned up and it runs again.
+pub fn resume_batch_at(dir: &Path, id: &str) -> Result<TransactionReport, AppError> {
+    let (loaded, journal) = open_batch(dir, id)?;
+    let failed = loaded.batch.atomic && loaded.steps.values().any(|step| step.done.as_ref().is_some_and(|result| !result.success));
+    if loaded.batch.rolling_back || failed {
+        return Err(AppError::new(ErrorType::InvalidInput,
+            format!("Batch {} was being rolled back and can only be rolled back", id)).at(&loaded.batch.base));
+    }
+    let anchor = open_anchor(Path::new(&loaded.batch.base), &loaded.batch.operations)?;
+    let Recovered { undo_log, mut results, interrupted } = recover(&anchor, &loaded);
+    println!("Resuming batch {} after {} operations", id, results.len());
+
+    // 清理未完成的操作留下的部分结果，随后重新执行它
+    let (cleanup, undo_log): (Vec<_>, Vec<_>) = undo_log.into_iter()
+        .partition(|(index, _)| interrupted == Some((*index, false)));
+    for (index, action) in cleanup.iter().rev() {
+        undo(&anchor, &loaded.copy_settings, action).map_err(|e| AppError { message: format!("Failed to clean up operation {}: {}", index + 1, e.message), ..e })?;
+    }
+    if let Some((index, true)) = interrupted {
+        let operation = &loaded.batch.operations[index];
+        results.push(OperationResult::ok(format!("Completed before the interruption: {}", operation)).describe(operation, Duration::ZERO));
+    }
+
+    Ok(run_transaction(Transaction::recovered(anchor, undo_log, journal), &loaded.batch.operations, results, loaded.batch.atomic))
+}
+
+/// Reverses everything an interrupted batch did, including a rollback that stopped halfway.
+pub fn rollback_batch_at(dir: &Path, id: &str) -> Result<TransactionReport, AppError> {
+    let (loaded, journal) = open_batch(dir, id)?;
+    if loaded.batch.committed {
+        return Err(AppError::new(ErrorType::InvalidInput,
+            format!("Batch {} already finished and its deleted entries may be gone", id)).at(&loaded.batch.base));
+    }
+    let anchor = open_anchor(Path::new(&loaded.batch.base), &loaded.batch.operations)?;
+    let Recovered { undo_log, results, interrupted } = recover(&anchor, &loaded);
+    println!("Rolling back batch {}", id);
+
+    let rollback = Transaction::recovered(anchor, undo_log, journal).rollback();
+    let rolled_back = rollback.iter().all(|step| step.success);
+    Ok(TransactionReport {
+        committed: false,
+        summary: BatchSummary::of(&results),
+        results,
+        failed_at: interrupted.map(|(index, _)| index),
+        rollback,
+        rolled_back,
+        cleanup_errors: Vec::new(),
+        cancelled: false,
+        undo: Vec::new(),
+    })
+}
+
+/// Lists batches that were interrupted, to be offered for resume or rollback.
+#[command]
+pub fn list_interrupted_batches() -> Vec<InterruptedBatch> {
+    find_interrupted(&journal_dir())
+}
+
+#[command]
+pub fn resume_batch(#[allow(non_snake_case)] batchId: String) -> Result<TransactionReport, AppError> {
+    let batch = find_interrupted(&journal_dir()).into_iter().find(|batch| batch.id == batchId);
+    let report = resume_batch_at(&journal_dir(), &batchId)?;
+    if let Some(batch) = batch {
+        record_applied_batch(&app_data_dir(), &batch.base, &batch.operations, &report);
+    }
+    Ok(report)
+}
+
+#[command]
+pub fn rollback_batch(#[allow(non_snake_case)] batchId: String) -> Result<TransactionReport, AppError> {
+    rollback_batch_at(&journal_dir(), &batchId)
+}
+
+/// Forgets an interrupted batch without touching the files it changed. Refused while
+/// entries deleted by the batch are still staged under hidden names: only the journal
+/// knows where they are, so the batch has to be resumed or rolled back instead.
+pub fn discard_batch_at(dir: &Path, id: &str) -> Result<(), AppError> {
+    let path = journal_path(dir, id)?;
+    let loaded = load(&path)?;
+    let anchor = open_anchor(Path::new(&loaded.batch.base), &loaded.batch.operations).ok();
+    let staged: Vec<&String> = loaded.steps.values()
+        .flat_map(|step| &step.undo)
+        .filter_map(|action| match action {
+            UndoAction::Restore { staged, .. } => Some(staged),
+            _ => None,
+        })
+        .filter(|staged| anchor.as_ref().is_some_and(|anchor| anchor.exists(Path::new(staged))))
+        .collect();
+    if let Some(first) = staged.first() {
+        return Err(AppError::new(ErrorType::InvalidInput, format!(
+            "Batch {} still keeps {} deleted entries under hidden names; resume or roll it back instead", id, staged.len()
+        )).at(first));
+    }
+    fs::remove_file(&path).map_err(|e| AppError::io(&e, format!("Failed to remove journal {}: {}", path.display(), e)).at(&path))
+}
+
+#[command]
+pub fn discard_batch(#[allow(non_snake_case)] batchId: String) -> Result<(), AppError> {
+    discard_batch_at(&journal_dir(), &batchId)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::test_support::{names, path, TempDir};
+
+    // 执行前两个操作，写下第三个操作的意图后“崩溃”，此时第三个重命名已经完成
+    fn crash_midway(work: &Path) -> (PathBuf, PathBuf, String) {
+        let root = work.join("root");
+        let journals = work.join("journal");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("b.txt"), "b").unwrap();
+        fs::write(root.join("sub/c.txt"), "c").unwrap();
+
+        let operations = vec![
+            FileOperation::Move { from: path(&root, "a.txt"), to: path(&root, "new/a.txt") },
+            FileOperation::Delete { path: path(&root, "b.txt") },
+            FileOperation::Rename { from: path(&root, "sub"), to: path(&root, "renamed") },
+            FileOperation::CreateFile { path: path(&root, "renamed/d.txt") },
+        ];
+        let journal = Journal::create(&journals, &root, &operations, true, &CopySettings::default()).unwrap();
+        let id = journal.id().to_string();
+        let mut transaction = Transaction::with_journal(Anchor::open(&root).unwrap(), journal);
+        assert!(transaction.apply(0, &operations[0]).success);
+        assert!(transaction.apply(1, &operations[1]).success);
+        drop(transaction);
+
+        let mut journal = Journal::open(&journal_path(&journals, &id).unwrap(), &id, &CopySettings::default()).unwrap();
+        journal.record(&JournalRecord::Intent {
+            index: 2,
+            undo: vec![UndoAction::Rename { from: path(&root, "renamed"), to: path(&root, "sub") }],
+        }).unwrap();
+        fs::rename(root.join("sub"), root.join("renamed")).unwrap();
+        // 最后一条记录只写了一半
+        journal.file.write_all(b"{\"record\":\"Done\",\"ind").unwrap();
+        (root, journals, id)
+    }
+
+    #[test]
+    fn test_interrupted_batch_is_found_and_resumed() {
+        let work = TempDir::new("journal");
+        let (root, journals, id) = crash_midway(&work);
+
+        let batches = find_interrupted(&journals);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].id, id);
+        assert_eq!(batches[0].completed, 2);
+        assert_eq!(batches[0].interrupted, Some(2));
+        assert!(!batches[0].committed);
+
+        let report = resume_batch_at(&journals, &id).unwrap();
+        assert!(report.committed, "{:?}", report.results);
+        assert_eq!(report.results.len(), 4);
+        assert_eq!(names(&root), vec!["new", "renamed"]);
+        assert_eq!(names(&root.join("renamed")), vec!["c.txt", "d.txt"]);
+        // 日志在完成后被删除
+        assert!(find_interrupted(&journals).is_empty());
+    }
+
+    #[test]
+    fn test_interrupted_batch_is_rolled_back() {
+        let work = TempDir::new("journal");
+        let (root, journals, id) = crash_midway(&work);
+
+        // 回滚中途再次“崩溃”：日志中写了一半的记录被去掉，之后的记录仍然可读
+        let journal = Journal::open(&journal_path(&journals, &id).unwrap(), &id, &CopySettings::default()).unwrap();
+        drop(journal);
+        assert_eq!(find_interrupted(&journals)[0].completed, 2);
+
+        // 被删除的 b.txt 还在隐藏名称下，只有日志知道它在哪里
+        let error = discard_batch_at(&journals, &id).unwrap_err();
+        assert_eq!(error.error_type, ErrorType::InvalidInput);
+        assert!(error.path.unwrap().contains(".treenamer-deleted-"));
+
+        let report = rollback_batch_at(&journals, &id).unwrap();
+        assert!(report.rolled_back, "{:?}", report.rollback);
+        assert_eq!(report.failed_at, Some(2));
+        assert_eq!(names(&root), vec!["a.txt", "b.txt", "sub"]);
+        assert_eq!(fs::read_to_string(root.join("b.txt")).unwrap(), "b");
+        assert_eq!(names(&root.join("sub")), vec!["c.txt"]);
+        assert!(find_interrupted(&journals).is_empty());
+    }
+
+    #[test]
+    fn test_resumed_failures_keep_their_error_type_and_path() {
+        let work = TempDir::new("journal");
+        let (root, journals) = (work.join("root"), work.join("journal"));
+        fs::create_dir_all(&root).unwrap();
+
+        let operations = vec![
+            FileOperation::Delete { path: path(&root, "missing.txt") },
+            FileOperation::CreateFile { path: path(&root, "new.txt") },
+        ];
+        let journal = Journal::create(&journals, &root, &operations, false, &CopySettings::default()).unwrap();
+        let id = journal.id().to_string();
+        let mut transaction = Transaction::with_journal(Anchor::open(&root).unwrap(), journal);
+        assert!(!transaction.apply(0, &operations[0]).success);
+        drop(transaction);
+
+        let report = resume_batch_at(&journals, &id).unwrap();
+        let error = report.results[0].error.as_ref().unwrap();
+        assert_eq!(error.error_type, ErrorType::FileNotFound);
+        assert_eq!(error.path.as_deref(), Some(path(&root, "missing.txt").as_str()));
+        assert!(report.results[1].success, "{:?}", report.results);
+    }
+
+    #[test]
+    fn test_journal_is_kept_outside_the_target_directory() {
+        let work = TempDir::new("journal");
+
+        assert!(Journal::create(&work.join("journal"), &work, &[], true, &CopySettings::default()).is_err());
+        assert!(journal_path(&work, "../escape").is_err());
+    }
+}
@@ -1,8 +1,33 @@
 // Export all command modules
 pub mod fs;
 pub mod tree;
+pub mod fingerprint;
+pub mod plan;
+pub mod conflicts;
+pub mod collision;
+pub mod plan_file;
+pub mod volume;
+pub mod transaction;
+pub mod async_apply;
+pub mod journal;
+pub mod history;
+pub mod backup;
+pub mod simulate;
+pub mod copy_tree;
+pub mod cross_device;
+pub mod atomic_rename;
+pub mod anchor;
 pub mod test;
+#[cfg(test)]
+pub(crate) mod test_support;
 
 // Re-export all commands for easier imports
 pub use fs::*;
-pub use tree::*; 
\ No newline at end of file
+pub use tree::*;
+pub use plan_file::*;
+pub use transaction::*;
+pub use async_apply::*;
+pub use journal::*;
+pub use history::*;
+pub use backup::*;
+pub use copy_tree::*;
\ No newline at end of file
@@ -0,0 +1,755 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::commands::conflicts::Conflict;
+use crate::commands::collision::{Collision, CollisionPolicy};
+use crate::commands::fingerprint::ExternalChange;
+use crate::commands::fs::{FileOperation, NodeEntry, OperationKind};
+
+/// One step of a plan, tagged with the node it belongs to.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PlannedOperation {
+    pub index: usize,
+    pub node_id: String,
+    pub kind: OperationKind,
+    pub operation: FileOperation,
+    /// Indices of earlier operations that must have run before this one
+    pub depends_on: Vec<usize>,
+    /// The collision policy that produced or changed this operation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collision: Option<CollisionPolicy>,
+}
+
+/// Everything `apply_operations` would do, computed without touching the disk.
+#[derive(Debug, Serialize, Clone)]
+pub struct Plan {
+    pub operations: Vec<PlannedOperation>,
+    pub conflicts: Vec<Conflict>,
+    /// Targets that already existed on disk and how their policies handle them
+    pub collisions: Vec<Collision>,
+    pub warnings: Vec<String>,
+    /// Entries the plan works on that changed on disk since the scan
+    pub external_changes: Vec<ExternalChange>,
+    /// Stable hash of the operations, independent of where the directory lives
+    pub hash: String,
+}
+
+/// A position in the tree: the directory a node lives in and its own name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Slot {
+    pub parent_id: String,
+    pub name: String,
+}
+
+/// The change of a single node: only the node itself is renamed or moved,
+/// its descendants follow along without operations of their own.
+#[derive(Debug, Clone)]
+pub struct NodeMove {
+    pub node_id: String,
+    pub from: Slot,
+    pub to: Slot,
+    /// Whether the node ends up in a different directory (a move rather than a rename)
+    pub changes_parent: bool,
+}
+
+/// Orders node moves so that none of them targets a slot that is still occupied
+/// by another pending move.
+///
+/// Every move is blocked by at most one other move (the one leaving its target),
+/// so the dependency graph consists of chains and simple cycles. Chains are emitted
+/// back to front. Each cycle (a swap or a rotation) is broken by first parking one
+/// of its members under a unique temporary name in the same directory, which turns
+/// the rest of the cycle into a chain. `is_taken` reports slots that are occupied
+/// by something outside the plan and can therefore not be used as temporary names.
+///
+/// Moves that do not depend on each other keep their relative input order.
+pub fn order_renames(moves: Vec<NodeMove>, is_taken: impl Fn(&Slot) -> bool) -> Vec<NodeMove> {
+    let by_source: HashMap<&Slot, usize> = moves.iter().enumerate()
+        .map(|(index, node_move)| (&node_move.from, index))
+        .collect();
+    
+    // blocker[i] = 需要先把目标位置腾出来的操作
+    let blocker: Vec<Option<usize>> = moves.iter().enumerate()
+        .map(|(index, node_move)| by_source.get(&node_move.to).copied().filter(|&other| other != index))
+        .collect();
+    
+    // 所有已被占用或将被占用的位置，临时名称必须避开它们
+    let mut reserved: HashSet<Slot> = moves.iter()
+        .flat_map(|node_move| [node_move.from.clone(), node_move.to.clone()])
+        .collect();
+    
+    let mut done = vec![false; moves.len()];
+    let mut ordered = Vec::with_capacity(moves.len());
+    
+    for start in 0..moves.len() {
+        if done[start] {
+            continue;
+        }
+        
+        // 沿着阻塞链前进，直到遇到已完成的操作、链尾或环
+        let mut chain = vec![start];
+        let mut on_chain: HashMap<usize, usize> = HashMap::from([(start, 0)]);
+        let mut cycle_start = None;
+        while let Some(next) = blocker[*chain.last().unwrap()] {
+            if done[next] {
+                break;
+            }
+            if let Some(&position) = on_chain.get(&next) {
+                cycle_start = Some(position);
+                break;
+            }
+            on_chain.insert(next, chain.len());
+            chain.push(next);
+        }
+        
+        match cycle_start {
+            None => {
+                for &index in chain.iter().rev() {
+                    ordered.push(moves[index].clone());
+                    done[index] = true;
+                }
+            },
+            Some(position) => {
+                // 先把环中的第一个操作移到临时名称，释放它的源位置
+                let parked = &moves[chain[position]];
+                let temp = temporary_slot(&parked.from, &mut reserved, &is_taken);
+                println!("Breaking rename cycle through temporary name: {}", temp.name);
+                ordered.push(NodeMove {
+                    node_id: parked.node_id.clone(),
+                    from: parked.from.clone(),
+                    to: temp.clone(),
+                    changes_parent: false,
+                });
+                
+                for &index in chain[position + 1..].iter().rev() {
+                    ordered.push(moves[index].clone());
+                    done[index] = true;
+                }
+                
+                ordered.push(NodeMove { from: temp, ..parked.clone() });
+                done[chain[position]] = true;
+                
+                for &index in chain[..position].iter().rev() {
+                    ordered.push(moves[index].clone());
+                    done[index] = true;
+                }
+            },
+        }
+    }
+    
+    ordered
+}
+
+/// Tracks where every node is while the operations of a plan are applied one by one,
+/// so that each operation can be expressed against the state left by the ones before it.
+pub struct TreeState {
+    // 节点ID -> (父节点ID, 名称)
+    nodes: HashMap<String, (Option<String>, String)>,
+}
+
+impl TreeState {
+    pub(crate) fn new(entries: &[NodeEntry]) -> Self {
+        let mut state = TreeState { nodes: HashMap::new() };
+        for entry in entries {
+            let parent_id = entry.parent.map(|index| entries[index].id.clone());
+            state.insert(&entry.id, parent_id, &entry.name);
+        }
+        state
+    }
+    
+    pub fn insert(&mut self, id: &str, parent_id: Option<String>, name: &str) {
+        self.nodes.insert(id.to_string(), (parent_id, name.to_string()));
+    }
+    
+    pub fn contains(&self, id: &str) -> bool {
+        self.nodes.contains_key(id)
+    }
+    
+    /// Current path of a node, starting with the root name.
+    pub fn path(&self, id: &str) -> String {
+        let mut names = Vec::new();
+        let mut current = Some(id);
+        while let Some(node_id) = current {
+            let Some((parent_id, name)) = self.nodes.get(node_id) else { break };
+            names.push(name.as_str());
+            current = parent_id.as_deref();
+        }
+        names.reverse();
+        names.join("/")
+    }
+    
+    /// Applies a move and returns the node's path before and after it.
+    pub fn apply(&mut self, node_move: &NodeMove) -> (String, String) {
+        let from = self.path(&node_move.node_id);
+        self.insert(&node_move.node_id, Some(node_move.to.parent_id.clone()), &node_move.to.name);
+        let to = self.path(&node_move.node_id);
+        (from, to)
+    }
+}
+
+/// A node-level change that still has to be placed in the final order.
+/// Paths are not known yet: they depend on which changes run before it.
+pub enum NodeChange {
+    Move(NodeMove),
+    /// A new node; `key` identifies it in the tree state, `parent` is the key of its directory
+    Create { node_id: String, key: String, parent: String, name: String, is_dir: bool },
+    /// A further occurrence of `source`; `nested` lists the (key, parent key, name)
+    /// of the existing nodes that are copied along with it
+    Copy { node_id: String, key: String, source: String, parent: String, name: String, nested: Vec<(String, String, String)> },
+    /// A removed node; `contains` holds the IDs of everything originally below it
+    Delete { node_id: String, contains: HashSet<String> },
+}
+
+impl NodeChange {
+    // 没有其他约束时的执行顺序：移动 -> 复制 -> 新建目录 -> 新建文件 -> 删除
+    fn rank(&self) -> u8 {
+        match self {
+            NodeChange::Move(_) => 0,
+            NodeChange::Copy { .. } => 1,
+            NodeChange::Create { is_dir: true, .. } => 2,
+            NodeChange::Create { is_dir: false, .. } => 3,
+            NodeChange::Delete { .. } => 4,
+        }
+    }
+}
+
+// 一个变更暂时不能执行的原因
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Wait {
+    /// 必须先执行的变更
+    Change(usize),
+    /// 父目录尚不存在
+    Node(String),
+    /// 目标位置仍被一个即将离开的节点占用
+    Slot(Slot),
+    /// 所有移动完成之后
+    Moves,
+}
+
+struct Scheduler<'a> {
+    changes: &'a [NodeChange],
+    state: TreeState,
+    occupants: HashMap<Slot, String>,
+    depends_on: Vec<Vec<usize>>,
+    done: Vec<bool>,
+    pending_moves: HashMap<&'a str, usize>,
+    pending_deletes: HashSet<&'a str>,
+    moves_left: usize,
+    reserved: HashSet<Slot>,
+    ordered: Vec<(String, FileOperation)>,
+}
+
+impl<'a> Scheduler<'a> {
+    fn will_vacate(&self, id: &str) -> bool {
+        self.pending_moves.get(id).is_some_and(|&count| count > 0) || self.pending_deletes.contains(id)
+    }
+    
+    // 目标位置被另一个还会离开的节点占用时需要等待；不会离开的节点属于冲突或目录合并
+    fn slot_wait(&self, slot: &Slot, id: &str) -> Option<Wait> {
+        match self.occupants.get(slot) {
+            Some(occupant) if occupant != id && self.will_vacate(occupant) => Some(Wait::Slot(slot.clone())),
+            _ => None,
+        }
+    }
+    
+    fn parent_wait(&self, parent: &str) -> Option<Wait> {
+        (!self.state.contains(parent)).then(|| Wait::Node(parent.to_string()))
+    }
+    
+    fn blocker(&self, index: usize) -> Option<Wait> {
+        if let Some(&dependency) = self.depends_on[index].iter().find(|&&dependency| !self.done[dependency]) {
+            return Some(Wait::Change(dependency));
+        }
+        match &self.changes[index] {
+            NodeChange::Move(node_move) => self.parent_wait(&node_move.to.parent_id)
+                .or_else(|| self.slot_wait(&node_move.to, &node_move.node_id)),
+            NodeChange::Create { key, parent, name, .. } => self.parent_wait(parent)
+                .or_else(|| self.slot_wait(&Slot { parent_id: parent.clone(), name: name.clone() }, key)),
+            NodeChange::Copy { key, parent, name, .. } => {
+                if self.moves_left > 0 {
+                    return Some(Wait::Moves);
+                }
+                self.parent_wait(parent)
+                    .or_else(|| self.slot_wait(&Slot { parent_id: parent.clone(), name: name.clone() }, key))
+            },
+            NodeChange::Delete { .. } => None,
+        }
+    }
+    
+    // 把节点放到新位置，返回因此可以继续的等待
+    fn place(&mut self, id: &str, parent: &str, name: &str, wakes: &mut Vec<Wait>) {
+        match self.state.nodes.get(id) {
+            Some((Some(old_parent), old_name)) => {
+                let old = Slot { parent_id: old_parent.clone(), name: old_name.clone() };
+                if self.occupants.get(&old).is_some_and(|occupant| occupant == id) {
+                    self.occupants.remove(&old);
+                }
+                wakes.push(Wait::Slot(old));
+            },
+            Some((None, _)) => {},
+            None => wakes.push(Wait::Node(id.to_string())),
+        }
+        self.state.insert(id, Some(parent.to_string()), name);
+        self.occupants.insert(Slot { parent_id: parent.to_string(), name: name.to_string() }, id.to_string());
+    }
+    
+    fn run(&mut self, index: usize) -> Vec<Wait> {
+        let mut wakes = vec![Wait::Change(index)];
+        self.done[index] = true;
+        
+        let (node_id, operation) = match &self.changes[index] {
+            NodeChange::Move(node_move) => {
+                let from = self.state.path(&node_move.node_id);
+                // 目标位置上还有一个不会离开的同名目录：两个目录合并
+                let merges = self.occupants.get(&node_move.to).is_some_and(|occupant| *occupant != node_move.node_id);
+                self.place(&node_move.node_id, &node_move.to.parent_id, &node_move.to.name, &mut wakes);
+                let to = self.state.path(&node_move.node_id);
+                if let Some(count) = self.pending_moves.get_mut(node_move.node_id.as_str()) {
+                    *count -= 1;
+                }
+                self.moves_left -= 1;
+                if self.moves_left == 0 {
+                    wakes.push(Wait::Moves);
+                }
+                (node_move.node_id.clone(), if merges {
+                    FileOperation::Merge { from, to }
+                } else if node_move.changes_parent {
+                    FileOperation::Move { from, to }
+                } else {
+                    FileOperation::Rename { from, to }
+                })
+            },
+            NodeChange::Create { node_id, key, parent, name, is_dir } => {
+                self.place(key, parent, name, &mut wakes);
+                let path = self.state.path(key);
+                (node_id.clone(), if *is_dir {
+                    FileOperation::CreateDir { path }
+                } else {
+                    FileOperation::CreateFile { path }
+                })
+            },
+            NodeChange::Copy { node_id, key, source, parent, name, nested } => {
+                let from = self.state.path(source);
+                self.place(key, parent, name, &mut wakes);
+                let to = self.state.path(key);
+                for (nested_key, nested_parent, nested_name) in nested {
+                    self.place(nested_key, nested_parent, nested_name, &mut wakes);
+                }
+                (node_id.clone(), FileOperation::Copy { from, to })
+            },
+            NodeChange::Delete { node_id, .. } => {
+                let path = self.state.path(node_id);
+                if let Some((Some(parent), name)) = self.state.nodes.remove(node_id) {
+                    let slot = Slot { parent_id: parent, name };
+                    if self.occupants.get(&slot) == Some(node_id) {
+                        self.occupants.remove(&slot);
+                    }
+                    wakes.push(Wait::Slot(slot));
+                }
+                self.pending_deletes.remove(node_id.as_str());
+                (node_id.clone(), FileOperation::Delete { path })
+            },
+        };
+        
+        self.ordered.push((node_id, operation));
+        wakes
+    }
+    
+    // 把一个还要移动的节点暂时移到临时名称，释放它占用的位置
+    fn park(&mut self, id: &str, is_taken: &impl Fn(&Slot) -> bool) -> Vec<Wait> {
+        let mut wakes = Vec::new();
+        let Some((Some(parent), name)) = self.state.nodes.get(id).cloned() else { return wakes };
+        let occupants = &self.occupants;
+        let temp = temporary_slot(
+            &Slot { parent_id: parent, name },
+            &mut self.reserved,
+            &|slot: &Slot| is_taken(slot) || occupants.contains_key(slot),
+        );
+        println!("Parking {} under temporary name: {}", id, temp.name);
+        
+        let from = self.state.path(id);
+        self.place(id, &temp.parent_id, &temp.name, &mut wakes);
+        let to = self.state.path(id);
+        self.ordered.push((id.to_string(), FileOperation::Rename { from, to }));
+        wakes
+    }
+}
+
+/// Puts node changes into a topological order and expresses each of them as a
+/// file operation with tree paths (starting with the root name).
+///
+/// The order is derived from the tree model alone, never from the disk: a change
+/// waits for the directory it goes into to exist, for its target to be vacated by
+/// nodes that are still going to leave, and for the changes it explicitly depends on
+/// (earlier moves of the same node, moves out of a directory before it is deleted).
+/// Among the changes that are ready, the lowest rank and then the input position
+/// wins, so the result is deterministic. When everything left is blocked on a slot,
+/// its occupant is parked under a temporary name, which breaks cycles such as a
+/// directory moving into a new directory that takes its old name.
+pub fn schedule_changes(
+    state: TreeState,
+    changes: &[NodeChange],
+    is_taken: impl Fn(&Slot) -> bool
+) -> Vec<(String, FileOperation)> {
+    let occupants: HashMap<Slot, String> = state.nodes.iter()
+        .filter_map(|(id, (parent, name))| {
+            parent.as_ref().map(|parent| (Slot { parent_id: parent.clone(), name: name.clone() }, id.clone()))
+        })
+        .collect();
+    
+    // 同一节点的多次移动保持顺序；删除等待从其中移出和复制出的节点
+    let mut depends_on = vec![Vec::new(); changes.len()];
+    let mut last_move: HashMap<&str, usize> = HashMap::new();
+    let mut pending_moves: HashMap<&str, usize> = HashMap::new();
+    let mut pending_deletes = HashSet::new();
+    let mut moves_left = 0;
+    for (index, change) in changes.iter().enumerate() {
+        if let NodeChange::Move(node_move) = change {
+            if let Some(previous) = last_move.insert(&node_move.node_id, index) {
+                depends_on[index].push(previous);
+            }
+            *pending_moves.entry(node_move.node_id.as_str()).or_default() += 1;
+            moves_left += 1;
+        }
+    }
+    let mut reading: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (index, change) in changes.iter().enumerate() {
+        match change {
+            NodeChange::Move(node_move) => reading.entry(node_move.node_id.as_str()).or_default().push(index),
+            NodeChange::Copy { source, .. } => reading.entry(source.as_str()).or_default().push(index),
+            _ => {},
+        }
+    }
+    for (index, change) in changes.iter().enumerate() {
+        let NodeChange::Delete { node_id, contains } = change else { continue };
+        pending_deletes.insert(node_id.as_str());
+        for id in contains {
+            depends_on[index].extend(reading.get(id.as_str()).into_iter().flatten());
+        }
+        depends_on[index].sort();
+    }
+    
+    let mut scheduler = Scheduler {
+        changes,
+        state,
+        occupants,
+        depends_on,
+        done: vec![false; changes.len()],
+        pending_moves,
+        pending_deletes,
+        moves_left,
+        reserved: HashSet::new(),
+        ordered: Vec::with_capacity(changes.len()),
+    };
+    
+    let mut ready: BinaryHeap<Reverse<(u8, usize)>> = changes.iter().enumerate()
+        .map(|(index, change)| Reverse((change.rank(), index)))
+        .collect();
+    let mut waiting: HashMap<Wait, Vec<usize>> = HashMap::new();
+    let mut blocked: BTreeMap<(u8, usize), Wait> = BTreeMap::new();
+    
+    loop {
+        while let Some(Reverse((rank, index))) = ready.pop() {
+            if scheduler.done[index] {
+                continue;
+            }
+            blocked.remove(&(rank, index));
+            let wakes = match scheduler.blocker(index) {
+                Some(wait) => {
+                    waiting.entry(wait.clone()).or_default().push(index);
+                    blocked.insert((rank, index), wait);
+                    continue;
+                },
+                None => scheduler.run(index),
+            };
+            for wake in wakes {
+                for waiter in waiting.remove(&wake).unwrap_or_default() {
+                    ready.push(Reverse((changes[waiter].rank(), waiter)));
+                }
+            }
+        }
+        
+        let Some((&(rank, index), _)) = blocked.iter().next() else { break };
+        
+        // 全部被阻塞：优先把占用目标位置的节点移到临时名称，否则按顺序强制执行
+        let parkable = blocked.values().find_map(|wait| match wait {
+            Wait::Slot(slot) => scheduler.occupants.get(slot)
+                .filter(|occupant| scheduler.pending_moves.get(occupant.as_str()).is_some_and(|&count| count > 0))
+                .cloned(),
+            _ => None,
+        });
+        let wakes = match parkable {
+            Some(occupant) => scheduler.park(&occupant, &is_taken),
+            None => {
+                println!("Unable to satisfy the dependencies of change {}, scheduling it anyway", index);
+                blocked.remove(&(rank, index));
+                scheduler.run(index)
+            },
+        };
+        for wake in wakes {
+            for waiter in waiting.remove(&wake).unwrap_or_default() {
+                ready.push(Reverse((changes[waiter].rank(), waiter)));
+            }
+        }
+    }
+    
+    scheduler.ordered
+}
+
+// 获取操作读取/腾出的源路径，以及写入的目标路径
+pub(crate) fn source_and_target(operation: &FileOperation) -> (Option<&str>, Option<&str>) {
+    match operation {
+        FileOperation::Rename { from, to } | FileOperation::Move { from, to } | FileOperation::Copy { from, to }
+            | FileOperation::Merge { from, to } | FileOperation::Exchange { from, to } => (Some(from), Some(to)),
+        FileOperation::CreateDir { path } | FileOperation::CreateFile { path } => (None, Some(path)),
+        FileOperation::Delete { path } => (Some(path), None),
+    }
+}
+
+/// Derives the dependencies between ordered operations from the paths they touch.
+///
+/// An operation depends on the earlier operation that last put something at its
+/// source, its target's parent, or any of their ancestors, and on the operation
+/// that last vacated its target. An exchange needs both of its paths and puts
+/// something at both.
+pub fn compute_dependencies<'a>(operations: impl Iterator<Item = &'a FileOperation>) -> Vec<Vec<usize>> {
+    let mut last_writer: HashMap<&Path, usize> = HashMap::new();
+    let mut last_vacater: HashMap<&Path, usize> = HashMap::new();
+    let mut dependencies = Vec::new();
+    
+    for (index, operation) in operations.enumerate() {
+        let (source, target) = source_and_target(operation);
+        let mut depends_on = BTreeSet::new();
+        
+        if let Some(source) = source {
+            for ancestor in Path::new(source).ancestors() {
+                depends_on.extend(last_writer.get(ancestor));
+            }
+        }
+        if let Some(target) = target {
+            let target = Path::new(target);
+            for ancestor in target.ancestors().skip(1) {
+                depends_on.extend(last_writer.get(ancestor));
+            }
+            depends_on.extend(last_vacater.get(target));
+        }
+        
+        let exchange = matches!(operation, FileOperation::Exchange { .. });
+        if let (true, Some(target)) = (exchange, target) {
+            depends_on.extend(last_writer.get(Path::new(target)));
+        }
+        
+        // 复制不会腾出源路径，交换之后两个路径上都有条目
+        if let (Some(source), false) = (source, matches!(operation, FileOperation::Copy { .. })) {
+            if exchange {
+                last_writer.insert(Path::new(source), index);
+            } else {
+                last_vacater.insert(Path::new(source), index);
+            }
+        }
+        if let Some(target) = target {
+            last_writer.insert(Path::new(target), index);
+        }
+        dependencies.push(depends_on.into_iter().collect());
+    }
+    
+    dependencies
+}
+
+/// Expresses a path relative to the scanned directory, with `/` separators.
+/// The directory itself is the empty path and a sibling (the target of a root
+/// rename) is `../name`; anything else is not relative to the plan.
+pub fn relative_path(base_path: &Path, path: &str) -> Option<String> {
+    let join = |relative: &Path| relative.components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect::<Vec<_>>()
+        .join("/");
+    
+    let path = Path::new(path);
+    if let Ok(relative) = path.strip_prefix(base_path) {
+        return Some(join(relative));
+    }
+    let parent = base_path.parent()?;
+    let relative = path.strip_prefix(parent).ok()?;
+    (relative.components().count() == 1).then(|| format!("../{}", join(relative)))
+}
+
+/// Hashes the ordered operations with paths relative to the scanned directory,
+/// so the same edit produces the same hash wherever the directory lives.
+pub fn plan_hash(base_path: &Path, operations: &[PlannedOperation]) -> String {
+    let relative = |path: &str| relative_path(base_path, path).unwrap_or_else(|| path.to_string());
+    
+    let mut canonical = String::new();
+    for planned in operations {
+        let (source, target) = source_and_target(&planned.operation);
+        canonical.push_str(&format!(
+            "{:?}\t{}\t{}\t{}\n",
+            planned.kind,
+            planned.node_id,
+            source.map(relative).unwrap_or_default(),
+            target.map(relative).unwrap_or_default(),
+        ));
+    }
+    
+    format!("{:x}", md5::compute(canonical.as_bytes()))
+}
+
+/// Replaces each swap through a temporary name (`a → tmp`, `b → a`, `tmp → b`) with
+/// a single atomic `Exchange` of `a` and `b`, which never leaves either entry under
+/// a temporary name.
+pub(crate) fn collapse_swaps(ordered: Vec<(String, FileOperation)>) -> Vec<(String, FileOperation)> {
+    let mut collapsed = Vec::with_capacity(ordered.len());
+    let mut index = 0;
+    while index < ordered.len() {
+        match ordered.get(index..index + 3).and_then(as_swap) {
+            Some(exchange) => {
+                collapsed.push((ordered[index].0.clone(), exchange));
+                index += 3;
+            },
+            None => {
+                collapsed.push(ordered[index].clone());
+                index += 1;
+            },
+        }
+    }
+    collapsed
+}
+
+fn as_swap(steps: &[(String, FileOperation)]) -> Option<FileOperation> {
+    let moved = |operation: &FileOperation| match operation {
+        FileOperation::Rename { from, to } | FileOperation::Move { from, to } => Some((from.clone(), to.clone())),
+        _ => None,
+    };
+    let (a, temp) = moved(&steps[0].1)?;
+    let (b, to_a) = moved(&steps[1].1)?;
+    let (from_temp, to_b) = moved(&steps[2].1)?;
+    let is_temporary = Path::new(&temp).file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with(".treenamer-tmp-"));
+    (is_temporary && steps[0].0 == steps[2].0 && to_a == a && from_temp == temp && to_b == b)
+        .then_some(FileOperation::Exchange { from: a, to: b })
+}
+
+// 在同一目录下生成一个不会与其他位置冲突的临时名称
+fn temporary_slot(slot: &Slot, reserved: &mut HashSet<Slot>, is_taken: &impl Fn(&Slot) -> bool) -> Slot {
+    let mut counter = 0;
+    loop {
+        let candidate = Slot {
+            parent_id: slot.parent_id.clone(),
+            name: format!(".treenamer-tmp-{}-{}", counter, slot.name),
+        };
+        if !reserved.contains(&candidate) && !is_taken(&candidate) {
+            reserved.insert(candidate.clone());
+            return candidate;
+        }
+        counter += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn slot(name: &str) -> Slot {
+        Slot { parent_id: "d".to_string(), name: name.to_string() }
+    }
+    
+    fn rename(from: &str, to: &str) -> NodeMove {
+        NodeMove { node_id: from.to_string(), from: slot(from), to: slot(to), changes_parent: false }
+    }
+    
+    // 在内存中模拟执行，目标位置被占用时返回错误
+    fn simulate(initial: &[&str], moves: &[NodeMove]) -> Result<HashMap<String, String>, String> {
+        let mut contents: HashMap<String, String> = initial.iter()
+            .map(|name| (name.to_string(), name.to_string()))
+            .collect();
+        for node_move in moves {
+            if contents.contains_key(&node_move.to.name) {
+                return Err(format!("{:?} would overwrite {}", node_move, node_move.to.name));
+            }
+            let content = contents.remove(&node_move.from.name)
+                .ok_or_else(|| format!("{:?} has no source", node_move))?;
+            contents.insert(node_move.to.name.clone(), content);
+        }
+        Ok(contents)
+    }
+    
+    #[test]
+    fn test_chain_is_ordered_back_to_front() {
+        let ordered = order_renames(vec![rename("a", "b"), rename("b", "c")], |_| false);
+        let contents = simulate(&["a", "b"], &ordered).unwrap();
+        assert_eq!(ordered.len(), 2);
+        assert_eq!(contents["b"], "a");
+        assert_eq!(contents["c"], "b");
+    }
+    
+    #[test]
+    fn test_swap_uses_temporary_name() {
+        let ordered = order_renames(vec![rename("a.txt", "b.txt"), rename("b.txt", "a.txt")], |_| false);
+        assert_eq!(ordered.len(), 3, "交换需要一次临时重命名: {:?}", ordered);
+        let contents = simulate(&["a.txt", "b.txt"], &ordered).unwrap();
+        assert_eq!(contents["a.txt"], "b.txt");
+        assert_eq!(contents["b.txt"], "a.txt");
+    }
+    
+    #[test]
+    fn test_temporary_name_avoids_taken_slots() {
+        let ordered = order_renames(
+            vec![rename("a", "b"), rename("b", "a")],
+            |slot| slot.name == ".treenamer-tmp-0-a",
+        );
+        assert_eq!(ordered[0].to.name, ".treenamer-tmp-1-a");
+    }
+    
+    #[test]
+    fn test_every_permutation_is_non_destructive() {
+        let names = ["1", "2", "3", "4", "5"];
+        
+        // 枚举所有排列
+        fn permute(prefix: Vec<usize>, n: usize, out: &mut Vec<Vec<usize>>) {
+            if prefix.len() == n {
+                out.push(prefix);
+                return;
+            }
+            for i in (0..n).filter(|i| !prefix.contains(i)) {
+                let mut next = prefix.clone();
+                next.push(i);
+                permute(next, n, out);
+            }
+        }
+        let mut permutations = Vec::new();
+        permute(Vec::new(), names.len(), &mut permutations);
+        assert_eq!(permutations.len(), 120);
+        
+        for perm in permutations {
+            let moves: Vec<NodeMove> = perm.iter().enumerate()
+                .filter(|(i, &j)| *i != j)
+                .map(|(i, &j)| rename(names[i], names[j]))
+                .collect();
+            let ordered = order_renames(moves, |_| false);
+            let contents = simulate(&names, &ordered)
+                .unwrap_or_else(|e| panic!("permutation {:?}: {}", perm, e));
+            for (i, &j) in perm.iter().enumerate() {
+                assert_eq!(contents[names[j]], names[i], "permutation {:?}", perm);
+            }
+            assert_eq!(contents.len(), names.len());
+        }
+    }
+    
+    #[test]
+    fn test_tree_state_rebases_descendant_paths() {
+        let mut state = TreeState { nodes: HashMap::new() };
+        state.insert("root", None, "root");
+        state.insert("dir", Some("root".to_string()), "photos");
+        state.insert("file", Some("dir".to_string()), "img.jpg");
+        
+        let dir_move = NodeMove {
+            node_id: "dir".to_string(),
+            from: Slot { parent_id: "root".to_string(), name: "photos".to_string() },
+            to: Slot { parent_id: "root".to_string(), name: "2024 photos".to_string() },
+            changes_parent: false,
+        };
+        assert_eq!(state.apply(&dir_move), ("root/photos".to_string(), "root/2024 photos".to_string()));
+        assert_eq!(state.path("file"), "root/2024 photos/img.jpg");
+    }
+}
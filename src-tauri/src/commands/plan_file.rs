@@ -0,0 +1,370 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Component, Path};
+use tauri::command;
+use serde::{Serialize, Deserialize};
+use crate::commands::fingerprint::find_external_changes;
+use crate::commands::fs::{build_plan, to_absolute_path, scanned_tree_entries, validate_root_name, FileOperation, OperationKind, PlanOptions};
+use crate::commands::journal::app_data_dir;
+use crate::commands::transaction::{apply_batch, TransactionReport};
+use crate::commands::plan::{plan_hash, relative_path, source_and_target, PlannedOperation};
+use crate::commands::collision::CollisionPolicy;
+use crate::commands::tree::{build_tree_with_options, DirectoryOptions, TreeNode};
+use crate::error::{AppError, ErrorType};
+
+/// Version written to new plan files. Bump it whenever the format changes.
+pub const PLAN_FILE_VERSION: u32 = 1;
+
+/// A saved rename plan. All paths are relative to the scanned directory and use `/`,
+/// so the file can be reviewed, kept and applied on another machine.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlanFile {
+    pub version: u32,
+    /// Name of the scanned directory when the plan was made (informational only)
+    pub root_name: String,
+    /// Fingerprint of the original tree, checked again before the plan is applied
+    pub fingerprint: String,
+    /// Scan options needed to reproduce the fingerprint
+    pub scan_options: DirectoryOptions,
+    pub plan_options: PlanOptions,
+    pub hash: String,
+    pub operations: Vec<PlannedOperation>,
+    pub warnings: Vec<String>,
+}
+
+/// Fingerprint of a scanned tree: its structure relative to the root,
+/// independent of node IDs and of the root's own name.
+pub fn tree_fingerprint(root: &TreeNode) -> String {
+    fn collect(node: &TreeNode, parent_path: &str, lines: &mut Vec<String>) {
+        for child in &node.children {
+            let path = if parent_path.is_empty() {
+                child.name.clone()
+            } else {
+                format!("{}/{}", parent_path, child.name)
+            };
+            lines.push(format!("{}{}", path, if child.is_dir { "/" } else { "" }));
+            collect(child, &path, lines);
+        }
+    }
+
+    let mut lines = Vec::new();
+    collect(root, "", &mut lines);
+    lines.sort();
+    format!("{:x}", md5::compute(lines.join("\n").as_bytes()))
+}
+
+// 将绝对路径转换为相对于根目录、使用 / 分隔的路径
+fn to_relative(base_path: &Path, path: &str) -> Result<String, AppError> {
+    relative_path(base_path, path)
+        .ok_or_else(|| AppError::new(ErrorType::InvalidInput, format!("Path {} is outside of {}", path, base_path.display())).at(path))
+}
+
+// 将计划文件中的相对路径还原为绝对路径，拒绝任何可能离开根目录的路径
+fn to_absolute(base_path: &Path, relative: &str) -> Result<String, AppError> {
+    let path = Path::new(relative);
+    if relative.is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(AppError::new(ErrorType::InvalidInput, format!("Invalid path in plan file: {}", relative)).at(relative));
+    }
+    Ok(base_path.join(path).to_string_lossy().to_string())
+}
+
+/// Builds a plan file for the given edit. Plans with conflicts, or for a directory
+/// that changed since it was scanned, cannot be saved.
+pub fn create_plan_file(
+    base_path: &str,
+    original_tree: &str,
+    modified_tree: &str,
+    plan_options: PlanOptions,
+    scan_options: DirectoryOptions
+) -> Result<PlanFile, AppError> {
+    let plan = build_plan(base_path, original_tree, modified_tree, &plan_options)?;
+    if !plan.conflicts.is_empty() {
+        return Err(AppError::new(ErrorType::UnresolvedConflict,
+            format!("Cannot save a plan with {} unresolved conflicts", plan.conflicts.len())).at(&plan.conflicts[0].path));
+    }
+    if !plan.external_changes.is_empty() {
+        return Err(AppError::new(ErrorType::ExternalModification,
+            format!("Cannot save a plan for a directory that changed since it was scanned ({} entries)", plan.external_changes.len()))
+            .at(to_absolute_path(Path::new(base_path), &plan.external_changes[0].path)));
+    }
+
+    let original: TreeNode = serde_json::from_str(original_tree)
+        .map_err(|e| AppError::new(ErrorType::InvalidInput, format!("Invalid original tree JSON: {}", e)).at(base_path))?;
+
+    let base = Path::new(base_path);
+    let mut operations = Vec::with_capacity(plan.operations.len());
+    for planned in plan.operations {
+        // 先检查所有路径都在根目录内
+        let (source, target) = source_and_target(&planned.operation);
+        for path in source.into_iter().chain(target) {
+            to_relative(base, path)?;
+        }
+        let operation = planned.operation.map_paths(|path| to_relative(base, path).unwrap_or_default());
+        operations.push(PlannedOperation { operation, ..planned });
+    }
+
+    Ok(PlanFile {
+        version: PLAN_FILE_VERSION,
+        root_name: original.name.clone(),
+        fingerprint: tree_fingerprint(&original),
+        scan_options,
+        plan_options,
+        hash: plan.hash,
+        operations,
+        warnings: plan.warnings,
+    })
+}
+
+/// Reads a plan file and checks its version and integrity.
+pub fn read_plan_file(plan_path: &str) -> Result<PlanFile, AppError> {
+    let content = fs::read_to_string(plan_path)
+        .map_err(|e| AppError::io(&e, format!("Failed to read plan file {}: {}", plan_path, e)).at(plan_path))?;
+    let plan: PlanFile = serde_json::from_str(&content)
+        .map_err(|e| AppError::new(ErrorType::InvalidInput, format!("Invalid plan file {}: {}", plan_path, e)).at(plan_path))?;
+
+    if plan.version != PLAN_FILE_VERSION {
+        return Err(AppError::new(ErrorType::InvalidInput,
+            format!("Unsupported plan file version {} (expected {})", plan.version, PLAN_FILE_VERSION)).at(plan_path));
+    }
+
+    // 相对路径的哈希与生成计划时的哈希相同
+    let hash = plan_hash(Path::new(""), &plan.operations);
+    if hash != plan.hash {
+        return Err(AppError::new(ErrorType::InvalidInput,
+            format!("Plan file {} was modified: hash {} does not match {}", plan_path, hash, plan.hash)).at(plan_path));
+    }
+
+    Ok(plan)
+}
+
+// 描述计划与当前磁盘状态的差异：缺失的源路径和已被占用的目标路径
+fn describe_drift(base_path: &Path, operations: &[PlannedOperation]) -> Vec<String> {
+    let mut written: HashSet<&str> = HashSet::new();
+    let mut vacated: HashSet<&str> = HashSet::new();
+    let mut drift = Vec::new();
+
+    for planned in operations {
+        let (source, target) = source_and_target(&planned.operation);
+        if let Some(source) = source {
+            // 由前面的操作产生的路径不需要事先存在
+            let produced = Path::new(source).ancestors()
+                .any(|ancestor| written.contains(ancestor.to_string_lossy().as_ref()));
+            if !produced && !base_path.join(source).exists() {
+                drift.push(format!("missing: {}", source));
+            }
+            match planned.kind {
+                OperationKind::Copy => {},
+                // 交换之后源路径上是另一个条目
+                OperationKind::Exchange => {
+                    written.insert(source);
+                },
+                _ => {
+                    vacated.insert(source);
+                },
+            }
+        }
+        if let Some(target) = target {
+            // 合并的目标本来就是已有的目录
+            let merges = planned.kind == OperationKind::Merge || planned.collision == Some(CollisionPolicy::Merge);
+            if !merges && planned.kind != OperationKind::Exchange && !vacated.contains(target) && base_path.join(target).exists() {
+                drift.push(format!("already exists: {}", target));
+            }
+            written.insert(target);
+        }
+    }
+
+    drift
+}
+
+/// Applies a saved plan after checking that the directory still matches
+/// the tree the plan was made from. The plan runs as one atomic, journaled
+/// transaction and is backed up and recorded in the history like any other batch.
+pub fn apply_plan_file_at(data_dir: &Path, base_path: &str, plan: &PlanFile) -> Result<TransactionReport, AppError> {
+    let base = Path::new(base_path);
+    if !base.is_dir() {
+        return Err(AppError::new(ErrorType::FileNotFound, format!("Path is not a directory: {}", base_path)).at(base_path));
+    }
+
+    let current = build_tree_with_options(base, plan.scan_options.clone())?;
+    let fingerprint = tree_fingerprint(&current);
+    if fingerprint != plan.fingerprint {
+        let drift = describe_drift(base, &plan.operations);
+        println!("Plan fingerprint mismatch: {} != {}", fingerprint, plan.fingerprint);
+        return Err(AppError::new(ErrorType::ExternalModification, format!(
+            "The directory has changed since the plan was saved (fingerprint {} does not match {}).{}",
+            fingerprint,
+            plan.fingerprint,
+            if drift.is_empty() { String::new() } else { format!("\n{}", drift.join("\n")) }
+        )).at(base_path));
+    }
+
+    let mut operations = Vec::with_capacity(plan.operations.len());
+    for (index, planned) in plan.operations.iter().enumerate() {
+        // 根目录改名只能是最后一个操作，目标是上级目录中的一个名称
+        if let FileOperation::Rename { from, to } = &planned.operation {
+            if let (true, Some(name)) = (from.is_empty(), to.strip_prefix("../")) {
+                if index + 1 != plan.operations.len() {
+                    return Err(AppError::new(ErrorType::InvalidInput, "Invalid plan file: the root directory must be renamed last").at(base_path));
+                }
+                validate_root_name(name)?;
+                let parent = base.parent()
+                    .ok_or_else(|| AppError::new(ErrorType::InvalidInput, format!("Cannot rename {}", base_path)).at(base_path))?;
+                operations.push(FileOperation::Rename {
+                    from: base_path.to_string(),
+                    to: parent.join(name).to_string_lossy().to_string(),
+                });
+                continue;
+            }
+        }
+        
+        let (source, target) = source_and_target(&planned.operation);
+        for path in source.into_iter().chain(target) {
+            to_absolute(base, path)?;
+        }
+        operations.push(planned.operation.map_paths(|path| to_absolute(base, path).unwrap_or_default()));
+    }
+
+    // 参与计划的条目（包括被移动目录中的内容）与重新扫描时的指纹比较
+    let entries = scanned_tree_entries(&current);
+    let sources: Vec<&str> = plan.operations.iter()
+        .filter(|planned| !matches!(planned.kind, OperationKind::CreateDir | OperationKind::CreateFile))
+        .filter_map(|planned| source_and_target(&planned.operation).0)
+        .collect();
+    let involved: HashSet<&str> = entries.iter()
+        .filter(|entry| {
+            let relative = entry.path.split_once('/').map_or("", |(_, rest)| rest);
+            sources.iter().any(|source| {
+                relative == *source || relative.strip_prefix(*source).is_some_and(|rest| rest.starts_with('/'))
+            })
+        })
+        .map(|entry| entry.id.as_str())
+        .collect();
+    let external_changes = find_external_changes(base, &entries, &involved);
+    if !external_changes.is_empty() {
+        let messages: Vec<String> = external_changes.iter().map(|c| c.message.clone()).collect();
+        println!("Refusing to apply plan file: {} entries changed since the scan", external_changes.len());
+        let first = to_absolute_path(base, &external_changes[0].path);
+        return Err(AppError::new(ErrorType::ExternalModification,
+            format!("The directory changed since it was scanned:\n{}", messages.join("\n"))).at(first));
+    }
+
+    apply_batch(data_dir, base, &operations, true)
+}
+
+#[command]
+pub fn save_plan(
+    #[allow(non_snake_case)] dirPath: String,
+    #[allow(non_snake_case)] originalTree: String,
+    #[allow(non_snake_case)] modifiedTree: String,
+    #[allow(non_snake_case)] planPath: String,
+    options: Option<PlanOptions>,
+    #[allow(non_snake_case)] scanOptions: Option<DirectoryOptions>
+) -> Result<PlanFile, AppError> {
+    println!("save_plan called with path: {}", planPath);
+    let plan = create_plan_file(
+        &dirPath,
+        &originalTree,
+        &modifiedTree,
+        options.unwrap_or_default(),
+        scanOptions.unwrap_or_default(),
+    )?;
+
+    let json = serde_json::to_string_pretty(&plan).map_err(|e| AppError::new(ErrorType::SystemError, e.to_string()).at(&planPath))?;
+    fs::write(&planPath, json).map_err(|e| AppError::io(&e, format!("Failed to write plan file {}: {}", planPath, e)).at(&planPath))?;
+    Ok(plan)
+}
+
+#[command]
+pub fn load_plan(#[allow(non_snake_case)] planPath: String) -> Result<PlanFile, AppError> {
+    read_plan_file(&planPath)
+}
+
+#[command]
+pub fn apply_plan_file(
+    #[allow(non_snake_case)] dirPath: String,
+    #[allow(non_snake_case)] planPath: String
+) -> Result<TransactionReport, AppError> {
+    println!("apply_plan_file called with plan: {}", planPath);
+    let plan = read_plan_file(&planPath)?;
+    apply_plan_file_at(&app_data_dir(), &dirPath, &plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::history::undo_last_batch_at;
+    use crate::commands::tree::build_tree;
+
+    #[test]
+    fn test_plan_file_round_trip_and_drift() {
+        let make_dir = |prefix: &str| {
+            let root = std::env::temp_dir().join(format!("{}_{}", prefix, uuid::Uuid::new_v4()));
+            fs::create_dir_all(root.join("photos")).unwrap();
+            fs::write(root.join("photos/img1.jpg"), "1").unwrap();
+            fs::write(root.join("photos/img2.jpg"), "2").unwrap();
+            root
+        };
+
+        let source = make_dir("treenamer_plan_source");
+        let original = build_tree(&source).unwrap();
+        let mut modified = original.clone();
+        modified.children[0].name = "2024 photos".to_string();
+        modified.children[0].children[0].name = "img2.jpg".to_string();
+        modified.children[0].children[1].name = "img1.jpg".to_string();
+
+        let plan_path = std::env::temp_dir().join(format!("treenamer_plan_{}.json", uuid::Uuid::new_v4()));
+        let saved = save_plan(
+            source.to_string_lossy().to_string(),
+            serde_json::to_string(&original).unwrap(),
+            serde_json::to_string(&modified).unwrap(),
+            plan_path.to_string_lossy().to_string(),
+            None,
+            None,
+        ).unwrap();
+        assert_eq!(saved.version, PLAN_FILE_VERSION);
+
+        // 计划文件中只有相对路径
+        let content = fs::read_to_string(&plan_path).unwrap();
+        assert!(!content.contains(source.to_string_lossy().as_ref()), "{}", content);
+        let loaded = load_plan(plan_path.to_string_lossy().to_string()).unwrap();
+        assert_eq!(loaded.hash, saved.hash);
+
+        // 在另一台机器上结构相同的目录中执行
+        let other = make_dir("treenamer_plan_other");
+        let data = other.with_extension("data");
+        let report = apply_plan_file_at(&data, &other.to_string_lossy(), &loaded).unwrap();
+        assert!(report.committed && report.results.iter().all(|r| r.success));
+        assert_eq!(fs::read_to_string(other.join("2024 photos/img1.jpg")).unwrap(), "2");
+        assert_eq!(fs::read_to_string(other.join("2024 photos/img2.jpg")).unwrap(), "1");
+
+        // 计划和其他批次一样记录在历史中，可以撤销
+        let undone = undo_last_batch_at(&data).unwrap();
+        assert!(undone.report.as_ref().is_some_and(|r| r.committed), "{:?}", undone);
+        assert_eq!(fs::read_to_string(other.join("photos/img1.jpg")).unwrap(), "1");
+
+        // 目录结构发生变化后拒绝执行，并报告差异
+        fs::remove_file(source.join("photos/img1.jpg")).unwrap();
+        let error = apply_plan_file_at(&data, &source.to_string_lossy(), &loaded).unwrap_err();
+        assert!(error.message.contains("changed") && error.message.contains("missing: photos/img1.jpg"), "{}", error);
+        assert_eq!(error.error_type, ErrorType::ExternalModification);
+        assert!(source.join("photos/img2.jpg").exists());
+
+        // 被修改过的计划文件无法加载
+        fs::write(&plan_path, content.replace("2024 photos", "2025 photos")).unwrap();
+        assert!(load_plan(plan_path.to_string_lossy().to_string()).is_err());
+
+        let _ = fs::remove_file(&plan_path);
+        let _ = fs::remove_dir_all(&source);
+        let _ = fs::remove_dir_all(&other);
+        let _ = fs::remove_dir_all(&data);
+    }
+
+    #[test]
+    fn test_plan_file_paths_cannot_escape_root() {
+        let base = Path::new("/tmp/root");
+        assert!(to_absolute(base, "../etc/passwd").is_err());
+        assert!(to_absolute(base, "/etc/passwd").is_err());
+        assert!(to_absolute(base, "").is_err());
+        assert_eq!(to_absolute(base, "a/b.txt").unwrap(), "/tmp/root/a/b.txt");
+    }
+}
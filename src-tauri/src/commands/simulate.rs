@@ -0,0 +1,579 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ffi::OsString;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use uuid::Uuid;
+use crate::commands::fs::{parse_tree_entries, ApplyReport, BatchSummary, FileOperation, MergeStep, OperationResult};
+use crate::commands::anchor::Anchor;
+use crate::commands::transaction::open_anchor;
+use crate::commands::tree::TreeNode;
+use crate::commands::volume::is_fold_only_rename;
+use crate::error::{AppError, ErrorType};
+
+// 模拟文件系统中的一个条目
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    /// Backed by this entry on disk (also after being renamed or copied in the model)
+    Real(PathBuf),
+    Dir,
+    File,
+    Absent,
+    /// On disk, but `Anchor::locate` refuses the way there (a symbolic link among the
+    /// directories, or a file where a directory is expected)
+    Unreachable(ErrorType, String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Dir,
+    File,
+}
+
+/// An in-memory filesystem layered over the disk. Paths that no operation touched
+/// read through to the disk by way of the same `Anchor` the real batch would use, so
+/// symbolic links are never followed; everything the operations do is kept in
+/// memory, so the disk is never changed.
+///
+/// Each operation goes through the same checks as `apply_operation`, with the
+/// semantics of `rename(2)` on Unix: a file replaces an existing file, a directory
+/// replaces an empty directory, and a directory moved onto a non-empty directory is
+/// merged into it.
+pub struct SimulatedFs {
+    anchor: Anchor,
+    // 虚拟路径 -> 条目，覆盖该路径及其下所有路径在磁盘上的状态
+    nodes: BTreeMap<PathBuf, Node>,
+}
+
+fn success(message: String) -> OperationResult {
+    OperationResult::ok(message)
+}
+
+fn failure(error_type: ErrorType, message: String, path: &Path) -> OperationResult {
+    OperationResult::failed(AppError::new(error_type, message).at(path))
+}
+
+impl SimulatedFs {
+    pub(crate) fn new(anchor: Anchor) -> Self {
+        Self { anchor, nodes: BTreeMap::new() }
+    }
+
+    // 与 apply_operation 一样通过 anchor 读取磁盘：缺少的条目不存在，无法到达的条目算作存在
+    fn real_node(&self, path: PathBuf) -> Node {
+        match self.anchor.metadata(&path) {
+            Ok(_) => Node::Real(path),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Node::Absent,
+            Err(e) => Node::Unreachable(ErrorType::of(&e), e.to_string()),
+        }
+    }
+
+    fn resolve(&self, path: &Path) -> Node {
+        for ancestor in path.ancestors() {
+            if let Some(node) = self.nodes.get(ancestor) {
+                let rest = path.strip_prefix(ancestor).unwrap_or(Path::new(""));
+                return match node {
+                    _ if rest.as_os_str().is_empty() => node.clone(),
+                    Node::Real(real) => self.real_node(real.join(rest)),
+                    Node::Unreachable(..) => node.clone(),
+                    _ => Node::Absent,
+                };
+            }
+        }
+        self.real_node(path.to_path_buf())
+    }
+
+    // 与 Anchor::is_dir 一样不跟随符号链接：指向目录的链接也是文件
+    fn kind(&self, path: &Path) -> Option<Kind> {
+        match self.resolve(path) {
+            Node::Real(real) => self.anchor.is_dir(&real).ok().map(|is_dir| if is_dir { Kind::Dir } else { Kind::File }),
+            Node::Dir => Some(Kind::Dir),
+            Node::File => Some(Kind::File),
+            Node::Absent | Node::Unreachable(..) => None,
+        }
+    }
+
+    // 与 Anchor::exists 一样：悬空的链接和无法到达的条目也算存在
+    fn present(&self, path: &Path) -> bool {
+        self.resolve(path) != Node::Absent
+    }
+
+    fn overrides_below<'a>(&'a self, path: &'a Path) -> impl Iterator<Item = (&'a PathBuf, &'a Node)> + 'a {
+        self.nodes.range::<Path, _>((Bound::Excluded(path), Bound::Unbounded))
+            .take_while(move |(key, _)| key.starts_with(path))
+    }
+
+    fn children(&self, path: &Path) -> BTreeSet<OsString> {
+        let mut names = BTreeSet::new();
+        if let Node::Real(real) = self.resolve(path) {
+            if let Ok(entries) = self.anchor.read_dir(&real) {
+                names.extend(entries);
+            }
+        }
+        for (key, node) in self.overrides_below(path) {
+            if key.parent() != Some(path) {
+                continue;
+            }
+            let Some(name) = key.file_name() else { continue };
+            if *node == Node::Absent {
+                names.remove(name);
+            } else {
+                names.insert(name.to_os_string());
+            }
+        }
+        names
+    }
+
+    // 设置一个路径，它下面原有的覆盖记录失效
+    fn set(&mut self, path: &Path, node: Node) {
+        let below: Vec<PathBuf> = self.overrides_below(path).map(|(key, _)| key.clone()).collect();
+        for key in below {
+            self.nodes.remove(&key);
+        }
+        self.nodes.insert(path.to_path_buf(), node);
+    }
+
+    // 把 from 下的条目（包括模拟中的修改）放到 to，keep_source 为 false 时移除 from
+    fn transfer(&mut self, from: &Path, to: &Path, keep_source: bool) {
+        let node = self.resolve(from);
+        let below: Vec<(PathBuf, Node)> = self.overrides_below(from)
+            .filter_map(|(key, node)| key.strip_prefix(from).ok().map(|rest| (to.join(rest), node.clone())))
+            .collect();
+        if !keep_source {
+            self.set(from, Node::Absent);
+        }
+        self.set(to, node);
+        self.nodes.extend(below);
+    }
+
+    fn create_dir_all(&mut self, path: &Path) -> Result<(), String> {
+        let missing: Vec<PathBuf> = path.ancestors()
+            .take_while(|dir| self.kind(dir) != Some(Kind::Dir))
+            .map(Path::to_path_buf)
+            .collect();
+        for dir in missing.iter().rev() {
+            if self.present(dir) {
+                return Err(format!("{} is not a directory", dir.display()));
+            }
+            self.set(dir, Node::Dir);
+        }
+        Ok(())
+    }
+
+    fn ensure_parent_dir(&mut self, path: &Path) -> Result<(), AppError> {
+        match path.parent() {
+            Some(parent) if !self.present(parent) => self.create_dir_all(parent)
+                .map_err(|e| AppError::new(ErrorType::SystemError, format!("Failed to create parent directory: {}", e)).at(parent)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs one operation against the model and reports what the real one would.
+    pub fn apply(&mut self, operation: &FileOperation) -> OperationResult {
+        let started = Instant::now();
+        let paths = match operation {
+            FileOperation::Rename { from, to } | FileOperation::Move { from, to } | FileOperation::Copy { from, to }
+            | FileOperation::Merge { from, to } | FileOperation::Exchange { from, to } => vec![from, to],
+            FileOperation::CreateDir { path } | FileOperation::CreateFile { path } | FileOperation::Delete { path } => vec![path],
+        };
+        // 与 apply_operation 一样在解析路径时失败
+        for path in paths {
+            if let Node::Unreachable(error_type, reason) = self.resolve(Path::new(path)) {
+                return failure(error_type, format!("Cannot reach {}: {}", path, reason), Path::new(path))
+                    .describe(operation, started.elapsed());
+            }
+        }
+        let result = match operation {
+            FileOperation::Rename { from, to } | FileOperation::Move { from, to } => self.rename(from, to),
+            FileOperation::CreateDir { path } => self.create_dir(path),
+            FileOperation::CreateFile { path } => self.create_file(path),
+            FileOperation::Delete { path } => self.delete(path),
+            FileOperation::Copy { from, to } => self.copy(from, to),
+            FileOperation::Merge { from, to } => self.merge(from, to),
+            FileOperation::Exchange { from, to } => self.exchange(from, to),
+        };
+        result.describe(operation, started.elapsed())
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> OperationResult {
+        let (from_path, to_path) = (Path::new(from), Path::new(to));
+        if let Err(error) = self.ensure_parent_dir(to_path) {
+            return OperationResult::failed(error);
+        }
+        let Some(source_kind) = self.kind(from_path) else {
+            return failure(ErrorType::FileNotFound, format!("Source path does not exist: {}", from), from_path);
+        };
+        if from_path == to_path || self.is_fold_only(from_path, to_path) {
+            self.transfer(from_path, to_path, false);
+            return success(format!("Renamed {} to {}", from, to));
+        }
+
+        let target_kind = self.kind(to_path);
+        let fail = |reason: &str| failure(ErrorType::SystemError, format!("Failed to rename {} to {}: {}", from, to, reason), from_path);
+        if to_path.parent().is_some_and(|parent| self.kind(parent) != Some(Kind::Dir)) {
+            return fail("Not a directory");
+        }
+        match (source_kind, target_kind) {
+            (Kind::Dir, _) if to_path.starts_with(from_path) => fail("Invalid argument"),
+            // 与 apply_rename 一样不覆盖已有的目标
+            (_, Some(_)) => failure(ErrorType::PathAlreadyExists, format!("Cannot rename {} to {}: the target already exists", from, to), to_path),
+            _ => {
+                self.transfer(from_path, to_path, false);
+                success(format!("Renamed {} to {}", from, to))
+            },
+        }
+    }
+
+    // 与 merge_steps 相同：缺少的条目移进去，两边都是目录时递归，其他同名条目是冲突
+    fn merge_steps(&self, from: &Path, to: &Path, steps: &mut Vec<MergeStep>) -> Result<(), String> {
+        for name in self.children(from) {
+            let (source, target) = (from.join(&name), to.join(&name));
+            match (self.kind(&source), self.kind(&target)) {
+                (_, None) => steps.push(MergeStep::Move { from: source, to: target }),
+                (Some(Kind::Dir), Some(Kind::Dir)) => self.merge_steps(&source, &target, steps)?,
+                _ => return Err(format!("{} already exists", target.display())),
+            }
+        }
+        steps.push(MergeStep::RemoveDir(from.to_path_buf()));
+        Ok(())
+    }
+
+    fn merge(&mut self, from: &str, to: &str) -> OperationResult {
+        let (from_path, to_path) = (Path::new(from), Path::new(to));
+        if self.kind(from_path) != Some(Kind::Dir) || self.kind(to_path) != Some(Kind::Dir) {
+            return failure(ErrorType::SystemError, format!("Cannot merge {} into {}: both must be directories", from, to), from_path);
+        }
+        let mut steps = Vec::new();
+        if let Err(e) = self.merge_steps(from_path, to_path, &mut steps) {
+            return failure(ErrorType::PathAlreadyExists, format!("Cannot merge {} into {}: {}", from, to, e), from_path);
+        }
+        for step in steps {
+            match step {
+                MergeStep::Move { from, to } => self.transfer(&from, &to, false),
+                MergeStep::RemoveDir(dir) => self.set(&dir, Node::Absent),
+            }
+        }
+        success(format!("Merged {} into {}", from, to))
+    }
+
+    fn exchange(&mut self, from: &str, to: &str) -> OperationResult {
+        let (from_path, to_path) = (Path::new(from), Path::new(to));
+        if !self.present(from_path) || !self.present(to_path) {
+            let missing = if self.present(from_path) { to_path } else { from_path };
+            return failure(ErrorType::FileNotFound, format!("Cannot exchange {} and {}: both must exist", from, to), missing);
+        }
+        if from_path.starts_with(to_path) || to_path.starts_with(from_path) {
+            return failure(ErrorType::SystemError, format!("Failed to exchange {} and {}: Invalid argument", from, to), from_path);
+        }
+        // 经过模型中的临时位置交换，两边的子条目一起移动
+        let temp = from_path.with_file_name(format!(".treenamer-tmp-{}", Uuid::new_v4().simple()));
+        self.transfer(from_path, &temp, false);
+        self.transfer(to_path, from_path, false);
+        self.transfer(&temp, to_path, false);
+        success(format!("Exchanged {} and {}", from, to))
+    }
+
+    // 只有大小写或规范化形式不同：与 apply_rename 使用同一个判断，包括卷的命名规则
+    fn is_fold_only(&self, from: &Path, to: &Path) -> bool {
+        match (self.resolve(from), self.resolve(to)) {
+            (Node::Real(a), Node::Real(b)) => is_fold_only_rename(&self.anchor, &a, &b),
+            _ => false,
+        }
+    }
+
+    // 与 copy_tree 相同：已有的目录合并，已有的文件覆盖
+    fn copy_into(&mut self, from: &Path, to: &Path) -> Result<(), String> {
+        if !self.present(to) {
+            self.create_dir_all(to)?;
+        }
+        for name in self.children(from) {
+            let (source, target) = (from.join(&name), to.join(&name));
+            if self.kind(&source) == Some(Kind::Dir) {
+                self.copy_into(&source, &target)?;
+            } else if self.kind(&target) == Some(Kind::Dir) {
+                return Err(format!("{} is a directory", target.display()));
+            } else {
+                self.transfer(&source, &target, true);
+            }
+        }
+        Ok(())
+    }
+
+    fn create_dir(&mut self, path: &str) -> OperationResult {
+        if self.kind(Path::new(path)) == Some(Kind::Dir) {
+            return OperationResult::skipped(format!("Directory already exists: {}", path));
+        }
+        match self.create_dir_all(Path::new(path)) {
+            Ok(_) => success(format!("Created directory {}", path)),
+            Err(e) => failure(ErrorType::SystemError, format!("Failed to create directory {}: {}", path, e), Path::new(path)),
+        }
+    }
+
+    fn create_file(&mut self, path: &str) -> OperationResult {
+        let file_path = Path::new(path);
+        if let Err(error) = self.ensure_parent_dir(file_path) {
+            return OperationResult::failed(error);
+        }
+        if self.present(file_path) {
+            return failure(ErrorType::PathAlreadyExists, format!("Failed to create file {}: File exists", path), file_path);
+        }
+        if file_path.parent().is_some_and(|parent| self.kind(parent) != Some(Kind::Dir)) {
+            return failure(ErrorType::SystemError, format!("Failed to create file {}: Not a directory", path), file_path);
+        }
+        self.set(file_path, Node::File);
+        success(format!("Created file {}", path))
+    }
+
+    fn delete(&mut self, path: &str) -> OperationResult {
+        if !self.present(Path::new(path)) {
+            return failure(ErrorType::FileNotFound, format!("Failed to delete {}: No such file or directory", path), Path::new(path));
+        }
+        self.set(Path::new(path), Node::Absent);
+        success(format!("Deleted {}", path))
+    }
+
+    fn copy(&mut self, from: &str, to: &str) -> OperationResult {
+        let (from_path, to_path) = (Path::new(from), Path::new(to));
+        if self.kind(to_path).is_some() {
+            return failure(ErrorType::PathAlreadyExists, format!("Copy target already exists: {}", to), to_path);
+        }
+        if let Err(error) = self.ensure_parent_dir(to_path) {
+            return OperationResult::failed(error);
+        }
+        let result = match self.kind(from_path) {
+            Some(Kind::Dir) => self.copy_into(from_path, to_path),
+            Some(Kind::File) => {
+                self.transfer(from_path, to_path, true);
+                Ok(())
+            },
+            None => Err("No such file or directory".to_string()),
+        };
+        match result {
+            Ok(_) => success(format!("Copied {} to {}", from, to)),
+            Err(e) => {
+                let error_type = if self.present(from_path) { ErrorType::SystemError } else { ErrorType::FileNotFound };
+                failure(error_type, format!("Failed to copy {} to {}: {}", from, to, e), from_path)
+            },
+        }
+    }
+
+    /// The simulated tree under `root`. Entries listed in `ids` keep their ids and,
+    /// when they are directories, are expanded; other directories are left collapsed.
+    pub fn tree(&self, root: &Path, ids: &HashMap<PathBuf, String>) -> TreeNode {
+        let name = root.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        self.tree_node(root, name, ids)
+    }
+
+    fn tree_node(&self, path: &Path, name: String, ids: &HashMap<PathBuf, String>) -> TreeNode {
+        let is_dir = self.kind(path) == Some(Kind::Dir);
+        let mut children: Vec<TreeNode> = if is_dir && ids.contains_key(path) {
+            self.children(path).into_iter()
+                .map(|child| self.tree_node(&path.join(&child), child.to_string_lossy().to_string(), ids))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        // 与扫描结果相同：目录在前，同类按名称排序
+        children.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+        TreeNode {
+            id: ids.get(path).cloned().unwrap_or_else(|| Uuid::new_v4().to_string()),
+            name,
+            is_dir,
+            children,
+            metadata: None,
+            fingerprint: None,
+        }
+    }
+
+    /// Lists the expected entries that the simulation did not produce.
+    pub fn mismatches(&self, expected: &[(PathBuf, bool)]) -> Vec<String> {
+        expected.iter().filter_map(|(path, is_dir)| match self.kind(path) {
+            None => Some(format!("missing: {}", path.display())),
+            Some(Kind::Dir) if !is_dir => Some(format!("expected a file, found a directory: {}", path.display())),
+            Some(Kind::File) if *is_dir => Some(format!("expected a directory, found a file: {}", path.display())),
+            _ => None,
+        }).collect()
+    }
+}
+
+/// Runs the operations against a `SimulatedFs` over `dir_path` and reports the
+/// per-operation results, the resulting tree and where it differs from the modified tree.
+pub(crate) fn dry_run(
+    dir_path: &str,
+    original_tree: &str,
+    modified_tree: &str,
+    operations: &[FileOperation],
+) -> Result<ApplyReport, AppError> {
+    let base = Path::new(dir_path);
+    let (original_entries, modified_entries) = parse_tree_entries(base, original_tree, modified_tree)?;
+    
+    // 根目录改名后，结果位于上级目录中的新名称下
+    let root = match (original_entries.first(), modified_entries.first(), base.parent()) {
+        (Some(orig_root), Some(new_root), Some(parent)) if orig_root.name != new_root.name => parent.join(&new_root.name),
+        _ => base.to_path_buf(),
+    };
+    let expected: Vec<(PathBuf, String, bool)> = modified_entries.iter()
+        .map(|entry| {
+            let path = entry.path.split('/').skip(1).fold(root.clone(), |path, component| path.join(component));
+            (path, entry.id.clone(), entry.is_dir)
+        })
+        .collect();
+    
+    let mut simulated = SimulatedFs::new(open_anchor(base, operations)?);
+    let mut results = Vec::new();
+    println!("Simulating operations:");
+    for (i, op) in operations.iter().enumerate() {
+        let result = simulated.apply(op);
+        println!("  {}. {} -> {}", i + 1, op, result.message);
+        results.push(result);
+    }
+    
+    let ids: HashMap<PathBuf, String> = expected.iter().map(|(path, id, _)| (path.clone(), id.clone())).collect();
+    let kinds: Vec<(PathBuf, bool)> = expected.iter().map(|(path, _, is_dir)| (path.clone(), *is_dir)).collect();
+    Ok(ApplyReport {
+        dry_run: true,
+        summary: BatchSummary::of(&results),
+        results,
+        tree: Some(simulated.tree(&root, &ids)),
+        mismatches: simulated.mismatches(&kinds),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::test_support::{path, TempDir};
+    use std::fs;
+    use crate::commands::fs::apply_operations;
+
+    fn json_node(id: &str, name: &str, is_dir: bool, children: Vec<serde_json::Value>) -> serde_json::Value {
+        serde_json::json!({ "id": id, "name": name, "is_dir": is_dir, "children": children })
+    }
+
+    fn names(node: &TreeNode) -> Vec<String> {
+        node.children.iter().map(|child| child.name.clone()).collect()
+    }
+
+    #[test]
+    fn test_dry_run_produces_the_modified_tree_without_touching_the_disk() {
+        let root = TempDir::new("simulate");
+        let root_name = root.name();
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("b.txt"), "b").unwrap();
+        fs::write(root.join("docs/c.txt"), "c").unwrap();
+
+        let original = json_node("root", &root_name, true, vec![
+            json_node("docs", "docs", true, vec![json_node("c", "c.txt", false, vec![])]),
+            json_node("a", "a.txt", false, vec![]),
+            json_node("b", "b.txt", false, vec![]),
+        ]).to_string();
+        // 交换两个文件名，把目录移到新目录下并改名
+        let modified = json_node("root", &root_name, true, vec![
+            json_node("new", "archive", true, vec![
+                json_node("docs", "papers", true, vec![json_node("c", "c.txt", false, vec![])]),
+            ]),
+            json_node("b", "a.txt", false, vec![]),
+            json_node("a", "b.txt", false, vec![]),
+        ]).to_string();
+        let base = root.to_string_lossy().to_string();
+
+        let report = apply_operations(base.clone(), original, modified, None, Some(true)).unwrap();
+        assert!(report.dry_run);
+        assert!(report.results.iter().all(|r| r.success), "{:?}", report.results);
+        assert!(report.mismatches.is_empty(), "{:?}", report.mismatches);
+
+        let tree = report.tree.unwrap();
+        assert_eq!(tree.id, "root");
+        assert_eq!(names(&tree), vec!["archive", "a.txt", "b.txt"]);
+        let archive = &tree.children[0];
+        assert_eq!((archive.id.as_str(), archive.is_dir), ("new", true));
+        assert_eq!(names(archive), vec!["papers"]);
+        assert_eq!(archive.children[0].id, "docs");
+        assert_eq!(archive.children[0].children[0].id, "c");
+        assert_eq!((tree.children[1].id.as_str(), tree.children[2].id.as_str()), ("b", "a"));
+
+        // 磁盘上没有任何变化
+        assert_eq!(fs::read_to_string(root.join("a.txt")).unwrap(), "a");
+        assert_eq!(fs::read_to_string(root.join("b.txt")).unwrap(), "b");
+        assert!(root.join("docs/c.txt").exists());
+        assert!(!root.join("archive").exists());
+        assert!(fs::read_dir(&root).unwrap().all(|entry| !entry.unwrap().file_name().to_string_lossy().starts_with(".treenamer")));
+    }
+
+    #[test]
+    fn test_simulation_applies_the_same_checks_as_the_disk() {
+        let root = TempDir::new("simulate");
+        fs::create_dir_all(root.join("sub/inner")).unwrap();
+        fs::create_dir_all(root.join("empty")).unwrap();
+        fs::create_dir_all(root.join("full")).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("sub/inner/b.txt"), "b").unwrap();
+        fs::write(root.join("full/d.txt"), "d").unwrap();
+
+        let mut simulated = SimulatedFs::new(Anchor::open(&root).unwrap());
+        let mut run = |op: FileOperation| simulated.apply(&op);
+
+        // 顺序错误：源在之前的操作中已经被删除
+        assert!(run(FileOperation::Delete { path: path(&root, "a.txt") }).success);
+        let result = run(FileOperation::Rename { from: path(&root, "a.txt"), to: path(&root, "z.txt") });
+        assert!(!result.success);
+        assert!(result.message.contains("Source path does not exist"), "{}", result.message);
+        assert!(!run(FileOperation::Delete { path: path(&root, "a.txt") }).success);
+
+        // 移动会创建缺少的父目录，目录内容随目录一起移动
+        assert!(run(FileOperation::Move { from: path(&root, "sub"), to: path(&root, "x/y/sub") }).success);
+        assert!(run(FileOperation::CreateFile { path: path(&root, "x/y/sub/inner/new.txt") }).success);
+        assert!(!run(FileOperation::CreateFile { path: path(&root, "x/y/sub/inner/b.txt") }).success);
+        assert!(run(FileOperation::Rename { from: path(&root, "x/y/sub/inner"), to: path(&root, "x/moved") }).success);
+        assert!(!run(FileOperation::Move { from: path(&root, "x"), to: path(&root, "x/y/x") }).success);
+
+        // 已有的目标不会被替换；合并把内容移进已有的目录，两边都有的文件是冲突
+        assert!(!run(FileOperation::Rename { from: path(&root, "x/moved"), to: path(&root, "empty") }).success);
+        assert!(run(FileOperation::Merge { from: path(&root, "x/moved"), to: path(&root, "empty") }).success);
+        assert!(!run(FileOperation::Copy { from: path(&root, "full"), to: path(&root, "empty") }).success);
+        assert!(run(FileOperation::Copy { from: path(&root, "full"), to: path(&root, "copy") }).success);
+        assert!(!run(FileOperation::Rename { from: path(&root, "empty"), to: path(&root, "full") }).success);
+        let result = run(FileOperation::Merge { from: path(&root, "empty"), to: path(&root, "full") });
+        assert!(result.message.starts_with("Merged"), "{}", result.message);
+        assert!(!run(FileOperation::Merge { from: path(&root, "copy"), to: path(&root, "full") }).success);
+        assert!(!run(FileOperation::Rename { from: path(&root, "copy/d.txt"), to: path(&root, "full") }).success);
+
+        let ids: HashMap<PathBuf, String> = ["", "full", "x", "copy"].iter()
+            .map(|relative| (root.join(relative).components().collect(), relative.to_string()))
+            .collect();
+        let tree = simulated.tree(&root, &ids);
+        assert_eq!(names(&tree), vec!["copy", "full", "x"]);
+        assert_eq!(names(&tree.children[0]), vec!["d.txt"]);
+        assert_eq!(names(&tree.children[1]), vec!["b.txt", "d.txt", "new.txt"]);
+        assert_eq!(names(&tree.children[2]), vec!["y"]);
+        assert!(simulated.mismatches(&[(root.join("full/new.txt"), false), (root.join("sub"), true)])
+            == vec![format!("missing: {}", root.join("sub").display())]);
+
+        // 磁盘上没有任何变化
+        assert!(root.join("a.txt").exists() && root.join("sub/inner/b.txt").exists());
+        assert!(!root.join("x").exists() && !root.join("copy").exists());
+        assert_eq!(fs::read_dir(root.join("empty")).unwrap().count(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_simulation_does_not_follow_symbolic_links() {
+        let root = TempDir::new("simulate");
+        let outside = TempDir::new("simulate_outside");
+        fs::write(outside.join("secret.txt"), "secret").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("link")).unwrap();
+
+        let mut simulated = SimulatedFs::new(Anchor::open(&root).unwrap());
+        let mut run = |op: FileOperation| simulated.apply(&op);
+
+        // 与 Anchor::locate 一样，经过链接的路径无法到达
+        let result = run(FileOperation::Rename { from: path(&root, "link/secret.txt"), to: path(&root, "secret.txt") });
+        assert!(!result.success);
+        assert_eq!(result.error.unwrap().error_type, ErrorType::PermissionDenied);
+        // 链接本身是一个文件，可以改名，但不能在它下面创建条目
+        assert!(run(FileOperation::Rename { from: path(&root, "link"), to: path(&root, "renamed") }).success);
+        assert!(!run(FileOperation::CreateFile { path: path(&root, "renamed/new.txt") }).success);
+        assert!(simulated.mismatches(&[(root.join("renamed"), false)]).is_empty());
+
+    }
+}
@@ -1,6 +1,6 @@
 use crate::commands::fs::*;
 use serde::{Serialize, Deserialize};
-use std::error::Error;
+use crate::error::AppError;
 use crate::commands::fs::generate_operations_from_json;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -11,7 +11,7 @@ pub struct TestTreeNode {
     children: Vec<TestTreeNode>,
 }
 
-pub fn test_generate_operations() -> Result<(), Box<dyn Error>> {
+pub fn test_generate_operations() -> Result<(), AppError> {
     println!("Running test_generate_operations");
     
     // 创建测试数据 - 原始树
@@ -184,12 +184,12 @@ pub fn test_generate_operations() -> Result<(), Box<dyn Error>> {
                     subdir_rename = true;
                     println!("Found subdir rename: {} -> {}", from, to);
                 }
-                // Check for new_dir creation within a rename operation
-                if to_str.contains("new_dir") {
-                    new_dir_create = true;
-                    println!("Found new directory creation in rename: {} -> {}", from, to);
-                }
             }
+            FileOperation::CreateDir { path } if path.contains("new_dir") => {
+                new_dir_create = true;
+                println!("Found new directory creation: {}", path);
+            }
+            _ => {}
         }
     }
     
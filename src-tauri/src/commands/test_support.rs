@@ -0,0 +1,54 @@
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// A fresh directory `treenamer_<prefix>_<uuid>` under the system temporary directory.
+/// It is removed when dropped, so a test that panics on an assertion still cleans up.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub(crate) fn new(prefix: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("treenamer_{}_{}", prefix, uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    /// The directory's own name, for trees rooted at it.
+    pub(crate) fn name(&self) -> String {
+        self.0.file_name().unwrap().to_string_lossy().to_string()
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// `relative` under `root`, as the string operations take.
+pub(crate) fn path(root: &Path, relative: &str) -> String {
+    root.join(relative).to_string_lossy().to_string()
+}
+
+/// The sorted names of the entries in `dir`.
+pub(crate) fn names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir).unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    names
+}
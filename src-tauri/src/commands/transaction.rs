@@ -0,0 +1,715 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tauri::command;
+use serde::{Serialize, Deserialize};
+use crate::commands::copy_tree::CopySettings;
+use crate::commands::fs::{apply_operation_with_settings, attach_node_ids, merge_steps, plan_for_apply, BatchSummary, FileOperation, MergeStep, OperationResult, PlanOptions};
+use crate::commands::volume::is_fold_only_rename;
+use crate::commands::anchor::Anchor;
+use crate::commands::journal::{app_data_dir, start_journal, Journal, JournalRecord};
+use crate::commands::backup::backup_before_batch;
+use crate::commands::history::record_applied_batch;
+use crate::error::{AppError, ErrorType};
+
+/// How to reverse one applied step.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum UndoAction {
+    /// Rename the entry back
+    Rename { from: String, to: String },
+    /// Recreate a directory that a merge emptied and removed
+    RecreateDir { path: String },
+    /// Remove a directory that the transaction created (only while it is empty)
+    RemoveDir { path: String },
+    /// Remove a file that the transaction created
+    RemoveFile { path: String },
+    /// Remove a copy made by the transaction
+    RemoveCopy { path: String },
+    /// Move a deleted entry back from where it was set aside
+    Restore { staged: String, path: String },
+    /// Exchange the two entries again; `identity` is that of the entry at `from`
+    /// before the exchange, which tells whether an interrupted exchange happened
+    Exchange { from: String, to: String, identity: Option<String> },
+}
+
+impl fmt::Display for UndoAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UndoAction::Rename { from, to } => write!(f, "Rename {} back to {}", from, to),
+            UndoAction::RecreateDir { path } => write!(f, "Recreate directory {}", path),
+            UndoAction::RemoveDir { path } => write!(f, "Remove created directory {}", path),
+            UndoAction::RemoveFile { path } => write!(f, "Remove created file {}", path),
+            UndoAction::RemoveCopy { path } => write!(f, "Remove copy {}", path),
+            UndoAction::Restore { staged, path } => write!(f, "Restore {} from {}", path, staged),
+            UndoAction::Exchange { from, to, .. } => write!(f, "Exchange {} and {} back", from, to),
+        }
+    }
+}
+
+/// The outcome of reversing one step during a rollback.
+#[derive(Debug, Serialize, Clone)]
+pub struct RollbackStep {
+    /// Index of the operation this step reverses
+    pub index: usize,
+    pub action: UndoAction,
+    pub success: bool,
+    pub message: String,
+    pub error: Option<AppError>,
+}
+
+/// The outcome of an all-or-nothing apply.
+#[derive(Debug, Serialize)]
+pub struct TransactionReport {
+    /// Whether every operation succeeded and the changes were kept
+    pub committed: bool,
+    /// Results of the operations that were attempted, in order
+    pub results: Vec<OperationResult>,
+    pub summary: BatchSummary,
+    /// Index of the operation whose failure triggered the rollback
+    pub failed_at: Option<usize>,
+    /// Every undo step that was attempted, in the order it ran
+    pub rollback: Vec<RollbackStep>,
+    /// Whether the directory is back in its original state after a failure
+    pub rolled_back: bool,
+    /// Problems while removing entries set aside for deletion after a commit
+    pub cleanup_errors: Vec<String>,
+    /// Whether the batch was cancelled between two operations; it stays in the
+    /// journal as an interrupted batch to be resumed or rolled back
+    pub cancelled: bool,
+    /// How to reverse the committed operations, in the order they were applied
+    #[serde(skip)]
+    pub undo: Vec<UndoAction>,
+}
+
+/// Applies operations one by one while recording how to reverse each of them.
+///
+/// Deletions only set the entry aside under a hidden name next to it; the data is
+/// removed for good when the transaction is committed. With a journal, the intent to
+/// run each operation is written to disk before it runs and its completion after it.
+/// Every step, including the undo steps, goes through the anchor of the batch
+/// directory and never leaves it.
+pub struct Transaction {
+    anchor: Anchor,
+    undo_log: Vec<(usize, UndoAction)>,
+    staged: Vec<String>,
+    journal: Option<Journal>,
+    // 跨设备移动的校验方式；有日志时使用批次开始时读取的设置
+    copy_settings: CopySettings,
+}
+
+impl Transaction {
+    pub(crate) fn new(anchor: Anchor) -> Self {
+        Self { anchor, undo_log: Vec::new(), staged: Vec::new(), journal: None, copy_settings: CopySettings::default() }
+    }
+
+    pub(crate) fn with_journal(anchor: Anchor, journal: Journal) -> Self {
+        Self { copy_settings: journal.copy_settings().clone(), journal: Some(journal), ..Self::new(anchor) }
+    }
+
+    // 从日志中恢复的事务
+    pub(crate) fn recovered(anchor: Anchor, undo_log: Vec<(usize, UndoAction)>, journal: Journal) -> Self {
+        let staged = undo_log.iter()
+            .filter_map(|(_, action)| match action {
+                UndoAction::Restore { staged, .. } => Some(staged.clone()),
+                _ => None,
+            })
+            .collect();
+        Self { anchor, undo_log, staged, copy_settings: journal.copy_settings().clone(), journal: Some(journal) }
+    }
+
+    /// Applies one operation and records how to reverse it. A failed operation only
+    /// leaves behind what it did on the way (such as created parent directories),
+    /// which is recorded too.
+    pub fn apply(&mut self, index: usize, operation: &FileOperation) -> OperationResult {
+        let started = Instant::now();
+        self.apply_recorded(index, operation).describe(operation, started.elapsed())
+    }
+
+    fn apply_recorded(&mut self, index: usize, operation: &FileOperation) -> OperationResult {
+        let undo = match prepare(&self.anchor, operation) {
+            Ok(undo) => undo,
+            Err(error) => return failure(error),
+        };
+        if let Some(journal) = &mut self.journal {
+            if let Err(e) = journal.record(&JournalRecord::Intent { index, undo: undo.clone() }) {
+                return failure(AppError::io(&e, format!("Failed to write the journal before {}: {}", operation, e)).at(journal.path()));
+            }
+        }
+
+        let result = perform(&self.anchor, &self.copy_settings, operation, &undo);
+        if let Some(journal) = &mut self.journal {
+            // 操作已经执行：日志写入失败时，恢复时会按磁盘状态判断它是否完成
+            let error = result.error.as_ref();
+            if let Err(e) = journal.record(&JournalRecord::Done {
+                index,
+                success: result.success,
+                message: result.message.clone(),
+                error_type: error.map(|error| error.error_type),
+                path: error.and_then(|error| error.path.clone()),
+            }) {
+                println!("Failed to write the journal after {}: {}", operation, e);
+            }
+        }
+
+        for action in undo {
+            if result.success || is_partial_effect(&self.anchor, &action) {
+                self.push(index, action);
+            }
+        }
+        result
+    }
+
+    pub(crate) fn push(&mut self, index: usize, action: UndoAction) {
+        if let UndoAction::Restore { staged, .. } = &action {
+            self.staged.push(staged.clone());
+        }
+        self.undo_log.push((index, action));
+    }
+
+    /// Reverses every applied operation, last one first. A failing step does not stop
+    /// the rollback; each step is reported so the resulting state is known exactly.
+    /// The journal is kept while any step failed, so the rollback can be retried.
+    pub fn rollback(mut self) -> Vec<RollbackStep> {
+        let mut steps = Vec::with_capacity(self.undo_log.len());
+        for (index, action) in std::mem::take(&mut self.undo_log).into_iter().rev() {
+            steps.push(self.undo_step(index, action));
+        }
+
+        if steps.iter().all(|step| step.success) {
+            if let Some(journal) = self.journal.take() {
+                journal.finish();
+            }
+        }
+        steps
+    }
+
+    /// Puts back the entry that was set aside to make room for `operation` after the
+    /// operation itself failed (an overwrite whose rename failed). The entry is never
+    /// removed on commit: if it cannot be put back, it stays under its hidden name.
+    pub(crate) fn restore_replaced(&mut self, operation: &FileOperation) -> Option<RollbackStep> {
+        let target = match operation {
+            FileOperation::Rename { to, .. } | FileOperation::Move { to, .. } | FileOperation::Copy { to, .. } => to,
+            FileOperation::CreateDir { path } | FileOperation::CreateFile { path } => path,
+            FileOperation::Delete { .. } | FileOperation::Merge { .. } | FileOperation::Exchange { .. } => return None,
+        };
+        let position = self.undo_log.iter()
+            .rposition(|(_, action)| matches!(action, UndoAction::Restore { path, .. } if path == target))?;
+        let (index, action) = self.undo_log[position].clone();
+        if let UndoAction::Restore { staged, .. } = &action {
+            self.staged.retain(|entry| entry != staged);
+        }
+
+        let step = self.undo_step(index, action);
+        if step.success {
+            self.undo_log.remove(position);
+        }
+        Some(step)
+    }
+
+    // 执行一个撤销动作，成功时记录到日志中
+    fn undo_step(&mut self, index: usize, action: UndoAction) -> RollbackStep {
+        let (success, message, error) = match undo(&self.anchor, &self.copy_settings, &action) {
+            Ok(message) => (true, message, None),
+            Err(error) => (false, error.message.clone(), Some(error)),
+        };
+        println!("Rollback of operation {}: {} ({})", index + 1, action, message);
+        if success {
+            if let Some(journal) = &mut self.journal {
+                if let Err(e) = journal.record(&JournalRecord::Undone { index, action: action.clone() }) {
+                    println!("Failed to write the journal: {}", e);
+                }
+            }
+        }
+        RollbackStep { index, action, success, message, error }
+    }
+
+    /// Keeps the changes and removes the entries that were set aside for deletion.
+    pub fn commit(mut self) -> Vec<String> {
+        if let Some(journal) = &mut self.journal {
+            if let Err(e) = journal.record(&JournalRecord::Commit) {
+                println!("Failed to write the journal: {}", e);
+            }
+        }
+
+        let mut errors = Vec::new();
+        for staged in &self.staged {
+            if !self.anchor.exists(Path::new(staged)) {
+                continue;
+            }
+            if let Err(e) = self.anchor.remove_all(Path::new(staged)) {
+                println!("Failed to remove {}: {}", staged, e);
+                errors.push(format!("Failed to remove {}: {}", staged, e));
+            }
+        }
+
+        if let Some(journal) = self.journal.take() {
+            journal.finish();
+        }
+        errors
+    }
+}
+
+/// Applies the operations as a single transaction: the first failure rolls back
+/// everything that was already applied. `root` is the directory the operations are
+/// confined to.
+pub fn execute_transaction(root: &Path, operations: &[FileOperation]) -> Result<TransactionReport, AppError> {
+    let anchor = open_anchor(root, operations)?;
+    Ok(run_transaction(Transaction::new(anchor), operations, Vec::new(), true))
+}
+
+/// Opens the directory a batch is confined to, before anything is applied. Next to
+/// it, only the other name of a root rename among `operations` can be reached. When
+/// the directory is already under that name (a batch interrupted after its root
+/// rename, or one undoing a root rename), it is opened there and paths are still
+/// resolved under `root`.
+pub(crate) fn open_anchor(root: &Path, operations: &[FileOperation]) -> Result<Anchor, AppError> {
+    match renamed_root(root, operations) {
+        Some(renamed) if fs::symlink_metadata(root).is_err() => Anchor::open_renamed(root, renamed)
+            .map_err(|e| AppError::io(&e, format!("Failed to open {}: {}", renamed.display(), e)).at(renamed)),
+        renamed => Anchor::open_with_rename(root, renamed)
+            .map_err(|e| AppError::io(&e, format!("Failed to open {}: {}", root.display(), e)).at(root)),
+    }
+}
+
+// 根目录改名的另一个名称：改名的目标，或撤销改名时的来源
+fn renamed_root<'a>(root: &Path, operations: &'a [FileOperation]) -> Option<&'a Path> {
+    operations.iter().find_map(|operation| match operation {
+        FileOperation::Rename { from, to } if Path::new(from) == root => Some(Path::new(to)),
+        FileOperation::Rename { from, to } if Path::new(to) == root => Some(Path::new(from)),
+        _ => None,
+    })
+}
+
+// 从 results.len() 处继续执行；atomic 为 false 时失败的操作不会触发回滚，
+// 只会恢复为它腾出位置而删除的条目（报告在 rollback 中）
+pub(crate) fn run_transaction(
+    transaction: Transaction,
+    operations: &[FileOperation],
+    results: Vec<OperationResult>,
+    atomic: bool,
+) -> TransactionReport {
+    run_transaction_observed(transaction, operations, results, atomic, &mut |_, _| true)
+}
+
+/// `run_transaction` that reports each result to `observe` as soon as the operation
+/// ran. When `observe` returns `false` the batch stops before the next operation
+/// without committing or rolling back: the journal keeps it as an interrupted batch.
+pub(crate) fn run_transaction_observed(
+    mut transaction: Transaction,
+    operations: &[FileOperation],
+    mut results: Vec<OperationResult>,
+    atomic: bool,
+    observe: &mut dyn FnMut(usize, &OperationResult) -> bool,
+) -> TransactionReport {
+    println!("Applying {} operations{}", operations.len() - results.len(), if atomic { " as a transaction" } else { "" });
+    let mut restored = Vec::new();
+    for (index, operation) in operations.iter().enumerate().skip(results.len()) {
+        println!("  {}. {}", index + 1, operation);
+        let result = transaction.apply(index, operation);
+        let failed = !result.success;
+        let proceed = observe(index, &result);
+        results.push(result);
+
+        // 覆盖失败时，被替换的条目不能在提交时被删除
+        if failed && !atomic {
+            if let (Some(step), Some(result)) = (transaction.restore_replaced(operation), results.last_mut()) {
+                result.message = format!("{} ({})", result.message, step.message);
+                result.needs_attention |= !step.success;
+                restored.push(step);
+            }
+        }
+
+        if failed && atomic {
+            println!("Operation {} failed, rolling back", index + 1);
+            let rollback = transaction.rollback();
+            let rolled_back = rollback.iter().all(|step| step.success);
+            return TransactionReport {
+                committed: false,
+                summary: BatchSummary::of(&results),
+                results,
+                failed_at: Some(index),
+                rollback,
+                rolled_back,
+                cleanup_errors: Vec::new(),
+                cancelled: false,
+                undo: Vec::new(),
+            };
+        }
+        // 取消：不提交也不回滚，日志保留已经执行的操作
+        if !proceed && index + 1 < operations.len() {
+            println!("Batch cancelled after operation {}", index + 1);
+            return TransactionReport {
+                committed: false,
+                summary: BatchSummary::of(&results),
+                results,
+                failed_at: None,
+                rollback: restored,
+                rolled_back: false,
+                cleanup_errors: Vec::new(),
+                cancelled: true,
+                undo: Vec::new(),
+            };
+        }
+    }
+
+    let undo = transaction.undo_log.iter().map(|(_, action)| action.clone()).collect();
+    let cleanup_errors = transaction.commit();
+    TransactionReport {
+        committed: true,
+        summary: BatchSummary::of(&results),
+        results,
+        failed_at: None,
+        rollback: restored,
+        rolled_back: false,
+        cleanup_errors,
+        cancelled: false,
+        undo,
+    }
+}
+
+// 检查操作并计算撤销它所需的动作（按执行顺序排列，回滚时倒序执行）
+fn prepare(anchor: &Anchor, operation: &FileOperation) -> Result<Vec<UndoAction>, AppError> {
+    let mut undo = Vec::new();
+    let target = match operation {
+        FileOperation::Rename { to, .. } | FileOperation::Move { to, .. } | FileOperation::Copy { to, .. } => Some(to),
+        FileOperation::CreateDir { path } | FileOperation::CreateFile { path } => Some(path),
+        FileOperation::Delete { .. } | FileOperation::Merge { .. } | FileOperation::Exchange { .. } => None,
+    };
+    // 操作会自动创建缺失的上级目录，回滚时需要删除它们
+    if let Some(target) = target {
+        for dir in missing_ancestors(anchor, Path::new(target)) {
+            undo.push(UndoAction::RemoveDir { path: dir.to_string_lossy().to_string() });
+        }
+    }
+
+    match operation {
+        FileOperation::Rename { from, to } | FileOperation::Move { from, to } => {
+            let (from_path, to_path) = (Path::new(from), Path::new(to));
+            // 已有的目标不会被替换：覆盖和合并在计划中是单独的删除和合并操作
+            if anchor.exists(to_path) && !is_fold_only_rename(anchor, from_path, to_path) {
+                return Err(AppError::new(ErrorType::PathAlreadyExists,
+                    format!("Refusing to replace {}: the target already exists", to)).at(to_path));
+            }
+            undo.push(UndoAction::Rename { from: to.clone(), to: from.clone() });
+        },
+        FileOperation::Merge { from, to } => {
+            // 移进去的条目逐个移回，清空后删除的目录重新创建
+            let steps = merge_steps(anchor, Path::new(from), Path::new(to))
+                .map_err(|e| AppError::io(&e, format!("Cannot merge {} into {}: {}", from, to, e)).at(from))?;
+            for step in steps {
+                undo.push(match step {
+                    MergeStep::Move { from, to } => UndoAction::Rename {
+                        from: to.to_string_lossy().to_string(),
+                        to: from.to_string_lossy().to_string(),
+                    },
+                    MergeStep::RemoveDir(dir) => UndoAction::RecreateDir { path: dir.to_string_lossy().to_string() },
+                });
+            }
+        },
+        FileOperation::Exchange { from, to } => {
+            if let Some(missing) = [from, to].into_iter().find(|path| anchor.is_dir(Path::new(path)).is_err()) {
+                return Err(AppError::new(ErrorType::FileNotFound,
+                    format!("Cannot exchange {} and {}: both must exist", from, to)).at(missing));
+            }
+            undo.push(UndoAction::Exchange { from: from.clone(), to: to.clone(), identity: anchor.identity(Path::new(from)) });
+        },
+        FileOperation::CreateDir { path } => {
+            if !anchor.is_dir(Path::new(path)).unwrap_or(false) {
+                undo.push(UndoAction::RemoveDir { path: path.clone() });
+            }
+        },
+        FileOperation::CreateFile { path } => undo.push(UndoAction::RemoveFile { path: path.clone() }),
+        FileOperation::Copy { to, .. } => {
+            // 回滚会删除整个目标，因此不能是已有的条目
+            if anchor.exists(Path::new(to)) {
+                return Err(AppError::new(ErrorType::PathAlreadyExists,
+                    format!("Copy target already exists: {}", to)).at(to));
+            }
+            undo.push(UndoAction::RemoveCopy { path: to.clone() });
+        },
+        FileOperation::Delete { path } => {
+            // 删除的条目先移到旁边的隐藏名称下，提交时才真正删除
+            let target = Path::new(path);
+            let name = target.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            let staged = target.with_file_name(format!(".treenamer-deleted-{}-{}", uuid::Uuid::new_v4().simple(), name));
+            undo.push(UndoAction::Restore { staged: staged.to_string_lossy().to_string(), path: path.clone() });
+        },
+    }
+    Ok(undo)
+}
+
+fn perform(anchor: &Anchor, copy_settings: &CopySettings, operation: &FileOperation, undo: &[UndoAction]) -> OperationResult {
+    let FileOperation::Delete { path } = operation else {
+        return apply_operation_with_settings(anchor, operation, copy_settings);
+    };
+    let Some(UndoAction::Restore { staged, .. }) = undo.last() else {
+        return failure(AppError::new(ErrorType::SystemError, format!("No place to set {} aside", path)).at(path));
+    };
+    match anchor.rename_noreplace(Path::new(path), Path::new(staged)) {
+        Ok(_) => OperationResult::ok(format!("Deleted {}", path)),
+        Err(e) => failure(AppError::io(&e, format!("Failed to delete {}: {}", path, e)).at(path)),
+    }
+}
+
+// 未完成的操作可能留下的部分结果：创建的上级目录、合并时已经移走的条目和删除的目录、复制了一部分的目标
+pub(crate) fn is_partial_effect(anchor: &Anchor, action: &UndoAction) -> bool {
+    let present = |path: &str| anchor.metadata(Path::new(path)).is_ok();
+    match action {
+        UndoAction::Rename { from, to } => present(from) && !present(to),
+        UndoAction::RemoveDir { path } => anchor.is_dir(Path::new(path)).unwrap_or(false),
+        UndoAction::RecreateDir { path } => !present(path),
+        UndoAction::RemoveCopy { path } => present(path),
+        _ => false,
+    }
+}
+
+// 执行一个撤销动作，返回描述信息
+pub(crate) fn undo(anchor: &Anchor, copy_settings: &CopySettings, action: &UndoAction) -> Result<String, AppError> {
+    // 撤销重命名和交换时使用与执行时相同的操作
+    let reverse = |from: &str, to: &str, operation: FileOperation| {
+        let result = apply_operation_with_settings(anchor, &operation, copy_settings);
+        if result.success {
+            Ok(result.message)
+        } else {
+            Err(result.error.unwrap_or_else(|| AppError::new(ErrorType::SystemError, format!("Failed to move {} back to {}: {}", from, to, result.message)).at(from)))
+        }
+    };
+    match action {
+        UndoAction::Rename { from, to } => {
+            if anchor.exists(Path::new(to)) && !is_fold_only_rename(anchor, Path::new(from), Path::new(to)) {
+                return Err(AppError::new(ErrorType::PathAlreadyExists, format!("Cannot rename {} back: {} already exists", from, to)).at(to));
+            }
+            reverse(from, to, FileOperation::Rename { from: from.clone(), to: to.clone() })
+        },
+        UndoAction::Exchange { from, to, .. } => reverse(from, to, FileOperation::Exchange { from: from.clone(), to: to.clone() }),
+        UndoAction::RecreateDir { path } => anchor.create_dir(Path::new(path))
+            .map(|_| format!("Recreated directory {}", path))
+            .map_err(|e| AppError::io(&e, format!("Failed to recreate directory {}: {}", path, e)).at(path)),
+        // 目录中出现了其他内容时保留它，不删除不属于本次操作的数据
+        UndoAction::RemoveDir { path } => anchor.remove_dir(Path::new(path))
+            .map(|_| format!("Removed directory {}", path))
+            .map_err(|e| AppError::io(&e, format!("Failed to remove directory {}: {}", path, e)).at(path)),
+        UndoAction::RemoveFile { path } => anchor.remove_file(Path::new(path))
+            .map(|_| format!("Removed file {}", path))
+            .map_err(|e| AppError::io(&e, format!("Failed to remove file {}: {}", path, e)).at(path)),
+        UndoAction::RemoveCopy { path } => anchor.remove_all(Path::new(path))
+            .map(|_| format!("Removed copy {}", path))
+            .map_err(|e| AppError::io(&e, format!("Failed to remove copy {}: {}", path, e)).at(path)),
+        UndoAction::Restore { staged, path } => {
+            if anchor.exists(Path::new(path)) {
+                return Err(AppError::new(ErrorType::PathAlreadyExists,
+                    format!("Cannot restore {}: the path is occupied (the data is kept at {})", path, staged)).at(path));
+            }
+            anchor.rename_noreplace(Path::new(staged), Path::new(path))
+                .map(|_| format!("Restored {}", path))
+                .map_err(|e| AppError::io(&e, format!("Failed to restore {} (the data is kept at {}): {}", path, staged, e)).at(path))
+        },
+    }
+}
+
+// 目标路径上尚不存在的上级目录，从外到内排列；无法访问的目录（如根目录之外）不算缺失
+fn missing_ancestors(anchor: &Anchor, path: &Path) -> Vec<PathBuf> {
+    let mut missing: Vec<PathBuf> = path.ancestors()
+        .skip(1)
+        .take_while(|dir| matches!(anchor.metadata(dir), Err(e) if e.kind() == std::io::ErrorKind::NotFound))
+        .map(Path::to_path_buf)
+        .collect();
+    missing.reverse();
+    missing
+}
+
+fn failure(error: AppError) -> OperationResult {
+    println!("{}", error.message);
+    OperationResult::failed(error)
+}
+
+/// Applies the changes between the two trees as a single transaction. When an
+/// operation fails, everything applied before it is reversed and the report lists
+/// every rollback step with its outcome.
+#[command]
+pub fn apply_transaction(
+    #[allow(non_snake_case)] dirPath: String,
+    #[allow(non_snake_case)] originalTree: String,
+    #[allow(non_snake_case)] modifiedTree: String,
+    options: Option<PlanOptions>
+) -> Result<TransactionReport, AppError> {
+    println!("apply_transaction called with path: {}", dirPath);
+    apply_transaction_at(&app_data_dir(), &dirPath, &originalTree, &modifiedTree, &options.unwrap_or_default())
+}
+
+/// `apply_transaction` with the backup, journal and history kept in `data_dir`.
+pub(crate) fn apply_transaction_at(
+    data_dir: &Path,
+    dir_path: &str,
+    original_tree: &str,
+    modified_tree: &str,
+    options: &PlanOptions,
+) -> Result<TransactionReport, AppError> {
+    let (operations, node_ids) = plan_for_apply(dir_path, original_tree, modified_tree, options)?;
+    let mut report = apply_batch(data_dir, Path::new(dir_path), &operations, true)?;
+    attach_node_ids(&mut report.results, &node_ids);
+    Ok(report)
+}
+
+/// Applies a planned batch in `base`: takes the backup, starts the journal, runs
+/// the operations as a transaction and records the batch in the history, all kept
+/// in `data_dir`. With `atomic` unset a failed operation does not stop the others.
+pub(crate) fn apply_batch(data_dir: &Path, base: &Path, operations: &[FileOperation], atomic: bool) -> Result<TransactionReport, AppError> {
+    let anchor = open_anchor(base, operations)?;
+    backup_before_batch(data_dir, base, operations)?;
+    let journal = start_journal(data_dir, base, operations, atomic)?;
+    let report = run_transaction(Transaction::with_journal(anchor, journal), operations, Vec::new(), atomic);
+    record_applied_batch(data_dir, &base.to_string_lossy(), operations, &report);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::test_support::{path, TempDir};
+
+    fn snapshot(root: &Path) -> Vec<(String, Option<String>)> {
+        let mut entries = Vec::new();
+        let mut stack = vec![root.to_path_buf()];
+        while let Some(dir) = stack.pop() {
+            for entry in fs::read_dir(&dir).unwrap() {
+                let path = entry.unwrap().path();
+                let relative = path.strip_prefix(root).unwrap().to_string_lossy().to_string();
+                if path.is_dir() {
+                    stack.push(path);
+                    entries.push((relative, None));
+                } else {
+                    entries.push((relative, Some(fs::read_to_string(&path).unwrap())));
+                }
+            }
+        }
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn test_failed_transaction_restores_the_original_state() {
+        let root = TempDir::new("transaction");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::create_dir_all(root.join("empty")).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("b.txt"), "b").unwrap();
+        fs::write(root.join("sub/c.txt"), "c").unwrap();
+        let before = snapshot(&root);
+
+        let operations = vec![
+            FileOperation::Move { from: path(&root, "a.txt"), to: path(&root, "x/y/a.txt") },
+            FileOperation::Delete { path: path(&root, "b.txt") },
+            FileOperation::Merge { from: path(&root, "sub"), to: path(&root, "empty") },
+            FileOperation::CreateDir { path: path(&root, "new/dir") },
+            FileOperation::CreateFile { path: path(&root, "new/file.txt") },
+            FileOperation::Copy { from: path(&root, "empty"), to: path(&root, "copy") },
+            FileOperation::Rename { from: path(&root, "missing.txt"), to: path(&root, "other.txt") },
+        ];
+        let report = execute_transaction(&root, &operations).unwrap();
+
+        assert!(!report.committed);
+        assert_eq!(report.failed_at, Some(6));
+        assert_eq!(report.results.len(), 7);
+        assert!(report.rolled_back, "{:?}", report.rollback);
+        // 回滚从最后执行的操作开始
+        assert_eq!(report.rollback.first().map(|step| step.index), Some(5));
+        assert_eq!(report.rollback.last().map(|step| step.index), Some(0));
+        assert_eq!(snapshot(&root), before);
+    }
+
+    #[test]
+    fn test_exchange_is_rolled_back_by_exchanging_again() {
+        let root = TempDir::new("transaction");
+        fs::create_dir_all(root.join("dir")).unwrap();
+        fs::write(root.join("dir/inner.txt"), "inner").unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        let before = snapshot(&root);
+
+        let operations = vec![
+            FileOperation::Exchange { from: path(&root, "a.txt"), to: path(&root, "dir") },
+            FileOperation::Rename { from: path(&root, "missing.txt"), to: path(&root, "other.txt") },
+        ];
+        let report = execute_transaction(&root, &operations).unwrap();
+        assert!(report.results[0].success, "{}", report.results[0].message);
+        assert!(!report.committed && report.rolled_back, "{:?}", report.rollback);
+        assert_eq!(snapshot(&root), before);
+        // 交换也不会残留临时名称
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_committed_transaction_removes_deleted_entries() {
+        let root = TempDir::new("transaction");
+        fs::create_dir_all(root.join("old")).unwrap();
+        fs::write(root.join("old/a.txt"), "a").unwrap();
+        fs::write(root.join("b.txt"), "b").unwrap();
+
+        let report = execute_transaction(&root, &[
+            FileOperation::Delete { path: path(&root, "old") },
+            FileOperation::Rename { from: path(&root, "b.txt"), to: path(&root, "old") },
+        ]).unwrap();
+
+        assert!(report.committed);
+        assert!(report.cleanup_errors.is_empty());
+        assert_eq!(snapshot(&root), vec![("old".to_string(), Some("b".to_string()))]);
+    }
+
+    #[test]
+    fn test_failed_overwrite_keeps_the_replaced_entry() {
+        let root = TempDir::new("transaction");
+        fs::create_dir_all(root.join("old")).unwrap();
+        fs::write(root.join("old/a.txt"), "a").unwrap();
+        fs::write(root.join("b.txt"), "b").unwrap();
+        fs::write(root.join("c.txt"), "c").unwrap();
+
+        // 覆盖 old 的重命名失败（来源已经不存在），其他操作继续执行
+        let operations = vec![
+            FileOperation::Delete { path: path(&root, "old") },
+            FileOperation::Rename { from: path(&root, "missing.txt"), to: path(&root, "old") },
+            FileOperation::Delete { path: path(&root, "c.txt") },
+        ];
+        let report = run_transaction(Transaction::new(Anchor::open(&root).unwrap()), &operations, Vec::new(), false);
+
+        assert!(report.committed);
+        assert!(!report.results[1].success);
+        assert!(report.results[1].message.contains("Restored"), "{}", report.results[1].message);
+        assert_eq!(report.rollback.len(), 1);
+        assert!(report.rollback[0].success && report.rollback[0].index == 0, "{:?}", report.rollback);
+        // 被替换的目录恢复原样，其他删除照常提交，没有残留的隐藏名称
+        assert_eq!(snapshot(&root).len(), 3);
+        assert_eq!(fs::read_to_string(root.join("old/a.txt")).unwrap(), "a");
+        assert!(!root.join("c.txt").exists());
+        // 历史中只剩下确实执行了的删除
+        assert_eq!(report.undo.len(), 1);
+    }
+
+    #[test]
+    fn test_failed_rollback_steps_are_reported() {
+        let root = TempDir::new("transaction");
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("b.txt"), "b").unwrap();
+
+        // 覆盖已有文件会被拒绝
+        let mut transaction = Transaction::new(Anchor::open(&root).unwrap());
+        let refused = transaction.apply(0, &FileOperation::Rename { from: path(&root, "a.txt"), to: path(&root, "b.txt") });
+        assert!(!refused.success);
+
+        assert!(transaction.apply(0, &FileOperation::Rename { from: path(&root, "a.txt"), to: path(&root, "c.txt") }).success);
+        assert!(transaction.apply(1, &FileOperation::Delete { path: path(&root, "b.txt") }).success);
+        // 回滚前有其他程序占用了原来的名称
+        fs::write(root.join("a.txt"), "other").unwrap();
+        fs::write(root.join("b.txt"), "other").unwrap();
+
+        let steps = transaction.rollback();
+        assert_eq!(steps.len(), 2);
+        assert!(steps.iter().all(|step| !step.success));
+        assert_eq!(steps[0].index, 1);
+        assert!(steps[0].message.contains("is occupied"), "{}", steps[0].message);
+        assert!(steps[1].message.contains("already exists"), "{}", steps[1].message);
+        // 没有数据丢失
+        assert_eq!(fs::read_to_string(root.join("c.txt")).unwrap(), "a");
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 4);
+    }
+}
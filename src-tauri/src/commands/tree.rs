@@ -1,17 +1,23 @@
 use std::path::{Path};
 use std::fs;
+use std::fs::Metadata;
+use std::time::UNIX_EPOCH;
 use tauri::command;
 use serde::{Serialize, Deserialize};
 use regex::Regex;
 use uuid::Uuid;
-use std::error::Error;
+use crate::error::{AppError, ErrorType};
+use crate::commands::fingerprint::NodeFingerprint;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DirectoryOptions {
     pub max_depth: usize,
     pub exclude_pattern: String,
     pub follow_symlinks: bool,
     pub show_hidden: bool,
+    // 是否采集大小、修改时间和条目数，用于渲染只读元数据列
+    #[serde(default)]
+    pub include_metadata: bool,
 }
 
 impl Default for DirectoryOptions {
@@ -21,6 +27,7 @@ impl Default for DirectoryOptions {
             exclude_pattern: String::from("node_modules|.git"),
             follow_symlinks: false,
             show_hidden: false,
+            include_metadata: false,
         }
     }
 }
@@ -31,13 +38,60 @@ pub struct TreeNode {
     pub name: String,
     pub is_dir: bool,
     pub children: Vec<TreeNode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<NodeMetadata>,
+    /// Recorded for every scanned node so that changes made on disk before the
+    /// plan is applied can be detected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<NodeFingerprint>,
+}
+
+/// Read-only information shown next to a node name. It is never parsed back
+/// from the tree text, so editing it cannot influence the generated operations.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct NodeMetadata {
+    /// File size in bytes (files only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Last modification time as seconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<u64>,
+    /// Number of listed children (directories only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub item_count: Option<usize>,
+}
+
+/// Which metadata columns to append after each name in the tree text.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct TreeColumns {
+    #[serde(default)]
+    pub size: bool,
+    #[serde(default)]
+    pub modified: bool,
+    #[serde(default)]
+    pub item_count: bool,
+}
+
+impl TreeColumns {
+    fn any(&self) -> bool {
+        self.size || self.modified || self.item_count
+    }
+
+    /// Number of columns written after each name.
+    pub fn count(&self) -> usize {
+        [self.size, self.modified, self.item_count].into_iter().filter(|shown| *shown).count()
+    }
 }
 
+/// Separates the name from the metadata columns in the tree text.
+/// Each column starts with one separator, so the parser finds them from the end of the line.
+pub const COLUMN_SEPARATOR: char = '┊';
+
 #[command]
 pub fn parse_directory(
     #[allow(non_snake_case)] dirPath: String, 
     options: Option<DirectoryOptions>
-) -> Result<String, String> {
+) -> Result<String, AppError> {
     println!("parse_directory called with dirPath: {:?}", dirPath);
     println!("parse_directory options parameter: {:?}", options);
     
@@ -45,21 +99,19 @@ pub fn parse_directory(
     if !path.exists() {
         let error_msg = format!("Path does not exist: {}", dirPath);
         println!("Error: {}", error_msg);
-        return Err(error_msg);
+        return Err(AppError::new(ErrorType::FileNotFound, error_msg).at(path));
     }
     if !path.is_dir() {
         let error_msg = format!("Path is not a directory: {}", dirPath);
         println!("Error: {}", error_msg);
-        return Err(error_msg);
+        return Err(AppError::new(ErrorType::InvalidInput, error_msg).at(path));
     }
     
     let options_to_use = options.unwrap_or_default();
     println!("Using options: {:?}", options_to_use);
     
-    let tree = build_tree_with_options(path, options_to_use).map_err(|e| {
-        let error_msg = e.to_string();
-        println!("Error building tree: {}", error_msg);
-        error_msg
+    let tree = build_tree_with_options(path, options_to_use).inspect_err(|e| {
+        println!("Error building tree: {}", e.message);
     })?;
     
     let json = match serde_json::to_string(&tree) {
@@ -70,7 +122,7 @@ pub fn parse_directory(
         Err(e) => {
             let error_msg = e.to_string();
             println!("Error serializing tree to JSON: {}", error_msg);
-            return Err(error_msg);
+            return Err(AppError::new(ErrorType::SystemError, error_msg).at(path));
         }
     };
     
@@ -78,7 +130,7 @@ pub fn parse_directory(
     Ok(json)
 }
 
-pub fn build_tree_with_options(path: &Path, options: DirectoryOptions) -> Result<TreeNode, Box<dyn Error>> {
+pub fn build_tree_with_options(path: &Path, options: DirectoryOptions) -> Result<TreeNode, AppError> {
     println!("parse_directory called with path: {}", path.display());
     
     // Create regex for exclusion pattern
@@ -86,18 +138,18 @@ pub fn build_tree_with_options(path: &Path, options: DirectoryOptions) -> Result
         Ok(regex) => regex,
         Err(e) => {
             println!("Error: Invalid exclude pattern: {}", e);
-            return Err(format!("Invalid exclude pattern: {}", e).into());
+            return Err(AppError::new(ErrorType::InvalidInput, format!("Invalid exclude pattern: {}", e)).at(path));
         }
     };
     
     println!("Building tree structure...");
     // Build the tree structure
-    let tree = build_tree_internal(path, &exclude_regex, options.max_depth, options.follow_symlinks, options.show_hidden, 0)?;
+    let tree = build_tree_internal(path, &exclude_regex, options.max_depth, options.follow_symlinks, options.show_hidden, options.include_metadata, 0)?;
     
     Ok(tree)
 }
 
-pub fn build_tree(path: &Path) -> Result<TreeNode, Box<dyn Error>> {
+pub fn build_tree(path: &Path) -> Result<TreeNode, AppError> {
     build_tree_with_options(path, DirectoryOptions::default())
 }
 
@@ -107,8 +159,9 @@ fn build_tree_internal(
     max_depth: usize, 
     follow_symlinks: bool, 
     show_hidden: bool, 
+    include_metadata: bool,
     current_depth: usize
-) -> Result<TreeNode, Box<dyn Error>> {
+) -> Result<TreeNode, AppError> {
     let name = path.file_name()
         .map(|name| name.to_string_lossy().to_string())
         .unwrap_or_else(|| path.to_string_lossy().to_string());
@@ -118,8 +171,14 @@ fn build_tree_internal(
         name,
         is_dir: true,
         children: Vec::new(),
+        metadata: None,
+        fingerprint: NodeFingerprint::of(path),
     };
     
+    if include_metadata {
+        node.metadata = fs::metadata(path).ok().map(|m| node_metadata(&m));
+    }
+    
     // Stop recursion if we've reached max depth
     if current_depth >= max_depth {
         return Ok(node);
@@ -128,7 +187,7 @@ fn build_tree_internal(
     // Read directory entries
     let entries = match fs::read_dir(path) {
         Ok(entries) => entries,
-        Err(e) => return Err(format!("Failed to read directory {}: {}", path.display(), e).into()),
+        Err(e) => return Err(AppError::io(&e, format!("Failed to read directory {}: {}", path.display(), e)).at(path)),
     };
     
     // Process each entry
@@ -171,9 +230,9 @@ fn build_tree_internal(
         
         if metadata.is_dir() {
             // Recursively process subdirectory
-            match build_tree_internal(&entry_path, exclude_regex, max_depth, follow_symlinks, show_hidden, current_depth + 1) {
+            match build_tree_internal(&entry_path, exclude_regex, max_depth, follow_symlinks, show_hidden, include_metadata, current_depth + 1) {
                 Ok(child_node) => children.push(child_node),
-                Err(e) => eprintln!("Error processing directory {}: {}", entry_path.display(), e),
+                Err(e) => eprintln!("Error processing directory {}: {}", entry_path.display(), e.message),
             }
         } else {
             // Add file node
@@ -182,6 +241,13 @@ fn build_tree_internal(
                 name: entry_name,
                 is_dir: false,
                 children: Vec::new(),
+                metadata: if include_metadata { Some(node_metadata(&metadata)) } else { None },
+                // 指纹始终记录链接本身
+                fingerprint: if follow_symlinks {
+                    NodeFingerprint::of(&entry_path)
+                } else {
+                    Some(NodeFingerprint::from_metadata(&metadata))
+                },
             });
         }
     }
@@ -195,19 +261,71 @@ fn build_tree_internal(
         }
     });
     
+    if let Some(meta) = node.metadata.as_mut() {
+        meta.item_count = Some(children.len());
+    }
+    
     node.children = children;
     Ok(node)
 }
 
-fn format_tree(nodes: &[TreeNode], result: &mut String, prefix: &str, _is_last: bool) {
+fn node_metadata(metadata: &Metadata) -> NodeMetadata {
+    let modified = metadata.modified().ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs());
+    
+    NodeMetadata {
+        size: if metadata.is_dir() { None } else { Some(metadata.len()) },
+        modified,
+        item_count: None,
+    }
+}
+
+/// Renders a tree as editable text, optionally followed by aligned read-only columns.
+pub fn format_tree_text(root: &TreeNode, columns: &TreeColumns) -> String {
+    let mut lines = Vec::new();
+    lines.push((format!("{}/", root.name), None));
+    format_tree(&root.children, &mut lines, "");
+    
+    if !columns.any() {
+        return lines.into_iter().map(|(text, _)| text + "\n").collect();
+    }
+    
+    // Column values for every line; the root line carries no columns
+    let cells: Vec<Vec<String>> = lines.iter()
+        .map(|(_, node)| match node {
+            Some(node) => column_cells(node, columns),
+            None => Vec::new(),
+        })
+        .collect();
+    
+    let name_width = lines.iter().map(|(text, _)| text.chars().count()).max().unwrap_or(0);
+    let column_count = cells.iter().map(|row| row.len()).max().unwrap_or(0);
+    let column_widths: Vec<usize> = (0..column_count)
+        .map(|i| cells.iter().filter_map(|row| row.get(i)).map(|c| c.chars().count()).max().unwrap_or(0))
+        .collect();
+    
+    let mut result = String::new();
+    for ((text, _), row) in lines.iter().zip(&cells) {
+        result.push_str(text);
+        if !row.is_empty() {
+            result.push_str(&" ".repeat(name_width - text.chars().count()));
+            for (cell, width) in row.iter().zip(&column_widths) {
+                result.push_str(&format!("  {} {:>width$}", COLUMN_SEPARATOR, cell, width = *width));
+            }
+        }
+        result.push('\n');
+    }
+    result
+}
+
+fn format_tree<'a>(nodes: &'a [TreeNode], lines: &mut Vec<(String, Option<&'a TreeNode>)>, prefix: &str) {
     for (i, node) in nodes.iter().enumerate() {
         let is_last_node = i == nodes.len() - 1;
         let connector = if is_last_node { "└── " } else { "├── " };
         
-        result.push_str(&format!("{}{}", prefix, connector));
-        
         if node.is_dir {
-            result.push_str(&format!("{}/\n", node.name));
+            lines.push((format!("{}{}{}/", prefix, connector, node.name), Some(node)));
             
             let new_prefix = if is_last_node {
                 format!("{}    ", prefix)
@@ -215,13 +333,98 @@ fn format_tree(nodes: &[TreeNode], result: &mut String, prefix: &str, _is_last:
                 format!("{}│   ", prefix)
             };
             
-            format_tree(&node.children, result, &new_prefix, is_last_node);
+            format_tree(&node.children, lines, &new_prefix);
         } else {
-            result.push_str(&format!("{}\n", node.name));
+            lines.push((format!("{}{}{}", prefix, connector, node.name), Some(node)));
         }
     }
 }
 
+fn column_cells(node: &TreeNode, columns: &TreeColumns) -> Vec<String> {
+    let meta = node.metadata.clone().unwrap_or_default();
+    let mut cells = Vec::new();
+    
+    if columns.size {
+        cells.push(meta.size.map(format_size).unwrap_or_default());
+    }
+    if columns.modified {
+        cells.push(meta.modified.map(format_timestamp).unwrap_or_default());
+    }
+    if columns.item_count {
+        cells.push(meta.item_count.map(|n| format!("{} items", n)).unwrap_or_default());
+    }
+    cells
+}
+
+/// Strips the read-only metadata columns from a line of tree text rendered with
+/// `column_count` columns. The columns are found by position, counting separators
+/// from the end of the line, so their content never matters and names containing
+/// the separator are kept. A line with fewer separators lost some of its columns
+/// and is cut at the first one; lines without separators are returned unchanged.
+pub fn strip_metadata_columns(line: &str, column_count: usize) -> &str {
+    if column_count == 0 {
+        return line;
+    }
+    let separators: Vec<usize> = line.match_indices(COLUMN_SEPARATOR).map(|(index, _)| index).collect();
+    let start = if separators.len() >= column_count {
+        separators[separators.len() - column_count]
+    } else {
+        match separators.first() {
+            Some(index) => *index,
+            None => return line,
+        }
+    };
+    line[..start].trim_end()
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+// 将Unix时间戳格式化为 UTC 的 "YYYY-MM-DD HH:MM"
+fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let seconds_of_day = secs % 86_400;
+    
+    // Civil-from-days conversion (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, seconds_of_day / 3600, (seconds_of_day % 3600) / 60)
+}
+
+#[command]
+pub fn render_tree_text(
+    #[allow(non_snake_case)] dirPath: String,
+    #[allow(non_snake_case)] treeJson: String,
+    columns: Option<TreeColumns>
+) -> Result<String, AppError> {
+    let tree: TreeNode = serde_json::from_str(&treeJson)
+        .map_err(|e| AppError::new(ErrorType::InvalidInput, format!("Invalid tree JSON: {}", e)).at(&dirPath))?;
+    
+    Ok(format_tree_text(&tree, &columns.unwrap_or_default()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -235,6 +438,8 @@ mod tests {
             name: "root".to_string(),
             is_dir: true,
             children: Vec::new(),
+            metadata: None,
+            fingerprint: None,
         };
 
         let child1 = TreeNode {
@@ -242,6 +447,8 @@ mod tests {
             name: "child1".to_string(),
             is_dir: false,
             children: Vec::new(),
+            metadata: None,
+            fingerprint: None,
         };
 
         let child2 = TreeNode {
@@ -249,6 +456,8 @@ mod tests {
             name: "child2".to_string(),
             is_dir: true,
             children: Vec::new(),
+            metadata: None,
+            fingerprint: None,
         };
 
         root.children.push(child1);
@@ -276,7 +485,10 @@ mod tests {
         // Test with non-existent path
         let result = parse_directory("__this_path_definitely_doesnt_exist__".to_string(), None);
         assert!(result.is_err(), "Parsing a non-existent directory should return an error");
-        let error_msg = result.unwrap_err();
+        let error = result.unwrap_err();
+        assert_eq!(error.error_type, ErrorType::FileNotFound);
+        assert_eq!(error.path.as_deref(), Some("__this_path_definitely_doesnt_exist__"));
+        let error_msg = error.message;
         assert!(
             error_msg.contains("exist") || error_msg.contains("find") || error_msg.contains("found"),
             "Error message should indicate the path doesn't exist: {}",
@@ -292,7 +504,7 @@ mod tests {
             // Now try to parse it as a directory
             let result = parse_directory(temp_file.to_string_lossy().to_string(), None);
             assert!(result.is_err(), "Parsing a file as a directory should return an error");
-            let error_msg = result.unwrap_err();
+            let error_msg = result.unwrap_err().message;
             assert!(
                 error_msg.contains("directory") || error_msg.contains("folder") || error_msg.contains("NotADirectory"),
                 "Error message should indicate the path is not a directory: {}",
@@ -303,4 +515,81 @@ mod tests {
             let _ = std::fs::remove_file(temp_file);
         }
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_format_tree_text_with_metadata_columns() {
+        let file = |id: &str, name: &str, size: u64| TreeNode {
+            id: id.to_string(),
+            name: name.to_string(),
+            is_dir: false,
+            children: Vec::new(),
+            metadata: Some(NodeMetadata { size: Some(size), modified: Some(1_700_000_000), item_count: None }),
+            fingerprint: None,
+        };
+        let root = TreeNode {
+            id: "root-id".to_string(),
+            name: "root".to_string(),
+            is_dir: true,
+            children: vec![
+                TreeNode {
+                    id: "photos-id".to_string(),
+                    name: "photos".to_string(),
+                    is_dir: true,
+                    children: vec![file("img-id", "img_0001.jpg", 2048)],
+                    metadata: Some(NodeMetadata { size: None, modified: Some(0), item_count: Some(1) }),
+                    fingerprint: None,
+                },
+                file("notes-id", "notes.txt", 12),
+            ],
+            metadata: None,
+            fingerprint: None,
+        };
+
+        // 不启用列时输出与普通树文本相同
+        let plain = format_tree_text(&root, &TreeColumns::default());
+        assert_eq!(plain, "root/\n├── photos/\n│   └── img_0001.jpg\n└── notes.txt\n");
+
+        let columns = TreeColumns { size: true, modified: true, item_count: true };
+        let text = format_tree_text(&root, &columns);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "root/", "根节点行不带元数据列");
+        assert!(lines[1].contains("1970-01-01 00:00") && lines[1].contains("1 items"));
+        assert!(lines[2].contains("2.0 KB") && lines[2].contains("2023-11-14 22:13"));
+        assert!(lines[3].contains("12 B"));
+
+        // 所有列分隔符对齐在同一位置
+        let separator_columns: Vec<usize> = lines[1..].iter()
+            .map(|line| line.chars().position(|c| c == COLUMN_SEPARATOR).unwrap())
+            .collect();
+        assert!(separator_columns.windows(2).all(|w| w[0] == w[1]), "列未对齐: {:?}", separator_columns);
+
+        // 去掉列后恢复原始行
+        let stripped: Vec<&str> = lines.iter().map(|line| strip_metadata_columns(line, columns.count())).collect();
+        assert_eq!(stripped.join("\n") + "\n", plain);
+
+        // 名称中的分隔符不会被当作列的开始
+        assert_eq!(strip_metadata_columns("├── a ┊ b.txt", 0), "├── a ┊ b.txt");
+        assert_eq!(strip_metadata_columns("├── a┊b.txt  ┊   2.0 KB ┊ 2024-05-01 12:00", 2), "├── a┊b.txt");
+        assert_eq!(strip_metadata_columns("├── a ┊ 12 B.txt  ┊     12 B", 1), "├── a ┊ 12 B.txt");
+    }
+
+    #[test]
+    fn test_strip_metadata_columns_ignores_cell_content() {
+        // 列的内容被改成任意文本时，仍然只按位置去掉
+        for line in [
+            "├── notes.txt  ┊ 2.0KB ┊ 2024-05-01 12:00 ┊",
+            "├── notes.txt  ┊ abc ┊ 2024-13-45 99 ┊ lots of items",
+            "├── notes.txt  ┊ ┊ ┊",
+            "├── notes.txt┊┊┊",
+            // 删掉了一部分列
+            "├── notes.txt  ┊ 12 B",
+        ] {
+            assert_eq!(strip_metadata_columns(line, 3), "├── notes.txt", "line: {}", line);
+        }
+
+        // 用户添加的行没有列
+        assert_eq!(strip_metadata_columns("├── new.txt", 3), "├── new.txt");
+        // 名称中的分隔符在列之前，不受影响
+        assert_eq!(strip_metadata_columns("├── a ┊ b.txt ┊ x ┊ y ┊ z", 3), "├── a ┊ b.txt");
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use serde::Serialize;
+use crate::commands::anchor::Anchor;
+use crate::commands::conflicts::is_case_insensitive;
+
+/// How a volume compares file names.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Default)]
+pub struct FoldingRules {
+    /// `Readme.md` and `README.md` name the same entry (vfat, exFAT, Samba, macOS, Windows)
+    pub case_insensitive: bool,
+    /// Composed and decomposed forms of the same character (NFC `é` and NFD `é`) name the same entry
+    pub normalizing: bool,
+}
+
+// 卷标识 -> 命名规则，每个卷只探测一次
+static VOLUME_RULES: OnceLock<Mutex<HashMap<String, FoldingRules>>> = OnceLock::new();
+
+// 测试中替换当前线程看到的命名规则，不影响并行的其他测试
+#[cfg(test)]
+thread_local! {
+    static STUBBED_RULES: std::cell::Cell<Option<FoldingRules>> = const { std::cell::Cell::new(None) };
+}
+
+#[cfg(unix)]
+fn volume_key(anchor: &Anchor, dir: &Path) -> Option<String> {
+    anchor.metadata(dir).ok().and_then(|metadata| metadata.device).map(|device| format!("dev:{}", device))
+}
+
+#[cfg(not(unix))]
+fn volume_key(anchor: &Anchor, dir: &Path) -> Option<String> {
+    anchor.metadata(dir).ok()?;
+    let canonical = fs::canonicalize(dir).ok()?;
+    canonical.components().next().map(|c| c.as_os_str().to_string_lossy().to_lowercase())
+}
+
+/// Returns the folding rules of the volume holding `dir`. The volume is probed the
+/// first time it is seen and the result is reused for every later directory on it.
+pub(crate) fn folding_rules(anchor: &Anchor, dir: &Path) -> FoldingRules {
+    #[cfg(test)]
+    if let Some(rules) = STUBBED_RULES.with(|rules| rules.get()) {
+        return rules;
+    }
+    let Some(key) = volume_key(anchor, dir) else { return FoldingRules::default() };
+    let cache = VOLUME_RULES.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(rules) = cache.lock().ok().and_then(|rules| rules.get(&key).copied()) {
+        return rules;
+    }
+
+    let rules = probe_folding_rules(anchor, dir);
+    println!("Volume {} folding rules: {:?}", key, rules);
+    if let Ok(mut cache) = cache.lock() {
+        cache.insert(key, rules);
+    }
+    rules
+}
+
+// 通过 anchor 创建一个探测文件，再用大小写不同、Unicode规范化形式不同的名称查找它
+fn probe_folding_rules(anchor: &Anchor, dir: &Path) -> FoldingRules {
+    let stem = format!(".treenamer-probe-{}", uuid::Uuid::new_v4().simple());
+    let probe = dir.join(format!("{}-\u{e9}", stem));
+    if let Err(e) = anchor.create_file(&probe) {
+        // 只读的卷：退回到不写入的大小写探测
+        println!("Unable to probe folding rules in {}: {}", dir.display(), e);
+        return FoldingRules { case_insensitive: is_case_insensitive(dir), normalizing: false };
+    }
+
+    // 只改变ASCII字母的大小写，有些卷只折叠ASCII
+    let upper = dir.join(format!("{}-\u{e9}", stem.to_uppercase()));
+    let decomposed = dir.join(format!("{}-e\u{301}", stem));
+    let rules = FoldingRules {
+        case_insensitive: anchor.metadata(&upper).is_ok(),
+        normalizing: anchor.metadata(&decomposed).is_ok(),
+    };
+
+    if let Err(e) = anchor.remove_file(&probe) {
+        println!("Failed to remove probe file {}: {}", probe.display(), e);
+    }
+    rules
+}
+
+/// Whether renaming `from` to `to` only changes how the name is spelled under the
+/// folding rules of the volume, i.e. the target already resolves to the source itself.
+/// A direct rename is then a no-op or an error, so it has to go through a temporary name.
+pub(crate) fn is_fold_only_rename(anchor: &Anchor, from: &Path, to: &Path) -> bool {
+    if from == to || from.parent() != to.parent() {
+        return false;
+    }
+    let Some(parent) = from.parent() else { return false };
+    let rules = folding_rules(anchor, parent);
+    if !rules.case_insensitive && !rules.normalizing {
+        return false;
+    }
+
+    match (anchor.metadata(from), anchor.metadata(to)) {
+        (Ok(source), Ok(target)) => {
+            let same = match (source.identity, target.identity) {
+                (Some(a), Some(b)) => a == b,
+                _ => matches!((fs::canonicalize(from), fs::canonicalize(to)), (Ok(a), Ok(b)) if a == b),
+            };
+            same || (rules.case_insensitive && lowercase_name(from) == lowercase_name(to))
+        },
+        _ => false,
+    }
+}
+
+fn lowercase_name(path: &Path) -> Option<String> {
+    path.file_name().map(|name| name.to_string_lossy().to_lowercase())
+}
+
+#[cfg(unix)]
+pub(crate) fn is_same_file(a: &fs::Metadata, b: &fs::Metadata, _: &Path, _: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn is_same_file(_: &fs::Metadata, _: &fs::Metadata, a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use crate::commands::fs::{apply_fold_only_rename, execute_operations, Fallback, FileOperation};
+
+    #[test]
+    fn test_case_sensitive_volume_renames_directly() {
+        let root = std::env::temp_dir().join(format!("treenamer_volume_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("Readme.md"), "readme").unwrap();
+
+        let anchor = Anchor::open(&root).unwrap();
+        let rules = folding_rules(&anchor, &root);
+        // 探测文件不会残留
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 1);
+        if !rules.case_insensitive {
+            assert!(!is_fold_only_rename(&anchor, &root.join("Readme.md"), &root.join("README.md")));
+        }
+
+        let results = execute_operations(&root, &[FileOperation::Rename {
+            from: root.join("Readme.md").to_string_lossy().to_string(),
+            to: root.join("README.md").to_string_lossy().to_string(),
+        }]);
+        assert!(results[0].success, "{}", results[0].message);
+        let names: Vec<String> = fs::read_dir(&root).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, vec!["README.md".to_string()]);
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_normalization_only_rename_on_normalizing_volume() {
+        let root = std::env::temp_dir().join(format!("treenamer_volume_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&root).unwrap();
+        let decomposed = root.join("Cafe\u{301}.txt");
+        let composed = root.join("Caf\u{e9}.txt");
+        fs::write(&decomposed, "menu").unwrap();
+        // 在不做规范化的卷上用硬链接模拟：两种形式的名称指向同一个条目
+        let linked = match fs::hard_link(&decomposed, &composed) {
+            Ok(()) => true,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => false,
+            Err(e) => panic!("{}", e),
+        };
+
+        STUBBED_RULES.with(|rules| rules.set(Some(FoldingRules { case_insensitive: false, normalizing: true })));
+        let anchor = Anchor::open(&root).unwrap();
+        assert!(is_fold_only_rename(&anchor, &decomposed, &composed));
+        assert!(!is_fold_only_rename(&anchor, &decomposed, &root.join("CAF\u{c9}.txt")));
+
+        if linked {
+            fs::remove_file(&composed).unwrap();
+        }
+        let result = apply_fold_only_rename(&anchor, &decomposed, &composed);
+        STUBBED_RULES.with(|rules| rules.set(None));
+        assert!(result.success, "{}", result.message);
+        assert_eq!(result.fallback, Some(Fallback::TemporaryName));
+        let names: Vec<String> = fs::read_dir(&root).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, vec!["Caf\u{e9}.txt".to_string()]);
+        assert_eq!(fs::read_to_string(&composed).unwrap(), "menu");
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    // 需要 mkfs.vfat 和挂载权限，不满足时跳过
+    #[test]
+    fn test_case_only_renames_on_loop_mounted_vfat() {
+        let work = std::env::temp_dir().join(format!("treenamer_vfat_{}", uuid::Uuid::new_v4()));
+        let image = work.join("volume.img");
+        let mount_point = work.join("mnt");
+        fs::create_dir_all(&mount_point).unwrap();
+        fs::File::create(&image).unwrap().set_len(8 * 1024 * 1024).unwrap();
+
+        let run = |program: &str, args: &[&std::ffi::OsStr]| {
+            Command::new(program).args(args).output().is_ok_and(|output| output.status.success())
+        };
+        if !run("mkfs.vfat", &[image.as_os_str()])
+            || !run("mount", &["-t".as_ref(), "vfat".as_ref(), "-o".as_ref(), "loop".as_ref(), image.as_os_str(), mount_point.as_os_str()]) {
+            println!("Skipping: unable to create and mount a vfat image");
+            let _ = fs::remove_dir_all(&work);
+            return;
+        }
+
+        fs::write(mount_point.join("Readme.md"), "readme").unwrap();
+        fs::create_dir(mount_point.join("docs")).unwrap();
+        let anchor = Anchor::open(&mount_point).unwrap();
+        assert!(folding_rules(&anchor, &mount_point).case_insensitive);
+        assert!(is_fold_only_rename(&anchor, &mount_point.join("Readme.md"), &mount_point.join("README.md")));
+
+        let results = execute_operations(&mount_point, &[
+            FileOperation::Rename {
+                from: mount_point.join("Readme.md").to_string_lossy().to_string(),
+                to: mount_point.join("README.md").to_string_lossy().to_string(),
+            },
+            // 空目录只改大小写时不能被当作已存在的空目标删除
+            FileOperation::Rename {
+                from: mount_point.join("docs").to_string_lossy().to_string(),
+                to: mount_point.join("Docs").to_string_lossy().to_string(),
+            },
+        ]);
+        for result in &results {
+            assert!(result.success, "{}", result.message);
+        }
+        let mut names: Vec<String> = fs::read_dir(&mount_point).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["Docs".to_string(), "README.md".to_string()]);
+        assert_eq!(fs::read_to_string(mount_point.join("README.md")).unwrap(), "readme");
+
+        run("umount", &[mount_point.as_os_str()]);
+        let _ = fs::remove_dir_all(&work);
+    }
+}
@@ -1,7 +1,9 @@
-use serde::Serialize;
+use serde::{Serialize, Deserialize};
 use std::fmt;
+use std::io::ErrorKind;
+use std::path::Path;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum ErrorType {
     PermissionDenied,
     FileNotFound,
@@ -10,9 +12,31 @@ pub enum ErrorType {
     UserAbort,
     InvalidFilename,
     DiskFull,
+    /// Malformed input from the frontend, such as invalid tree JSON or options
+    InvalidInput,
+    /// The plan has conflicts that no collision policy resolves
+    UnresolvedConflict,
+    /// Entries changed on disk since the directory was scanned
+    ExternalModification,
+    /// The batch deleted or overwrote something that cannot be brought back
+    Irreversible,
 }
 
-#[derive(Debug, Serialize)]
+impl ErrorType {
+    /// The error type matching an I/O error.
+    pub fn of(error: &std::io::Error) -> Self {
+        match error.kind() {
+            ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem => ErrorType::PermissionDenied,
+            ErrorKind::NotFound => ErrorType::FileNotFound,
+            ErrorKind::AlreadyExists => ErrorType::PathAlreadyExists,
+            ErrorKind::StorageFull | ErrorKind::QuotaExceeded => ErrorType::DiskFull,
+            ErrorKind::InvalidFilename => ErrorType::InvalidFilename,
+            _ => ErrorType::SystemError,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
 pub struct AppError {
     pub error_type: ErrorType,
     pub message: String,
@@ -20,39 +44,33 @@ pub struct AppError {
     pub recoverable: bool,
 }
 
+impl AppError {
+    pub fn new(error_type: ErrorType, message: impl Into<String>) -> Self {
+        AppError {
+            error_type,
+            message: message.into(),
+            path: None,
+            recoverable: matches!(error_type,
+                ErrorType::PathAlreadyExists | ErrorType::UnresolvedConflict | ErrorType::ExternalModification),
+        }
+    }
+
+    /// An error for a failed I/O call, described by `message`.
+    pub fn io(error: &std::io::Error, message: impl Into<String>) -> Self {
+        Self::new(ErrorType::of(error), message)
+    }
+
+    /// Records the path the error is about.
+    pub fn at(mut self, path: impl AsRef<Path>) -> Self {
+        self.path = Some(path.as_ref().to_string_lossy().to_string());
+        self
+    }
+}
+
 impl fmt::Display for AppError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{:?}: {}", self.error_type, self.message)
     }
 }
 
-impl From<std::io::Error> for AppError {
-    fn from(error: std::io::Error) -> Self {
-        match error.kind() {
-            std::io::ErrorKind::PermissionDenied => AppError {
-                error_type: ErrorType::PermissionDenied,
-                message: "Permission denied".into(),
-                path: None,
-                recoverable: false,
-            },
-            std::io::ErrorKind::NotFound => AppError {
-                error_type: ErrorType::FileNotFound,
-                message: "File not found".into(),
-                path: None,
-                recoverable: false,
-            },
-            std::io::ErrorKind::AlreadyExists => AppError {
-                error_type: ErrorType::PathAlreadyExists,
-                message: "Path already exists".into(),
-                path: None,
-                recoverable: true,
-            },
-            _ => AppError {
-                error_type: ErrorType::SystemError,
-                message: error.to_string(),
-                path: None,
-                recoverable: false,
-            }
-        }
-    }
-} 
\ No newline at end of file
+impl std::error::Error for AppError {}
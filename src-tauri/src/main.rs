@@ -1,6 +1,8 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+use tauri::Emitter;
+
 mod commands;
 mod error;
 #[cfg(test)]
@@ -17,15 +19,52 @@ pub fn run() {
 
     tauri::Builder::default()
         .plugin(tauri_plugin_dialog::init())
-        .setup(|_app| {
+        .setup(|app| {
+            // 跨设备移动的进度转发给前端
+            let handle = app.handle().clone();
+            commands::cross_device::set_progress_sink(move |progress| {
+                let _ = handle.emit("move-progress", progress);
+            });
+            // 后台执行的批次的进度和结果
+            let handle = app.handle().clone();
+            commands::async_apply::set_apply_event_sink(move |event| {
+                let _ = match event {
+                    commands::async_apply::ApplyEvent::Progress(progress) => handle.emit("apply-progress", progress),
+                    commands::async_apply::ApplyEvent::Finished(finished) => handle.emit("apply-finished", finished),
+                };
+            });
             println!("Tauri app setup complete");
             Ok(())
         })
         // Configure Tauri to handle snake_case in Rust to camelCase in JavaScript conversion
         .invoke_handler(tauri::generate_handler![
             commands::parse_directory,
+            commands::generate_operations,
             commands::apply_operations,
+            commands::check_conflicts,
             commands::is_protected_path,
+            commands::render_tree_text,
+            commands::save_plan,
+            commands::load_plan,
+            commands::apply_plan_file,
+            commands::apply_transaction,
+            commands::start_apply,
+            commands::cancel_apply,
+            commands::list_interrupted_batches,
+            commands::resume_batch,
+            commands::rollback_batch,
+            commands::discard_batch,
+            commands::get_history,
+            commands::undo_last_batch,
+            commands::redo_batch,
+            commands::list_backups,
+            commands::inspect_backup,
+            commands::restore_backup,
+            commands::prune_backups,
+            commands::get_backup_settings,
+            commands::set_backup_settings,
+            commands::get_copy_settings,
+            commands::set_copy_settings,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
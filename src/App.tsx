@@ -5,8 +5,22 @@ import './App.css';
 import DiffViewer from './components/DiffViewer/DiffViewer';
 import MonacoEditor from './components/Editor/MonacoEditor';
 import TreeValidator from './components/FileTree/TreeValidator';
+import { describeFailedTransaction, errorMessage, HistoryReport, InterruptedBatch, isAppError, TransactionReport } from './types/Transaction';
 import { TreeNode } from './types/TreeNode';
-import { checkDuplicatesAndMerges, formatTreeToText, parseTextToTree, validateRootNameChange } from './utils/treeUtils';
+import { applyInBackground, cancelApply, describeFailedOperations } from './utils/applyUtils';
+import { checkDuplicatesAndMerges, formatTreeToText, isValidRootName, parseTextToTree, renamedRootPath, validateRootNameChange } from './utils/treeUtils';
+
+// Scan options sent to parse_directory; these match the backend defaults
+const DIRECTORY_OPTIONS = {
+  max_depth: 10,
+  exclude_pattern: 'node_modules|.git',
+  follow_symlinks: false,
+  show_hidden: false,
+};
+
+// Read-only columns rendered next to each name when details are shown
+const METADATA_COLUMNS = { size: true, modified: true, item_count: true };
+const METADATA_COLUMN_COUNT = Object.values(METADATA_COLUMNS).filter(Boolean).length;
 
 function App() {
   // Core state
@@ -24,6 +38,14 @@ function App() {
   });
   // Add state to control diff view visibility
   const [showDiffView, setShowDiffView] = useState<boolean>(false);
+  // Whether size, modified date and item count are shown next to each name
+  const [showMetadata, setShowMetadata] = useState<boolean>(false);
+  const showMetadataRef = useRef<boolean>(false);
+  // Number of metadata columns the current tree text was rendered with
+  const columnCountRef = useRef<number>(0);
+  // Progress of the batch being applied in the background
+  const [applyProgress, setApplyProgress] = useState<{ batchId: string | null; completed: number; failed: number; total: number } | null>(null);
+  const [isCancelling, setIsCancelling] = useState<boolean>(false);
   
   // Reference to original text for true comparison
   const originalTextRef = useRef<string>('');
@@ -42,6 +64,49 @@ function App() {
     treeJsonRef.current = treeJson;
   }, [treeJson]);
 
+  // Offer to resume or roll back a batch that was interrupted or cancelled.
+  // Returns the report of what was done, or null when the batch was left as it is.
+  const settleInterruptedBatch = async (batch: InterruptedBatch, reason: string): Promise<TransactionReport | null> => {
+    const summary = `${batch.base}\n已完成 ${batch.completed} / ${batch.operations.length} 个操作`;
+    const canResume = !batch.rolling_back;
+    const resume = canResume && await confirm(
+      `${reason}:\n${summary}\n\n是否继续执行剩余的操作？`,
+      { title: "恢复中断的变更", kind: "warning" }
+    );
+    const rollback = !resume && await confirm(
+      `是否撤销这批变更中已经执行的操作？\n${summary}`,
+      { title: "恢复中断的变更", kind: "warning" }
+    );
+    if (!resume && !rollback) {
+      showNotification('warning', '变更保持在中断的状态，下次启动时可以继续执行或撤销');
+      return null;
+    }
+
+    const report = await invoke<TransactionReport>(resume ? 'resume_batch' : 'rollback_batch', { batchId: batch.id });
+    if (resume && !report.committed || rollback && !report.rolled_back) {
+      showNotification('error', describeFailedTransaction(report));
+    } else {
+      showNotification('success', resume ? '中断的变更已继续执行完成' : '中断的变更已撤销');
+    }
+    return report;
+  };
+
+  // Offer to resume or roll back batches that were interrupted by a crash
+  useEffect(() => {
+    const recoverInterruptedBatches = async () => {
+      try {
+        const batches = await invoke<InterruptedBatch[]>('list_interrupted_batches');
+        for (const batch of batches) {
+          await settleInterruptedBatch(batch, '上次的变更没有执行完');
+        }
+      } catch (err) {
+        console.error('Error recovering interrupted batches:', err);
+        showNotification('error', `恢复中断的变更时出错: ${errorMessage(err)}`);
+      }
+    };
+    recoverInterruptedBatches();
+  }, []);
+
   // Update window size on resize
   useEffect(() => {
     const handleResize = () => {
@@ -57,13 +122,24 @@ function App() {
 
   // Format JSON to text when treeJson changes
   useEffect(() => {
-    if (treeJson) {
+    if (!treeJson) {
+      return;
+    }
+    let stale = false;
+    const formatTree = async () => {
       try {
         // Set flag to skip validation for programmatic changes
         skipValidationRef.current = true;
         
-        const parsedTree = JSON.parse(treeJson) as TreeNode;
-        const formattedText = formatTreeToText(parsedTree);
+        // The metadata columns are laid out by the backend, which also formats sizes and dates
+        const columnCount = showMetadataRef.current ? METADATA_COLUMN_COUNT : 0;
+        const formattedText = columnCount > 0
+          ? await invoke<string>('render_tree_text', { dirPath: directoryPath, treeJson, columns: METADATA_COLUMNS })
+          : formatTreeToText(JSON.parse(treeJson) as TreeNode);
+        if (stale) {
+          return;
+        }
+        columnCountRef.current = columnCount;
         
         // Set state variables and reference
         setEditedTreeText(formattedText);
@@ -80,7 +156,11 @@ function App() {
         console.error('Error formatting tree JSON:', error);
         setEditedTreeText('Error formatting tree data');
       }
-    }
+    };
+    formatTree();
+    return () => {
+      stale = true;
+    };
   }, [treeJson]);
 
   const handleBrowse = async () => {
@@ -101,7 +181,7 @@ function App() {
       }
     } catch (err) {
       console.error('Error in handleBrowse:', err);
-      setError(`打开目录时出错: ${err}`);
+      setError(`打开目录时出错: ${errorMessage(err)}`);
     }
   };
 
@@ -129,7 +209,7 @@ function App() {
       setIsLoading(true);
       
       // Parse the edited text back to a tree structure
-      const parsedTree = parseTextToTree(editedTreeText, treeJsonRef.current);
+      const parsedTree = parseTextToTree(editedTreeText, treeJsonRef.current, columnCountRef.current);
       
       if (!parsedTree) {
         throw new Error('Failed to parse tree structure');
@@ -184,32 +264,127 @@ function App() {
         }
       }
       
-      // Call the backend to apply the changes
-      await invoke('apply_operations', {
-        dirPath: directoryPath,
-        originalTree: treeJsonRef.current,
-        modifiedTree: JSON.stringify(parsedTree)
-      });
+      // Apply in the background: every operation reports progress and the batch can be cancelled
+      setApplyProgress({ batchId: null, completed: 0, failed: 0, total: 0 });
+      const started: { batchId: string | null } = { batchId: null };
+      let report = await applyInBackground(
+        {
+          dirPath: directoryPath,
+          originalTree: treeJsonRef.current,
+          modifiedTree: JSON.stringify(parsedTree)
+        },
+        progress => setApplyProgress({
+          batchId: progress.batch_id,
+          completed: progress.completed,
+          failed: progress.failed,
+          total: progress.total
+        }),
+        batchId => {
+          started.batchId = batchId;
+          setApplyProgress(current => ({ completed: 0, failed: 0, total: 0, ...current, batchId }));
+        }
+      );
+      setApplyProgress(null);
       
-      // Reload directory to get fresh tree
-      await loadDirectory(directoryPath);
+      // A cancelled batch stays in the journal: continue it, roll it back or leave it for later
+      if (report.cancelled) {
+        const batches = await invoke<InterruptedBatch[]>('list_interrupted_batches');
+        const batch = batches.find(candidate => candidate.id === started.batchId);
+        const settled = batch ? await settleInterruptedBatch(batch, '变更已取消') : null;
+        if (settled) {
+          report = settled;
+        }
+      } else if (!report.committed) {
+        throw new Error(describeFailedTransaction(report));
+      }
+      
+      // Reload directory to get fresh tree; a renamed root is reopened at its new path once the batch completed
+      const originalRootName = (JSON.parse(treeJsonRef.current) as TreeNode).name;
+      const nextPath = report.committed && !report.cancelled && parsedTree.name !== originalRootName
+        ? renamedRootPath(directoryPath, parsedTree.name)
+        : directoryPath;
+      setDirectoryPath(nextPath);
+      await loadDirectory(nextPath);
       
       // Close diff view if it's open
       if (showDiffView) {
         setShowDiffView(false);
       }
       
-      // Show success notification
-      showNotification('success', '变更已成功应用');
+      if (report.cancelled || !report.committed) {
+        return;
+      }
+      // Failed operations do not stop the others; show them, or what still needs to be looked at
+      const attention = report.results.filter(result => result.needs_attention);
+      if (report.summary.failed > 0) {
+        showNotification('warning', `部分操作失败，其余变更已应用（可以撤销）:\n${describeFailedOperations(report)}`);
+      } else if (attention.length > 0) {
+        showNotification('warning', `变更已应用，但以下操作需要处理:\n${attention.map(result => result.message).join('\n')}`);
+      } else {
+        showNotification('success', '变更已成功应用');
+      }
     } catch (error) {
       console.error('Error applying changes:', error);
-      setError(`应用变更时出错: ${error}`);
-      showNotification('error', `应用变更时出错: ${error}`);
+      // 扫描之后目录在外部被修改：提示重新扫描，而不是重试
+      const hint = isAppError(error) && error.error_type === 'ExternalModification' ? '\n请刷新目录后重新编辑' : '';
+      setError(`应用变更时出错: ${errorMessage(error)}${hint}`);
+      showNotification('error', `应用变更时出错: ${errorMessage(error)}${hint}`);
     } finally {
+      setApplyProgress(null);
+      setIsCancelling(false);
       setIsLoading(false);
     }
   };
+
+  // Stop the batch running in the background before its next operation
+  const handleCancelApply = async () => {
+    if (!applyProgress?.batchId) {
+      return;
+    }
+    try {
+      setIsCancelling(true);
+      await cancelApply(applyProgress.batchId);
+    } catch (error) {
+      // The batch may have finished in the meantime
+      console.error('Error cancelling apply:', error);
+      setIsCancelling(false);
+    }
+  };
   
+  // Undo the last applied batch or redo the last undone one
+  const handleHistory = async (command: 'undo_last_batch' | 'redo_batch') => {
+    if (isEdited) {
+      const confirmed = await confirm(
+        "您有未应用的修改。继续将丢失这些修改。是否继续？",
+        { title: command === 'undo_last_batch' ? "确认撤销" : "确认重做", kind: "warning" }
+      );
+      if (!confirmed) {
+        return;
+      }
+    }
+
+    try {
+      setIsLoading(true);
+      const result = await invoke<HistoryReport>(command);
+      if (result.divergences.length > 0) {
+        showNotification('warning', `磁盘上的文件在变更之后已被修改，未做任何改动:\n${result.divergences.join('\n')}`);
+        return;
+      }
+      if (result.report && !result.report.committed) {
+        throw new Error(describeFailedTransaction(result.report));
+      }
+
+      setDirectoryPath(result.directory);
+      await loadDirectory(result.directory);
+      showNotification('success', command === 'undo_last_batch' ? '已撤销上一次的变更' : '已重做变更');
+    } catch (error) {
+      console.error('Error in undo/redo:', error);
+      showNotification('error', `${command === 'undo_last_batch' ? '撤销' : '重做'}时出错: ${errorMessage(error)}`);
+    } finally {
+      setIsLoading(false);
+    }
+  };
+
   // Validate root name in edited text
   const validateRootName = (text: string) => {
     // Skip validation if flag is set
@@ -225,12 +400,13 @@ function App() {
     try {
       const validationResult = validateRootNameChange(text, treeJsonRef.current);
       
-      if (validationResult.changed) {
+      // Renaming the root is allowed, only names that cannot be used are reverted
+      if (validationResult.changed && !isValidRootName(validationResult.newName.replace(/\/$/, ''))) {
         // Set the skip flag to avoid re-triggering validation during the reset
         skipValidationRef.current = true;
         
         // Show warning message
-        showNotification('warning', `根目录名称不能被修改，已自动恢复为原始名称: ${validationResult.originalName}`);
+        showNotification('warning', `根目录名称无效，已自动恢复为原始名称: ${validationResult.originalName}`);
         
         // Reset the root name in the editor by replacing just the first line
         const lines = text.split('\n');
@@ -260,11 +436,12 @@ function App() {
       
       console.log('Loading directory:', path);
       console.log('Current treeJson before loading:', treeJson ? treeJson.substring(0, 50) + '...' : 'empty');
-      console.log('Calling parse_directory with params:', { dirPath: path });
+      const options = { ...DIRECTORY_OPTIONS, include_metadata: showMetadataRef.current };
+      console.log('Calling parse_directory with params:', { dirPath: path, options });
       
       try {
         // Get tree directly from backend
-        const result = await invoke<string>('parse_directory', { dirPath: path });
+        const result = await invoke<string>('parse_directory', { dirPath: path, options });
         console.log('Parse directory successful, received data length:', result?.length || 0);
         console.log('New treeJson data:', result.substring(0, 50) + '...');
         
@@ -275,19 +452,38 @@ function App() {
         console.error('Invoke error details:', {
           error: invokeErr,
           errorType: typeof invokeErr,
-          errorMessage: invokeErr instanceof Error ? invokeErr.message : String(invokeErr),
+          errorMessage: errorMessage(invokeErr),
           errorStack: invokeErr instanceof Error ? invokeErr.stack : undefined
         });
         throw invokeErr;
       }
     } catch (err) {
       console.error('Error loading directory:', err);
-      setError(`解析目录时出错: ${err}`);
+      setError(`解析目录时出错: ${errorMessage(err)}`);
     } finally {
       setIsLoading(false);
     }
   };
 
+  // Show or hide the metadata columns; they are only collected when shown, so the directory is scanned again
+  const handleToggleMetadata = async () => {
+    if (isEdited) {
+      const confirmed = await confirm(
+        "您有未应用的修改。继续将丢失这些修改。是否继续？",
+        { title: "确认刷新", kind: "warning" }
+      );
+      if (!confirmed) {
+        return;
+      }
+    }
+
+    showMetadataRef.current = !showMetadataRef.current;
+    setShowMetadata(showMetadataRef.current);
+    if (treeJson && directoryPath.trim()) {
+      await loadDirectory(directoryPath);
+    }
+  };
+
   // Show notification message
   const showNotification = (type: string, message: string) => {
     setNotification({ 
@@ -387,6 +583,15 @@ function App() {
                 <path d="M21.5 2v6h-6M2.5 22v-6h6M2 11.5a10 10 0 0 1 18.8-4.3M22 12.5a10 10 0 0 1-18.8 4.2"/>
               </svg>
             </button>
+            <label className="metadata-toggle" title="在名称旁显示大小、修改时间和条目数（只读）">
+              <input
+                type="checkbox"
+                checked={showMetadata}
+                onChange={handleToggleMetadata}
+                disabled={isLoading}
+              />
+              详细信息
+            </label>
           </div>
 
           {error && <div className="error-message">{error}</div>}
@@ -418,6 +623,22 @@ function App() {
 
               {/* Action buttons */}
               <div className="action-buttons">
+                <button 
+                  className="button secondary" 
+                  onClick={() => handleHistory('undo_last_batch')}
+                  disabled={isLoading}
+                  title="撤销上一次应用的变更"
+                >
+                  撤销
+                </button>
+                <button 
+                  className="button secondary" 
+                  onClick={() => handleHistory('redo_batch')}
+                  disabled={isLoading}
+                  title="重做上一次撤销的变更"
+                >
+                  重做
+                </button>
                 <button 
                   className="button secondary" 
                   onClick={handleShowDiff}
@@ -457,6 +678,27 @@ function App() {
           )}
         </main>
       )}
+
+      {/* Progress of the batch being applied, also shown over the diff view */}
+      {applyProgress && (
+        <div className="apply-progress">
+          <progress
+            value={applyProgress.completed}
+            max={Math.max(applyProgress.total, 1)}
+          />
+          <span>
+            {isCancelling ? '正在取消…' : `正在应用 ${applyProgress.completed} / ${applyProgress.total || '?'}`}
+            {applyProgress.failed > 0 && `（${applyProgress.failed} 个失败）`}
+          </span>
+          <button
+            className="button secondary"
+            onClick={handleCancelApply}
+            disabled={!applyProgress.batchId || isCancelling}
+          >
+            取消
+          </button>
+        </div>
+      )}
     </div>
   );
 }
@@ -38,6 +38,16 @@ vi.mock('../components/FileTree/TreeValidator', () => ({
   ))
 }));
 
+// Listeners registered through the Tauri event API, so the mocked backend can send events
+const eventListeners = vi.hoisted(() => new Map<string, (event: { payload: unknown }) => void>());
+
+vi.mock('@tauri-apps/api/event', () => ({
+  listen: vi.fn().mockImplementation((name: string, handler: (event: { payload: unknown }) => void) => {
+    eventListeners.set(name, handler);
+    return Promise.resolve(() => eventListeners.delete(name));
+  })
+}));
+
 // Mock the Tauri core invoke function
 vi.mock('@tauri-apps/api/core', () => ({
   invoke: vi.fn().mockImplementation((cmd, args) => {
@@ -69,8 +79,35 @@ vi.mock('@tauri-apps/api/core', () => ({
         ]
       });
     }
-    if (cmd === 'apply_operations') {
-      return Promise.resolve();
+    if (cmd === 'render_tree_text') {
+      return Promise.resolve('test_dir/\n├── file1.txt  ┊ 12 B\n└── dir1/      ┊     \n    └── file2.txt ┊ 3 B\n');
+    }
+    if (cmd === 'list_interrupted_batches') {
+      return Promise.resolve([]);
+    }
+    if (cmd === 'start_apply') {
+      // The batch runs one operation and finishes right after it was started
+      setTimeout(() => {
+        eventListeners.get('apply-progress')?.({
+          payload: { batch_id: 'batch-1', index: 0, operation: {}, result: {}, completed: 1, failed: 0, total: 1 }
+        });
+        eventListeners.get('apply-finished')?.({
+          payload: {
+            batch_id: 'batch-1',
+            report: {
+              committed: true,
+              results: [],
+              summary: { total: 1, ok: 1, skipped: 0, failed: 0, partially_done: 0, fallbacks: 0, duration_ms: 1 },
+              failed_at: null,
+              rollback: [],
+              rolled_back: false,
+              cleanup_errors: [],
+              cancelled: false
+            }
+          }
+        });
+      }, 0);
+      return Promise.resolve('batch-1');
     }
     return Promise.reject(new Error(`Unknown command: ${cmd}`));
   })
@@ -192,14 +229,74 @@ describe('App Component', () => {
     });
   });
 
-  it('shows error notification when API calls fail', async () => {
+  it('scans with metadata and renders the columns when details are shown', async () => {
+    const { invoke } = await import('@tauri-apps/api/core');
+    
+    render(<App />);
+    
+    const input = screen.getByPlaceholderText('输入目录路径或点击浏览选择目录');
+    fireEvent.change(input, { target: { value: '/test/manual/path' } });
+    fireEvent.click(screen.getByTitle('刷新'));
+    await waitFor(() => {
+      expect(screen.getByTestId('monaco-editor')).toBeInTheDocument();
+    });
+    
+    fireEvent.click(screen.getByLabelText(/详细信息/));
+    
+    await waitFor(() => {
+      expect(invoke).toHaveBeenCalledWith('parse_directory', expect.objectContaining({
+        options: expect.objectContaining({ include_metadata: true })
+      }));
+      expect(invoke).toHaveBeenCalledWith('render_tree_text', expect.objectContaining({
+        columns: { size: true, modified: true, item_count: true }
+      }));
+      expect(screen.getByTestId('mock-editor-textarea')).toHaveValue(
+        'test_dir/\n├── file1.txt  ┊ 12 B\n└── dir1/      ┊     \n    └── file2.txt ┊ 3 B\n'
+      );
+    });
+  });
+
+  it('applies changes in the background and reports the outcome', async () => {
     const { invoke } = await import('@tauri-apps/api/core');
     
-    // Override the mock to simulate an error
-    vi.mocked(invoke).mockRejectedValueOnce(new Error('API error'));
+    render(<App />);
+    
+    const input = screen.getByPlaceholderText('输入目录路径或点击浏览选择目录');
+    fireEvent.change(input, { target: { value: '/test/manual/path' } });
+    fireEvent.click(screen.getByTitle('刷新'));
+    await waitFor(() => {
+      expect(screen.getByTestId('monaco-editor')).toBeInTheDocument();
+    });
+    
+    fireEvent.change(screen.getByTestId('mock-editor-textarea'), {
+      target: { value: 'test_dir/\n├── renamed.txt\n└── dir1/\n    └── file2.txt\n' }
+    });
+    await waitFor(() => {
+      expect(screen.getByRole('button', { name: /应用修改/i })).not.toBeDisabled();
+    });
+    fireEvent.click(screen.getByRole('button', { name: /应用修改/i }));
+    
+    await waitFor(() => {
+      expect(invoke).toHaveBeenCalledWith('start_apply', expect.objectContaining({ dirPath: '/test/manual/path' }));
+      expect(screen.getByText('变更已成功应用')).toBeInTheDocument();
+    });
+    expect(invoke).not.toHaveBeenCalledWith('apply_transaction', expect.anything());
+  });
+
+  it('shows error notification when API calls fail', async () => {
+    const { invoke } = await import('@tauri-apps/api/core');
     
     render(<App />);
     
+    // Override the mock to simulate an error (after the startup check for interrupted batches)
+    // Commands reject with an AppError object
+    vi.mocked(invoke).mockRejectedValueOnce({
+      error_type: 'FileNotFound',
+      message: 'API error',
+      path: '/test/error/path',
+      recoverable: false,
+    });
+    
     // Set up directory and trigger load
     const input = screen.getByPlaceholderText('输入目录路径或点击浏览选择目录');
     fireEvent.change(input, { target: { value: '/test/error/path' } });
@@ -13,13 +13,15 @@ const DirectorySettings = ({ onClose }: DirectorySettingsProps) => {
   const [excludePattern, setExcludePattern] = useState<string>('node_modules|.git');
   const [followSymlinks, setFollowSymlinks] = useState<boolean>(false);
   const [showHidden, setShowHidden] = useState<boolean>(false);
+  const [includeMetadata, setIncludeMetadata] = useState<boolean>(false);
   
   const handleApply = async () => {
     await loadDirectory({
       maxDepth,
       excludePattern,
       followSymlinks,
-      showHidden
+      showHidden,
+      includeMetadata
     });
     onClose();
   };
@@ -71,6 +73,16 @@ const DirectorySettings = ({ onClose }: DirectorySettingsProps) => {
         <label htmlFor="show-hidden">Show Hidden Files</label>
       </div>
       
+      <div className="settings-group checkbox">
+        <input
+          id="include-metadata"
+          type="checkbox"
+          checked={includeMetadata}
+          onChange={(e) => setIncludeMetadata(e.target.checked)}
+        />
+        <label htmlFor="include-metadata">Show Size, Date and Item Count</label>
+      </div>
+      
       <div className="settings-actions">
         <button onClick={onClose}>Cancel</button>
         <button onClick={handleApply} className="primary">Apply</button>
@@ -36,12 +36,14 @@ describe('DirectorySettings Component', () => {
     expect(screen.getByText(/exclude pattern/i)).toBeInTheDocument();
     expect(screen.getByText(/follow symbolic links/i)).toBeInTheDocument();
     expect(screen.getByText(/show hidden files/i)).toBeInTheDocument();
+    expect(screen.getByText(/show size, date and item count/i)).toBeInTheDocument();
     
     // Verify initial values - match the defaults in the component
     expect(screen.getByLabelText(/maximum depth/i)).toHaveValue(10);
     expect(screen.getByLabelText(/exclude pattern/i)).toHaveValue('node_modules|.git');
     expect(screen.getByLabelText(/follow symbolic links/i)).not.toBeChecked();
     expect(screen.getByLabelText(/show hidden files/i)).not.toBeChecked();
+    expect(screen.getByLabelText(/show size, date and item count/i)).not.toBeChecked();
   });
   
   it('should call loadDirectory and close when Apply is clicked', async () => {
@@ -74,7 +76,8 @@ describe('DirectorySettings Component', () => {
         maxDepth: 15,
         excludePattern: 'node_modules|.git|.vscode',
         followSymlinks: false,
-        showHidden: true
+        showHidden: true,
+        includeMetadata: false
       });
     });
     
@@ -1,11 +1,16 @@
 import { invoke } from '@tauri-apps/api/core';
 import { create } from 'zustand';
+import { ApplyProgress, describeFailedTransaction, errorMessage } from '../types/Transaction';
+import { applyInBackground, cancelApply, describeFailedOperations } from '../utils/applyUtils';
+import { renamedRootPath } from '../utils/treeUtils';
 
 interface DirectoryOptions {
   maxDepth: number;
   excludePattern: string;
   followSymlinks: boolean;
   showHidden: boolean;
+  /** Collect size, modified time and item count for the read-only columns */
+  includeMetadata: boolean;
 }
 
 // This interface matches the Rust struct
@@ -14,6 +19,7 @@ interface RustDirectoryOptions {
   exclude_pattern: string;
   follow_symlinks: boolean;
   show_hidden: boolean;
+  include_metadata: boolean;
 }
 
 interface DirectoryState {
@@ -21,9 +27,13 @@ interface DirectoryState {
   originalTree: string;
   isLoading: boolean;
   error: string | null;
+  /** Batch being applied in the background and its latest progress */
+  applyBatchId: string | null;
+  applyProgress: ApplyProgress | null;
   setDirectoryPath: (path: string) => void;
   loadDirectory: (options?: Partial<DirectoryOptions>) => Promise<void>;
   applyChanges: (modifiedTree: string) => Promise<void>;
+  cancelApply: () => Promise<void>;
   resetError: () => void;
 }
 
@@ -32,6 +42,7 @@ const defaultOptions: DirectoryOptions = {
   excludePattern: 'node_modules|.git',
   followSymlinks: false,
   showHidden: false,
+  includeMetadata: false,
 };
 
 export const useDirectoryStore = create<DirectoryState>((set, get) => ({
@@ -39,6 +50,8 @@ export const useDirectoryStore = create<DirectoryState>((set, get) => ({
   originalTree: '',
   isLoading: false,
   error: null,
+  applyBatchId: null,
+  applyProgress: null,
 
   setDirectoryPath: (path) => set({ directoryPath: path }),
 
@@ -61,7 +74,8 @@ export const useDirectoryStore = create<DirectoryState>((set, get) => ({
         max_depth: mergedOptions.maxDepth,
         exclude_pattern: mergedOptions.excludePattern,
         follow_symlinks: mergedOptions.followSymlinks,
-        show_hidden: mergedOptions.showHidden
+        show_hidden: mergedOptions.showHidden,
+        include_metadata: mergedOptions.includeMetadata
       };
       console.log('Converted Rust options:', rustOptions);
       
@@ -84,7 +98,7 @@ export const useDirectoryStore = create<DirectoryState>((set, get) => ({
         console.error('Invoke error details:', {
           error: invokeErr,
           errorType: typeof invokeErr,
-          errorMessage: invokeErr instanceof Error ? invokeErr.message : String(invokeErr),
+          errorMessage: errorMessage(invokeErr),
           errorStack: invokeErr instanceof Error ? invokeErr.stack : undefined,
           params: invokeParams
         });
@@ -92,7 +106,7 @@ export const useDirectoryStore = create<DirectoryState>((set, get) => ({
       }
     } catch (e) {
       console.error('Error loading directory:', e);
-      set({ error: String(e), isLoading: false });
+      set({ error: errorMessage(e), isLoading: false });
     }
   },
 
@@ -116,12 +130,23 @@ export const useDirectoryStore = create<DirectoryState>((set, get) => ({
       set({ isLoading: true, error: null });
       
       console.log('Applying operations...');
-      // Call the Rust function to apply the changes
-      await invoke('apply_operations', { 
-        dirPath: directoryPath,
-        originalTree: originalTree,
-        modifiedTree: modifiedTree
-      });
+      // Apply in the background; progress is kept in the store and the batch can be cancelled
+      const report = await applyInBackground(
+        { dirPath: directoryPath, originalTree, modifiedTree },
+        progress => set({ applyProgress: progress }),
+        batchId => set({ applyBatchId: batchId })
+      );
+      set({ applyBatchId: null, applyProgress: null });
+      // A cancelled batch stays in the journal and is offered to be resumed or rolled back
+      if (report.cancelled) {
+        throw new Error(`Applying was cancelled after ${report.summary.total} operations; resume or roll back the interrupted batch`);
+      }
+      if (!report.committed) {
+        throw new Error(describeFailedTransaction(report));
+      }
+      if (report.summary.failed > 0) {
+        console.warn('Some operations failed:', describeFailedOperations(report));
+      }
       
       console.log('Operations applied successfully, reloading directory...');
       // Reload the directory to reflect changes
@@ -129,16 +154,25 @@ export const useDirectoryStore = create<DirectoryState>((set, get) => ({
         max_depth: defaultOptions.maxDepth,
         exclude_pattern: defaultOptions.excludePattern,
         follow_symlinks: defaultOptions.followSymlinks,
-        show_hidden: defaultOptions.showHidden
+        show_hidden: defaultOptions.showHidden,
+        include_metadata: defaultOptions.includeMetadata
       };
       
+      // A renamed root is reopened at its new path
+      const originalRootName = JSON.parse(originalTree).name;
+      const modifiedRootName = JSON.parse(modifiedTree).name;
+      const nextPath = modifiedRootName !== originalRootName
+        ? renamedRootPath(directoryPath, modifiedRootName)
+        : directoryPath;
+      
       const result = await invoke<string>('parse_directory', { 
-        dirPath: directoryPath,
+        dirPath: nextPath,
         options: rustOptions
       });
       
       console.log('Directory reloaded, updating state...');
       set({ 
+        directoryPath: nextPath,
         originalTree: result, 
         isLoading: false,
         error: null
@@ -148,10 +182,17 @@ export const useDirectoryStore = create<DirectoryState>((set, get) => ({
       return Promise.resolve();
     } catch (e) {
       console.error('Error applying changes:', e);
-      set({ error: String(e), isLoading: false });
+      set({ error: errorMessage(e), isLoading: false, applyBatchId: null, applyProgress: null });
       return Promise.reject(e);
     }
   },
 
+  cancelApply: async () => {
+    const { applyBatchId } = get();
+    if (applyBatchId) {
+      await cancelApply(applyBatchId);
+    }
+  },
+
   resetError: () => set({ error: null }),
 })); 
\ No newline at end of file
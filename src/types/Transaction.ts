@@ -0,0 +1,150 @@
+export type OperationStatus = 'Ok' | 'Skipped' | 'Failed' | 'PartiallyDone';
+
+export type ErrorType =
+  | 'PermissionDenied'
+  | 'FileNotFound'
+  | 'PathAlreadyExists'
+  | 'SystemError'
+  | 'UserAbort'
+  | 'InvalidFilename'
+  | 'DiskFull'
+  | 'InvalidInput'
+  | 'UnresolvedConflict'
+  | 'ExternalModification'
+  | 'Irreversible';
+
+export interface AppError {
+  error_type: ErrorType;
+  message: string;
+  path: string | null;
+  recoverable: boolean;
+}
+
+export interface OperationResult {
+  success: boolean;
+  /** Something was left for the user to sort out, e.g. a source that could not be removed */
+  needs_attention: boolean;
+  message: string;
+  status: OperationStatus;
+  kind: string | null;
+  node_id: string | null;
+  from: string | null;
+  to: string | null;
+  error: AppError | null;
+  duration_ms: number;
+  fallback: 'CrossDeviceCopy' | 'TemporaryName' | null;
+}
+
+export interface BatchSummary {
+  total: number;
+  ok: number;
+  skipped: number;
+  failed: number;
+  partially_done: number;
+  fallbacks: number;
+  duration_ms: number;
+}
+
+export interface RollbackStep {
+  index: number;
+  action: Record<string, Record<string, string>>;
+  success: boolean;
+  message: string;
+  error: AppError | null;
+}
+
+export interface TransactionReport {
+  committed: boolean;
+  results: OperationResult[];
+  summary: BatchSummary;
+  failed_at: number | null;
+  rollback: RollbackStep[];
+  rolled_back: boolean;
+  cleanup_errors: string[];
+  /** Cancelled between two operations; the batch stays in the journal to be resumed or rolled back */
+  cancelled: boolean;
+}
+
+export interface InterruptedBatch {
+  id: string;
+  base: string;
+  started: number;
+  atomic: boolean;
+  operations: Record<string, Record<string, string>>[];
+  completed: number;
+  interrupted: number | null;
+  committed: boolean;
+  rolling_back: boolean;
+}
+
+export interface HistoryReport {
+  id: string;
+  directory: string;
+  divergences: string[];
+  report: TransactionReport | null;
+}
+
+/**
+ * Sent as `apply-progress` after every operation of a batch started with `start_apply`.
+ */
+export interface ApplyProgress {
+  batch_id: string;
+  index: number;
+  operation: Record<string, Record<string, string>>;
+  result: OperationResult;
+  completed: number;
+  failed: number;
+  total: number;
+}
+
+/**
+ * Sent as `apply-finished` once a batch started with `start_apply` has stopped.
+ */
+export interface ApplyFinished {
+  batch_id: string;
+  report: TransactionReport;
+}
+
+/**
+ * Progress of a move that copies data to another filesystem, sent as `move-progress` events.
+ */
+export interface MoveProgress {
+  from: string;
+  to: string;
+  current: string;
+  bytes_copied: number;
+  bytes_total: number;
+}
+
+/**
+ * Whether a value rejected by a command is an AppError.
+ */
+export const isAppError = (error: unknown): error is AppError =>
+  typeof error === 'object' && error !== null && 'error_type' in error && 'message' in error;
+
+/**
+ * The message of an error rejected by a command, or of any other thrown value.
+ */
+export const errorMessage = (error: unknown): string => {
+  if (isAppError(error)) {
+    return error.message;
+  }
+  return error instanceof Error ? error.message : String(error);
+};
+
+/**
+ * Describes why a transaction was not committed and what the rollback left behind.
+ */
+export const describeFailedTransaction = (report: TransactionReport): string => {
+  const failed = report.failed_at !== null ? report.results[report.failed_at] : undefined;
+  const lines = [`操作失败: ${failed ? failed.message : '未知错误'}`];
+  if (report.rolled_back) {
+    lines.push('所有已执行的操作均已撤销');
+  } else {
+    lines.push('部分操作未能撤销:');
+    report.rollback
+      .filter(step => !step.success)
+      .forEach(step => lines.push(`  ${step.message}`));
+  }
+  return lines.join('\n');
+};
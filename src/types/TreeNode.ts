@@ -1,3 +1,9 @@
+export interface NodeMetadata {
+  size?: number;
+  modified?: number;
+  item_count?: number;
+}
+
 export interface TreeNode {
   id: string;
   name: string;
@@ -5,4 +11,5 @@ export interface TreeNode {
   children: TreeNode[];
   oldPath?: string;
   newPath?: string;
+  metadata?: NodeMetadata;
 } 
\ No newline at end of file
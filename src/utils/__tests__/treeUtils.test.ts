@@ -12,7 +12,8 @@ import {
   buildIdMapping,
   extractNodeName,
   formatTreeToText,
-  parseTextToTree
+  parseTextToTree,
+  renamedRootPath
 } from '../treeUtils';
 
 describe('Tree Utility Functions', () => {
@@ -89,6 +90,31 @@ describe('Tree Utility Functions', () => {
       expect(result.name).toBe('─example-file.txt');
       expect(result.is_dir).toBe(false);
     });
+    
+    it('should ignore read-only metadata columns', () => {
+      const result = extractNodeName('│   ├── photos 2024/   ┊   2.0 KB ┊ 2024-05-01 12:00 ┊ 3 items', 3);
+      expect(result.name).toBe('photos 2024');
+      expect(result.is_dir).toBe(true);
+    });
+    
+    it('should keep the column separator when it is part of a name', () => {
+      expect(extractNodeName('├── a ┊ b.txt').name).toBe('a ┊ b.txt');
+      expect(extractNodeName('├── a┊b.txt  ┊   2.0 KB ┊ 2024-05-01 12:00', 2).name).toBe('a┊b.txt');
+      expect(extractNodeName('├── a ┊ 12 B.txt  ┊     12 B', 1).name).toBe('a ┊ 12 B.txt');
+    });
+    
+    it('should ignore edited metadata columns whatever they contain', () => {
+      for (const line of [
+        '├── notes.txt  ┊ 2.0KB ┊ 2024-05-01 12:00 ┊',
+        '├── notes.txt  ┊ abc ┊ 2024-13-45 99 ┊ lots of items',
+        '├── notes.txt┊┊┊',
+        '├── notes.txt  ┊ 12 B',
+      ]) {
+        expect(extractNodeName(line, 3).name).toBe('notes.txt');
+      }
+      // Lines added without columns are kept as they are
+      expect(extractNodeName('├── new.txt', 3).name).toBe('new.txt');
+    });
   });
 
   // Test for buildIdMapping
@@ -298,6 +324,25 @@ describe('Tree Utility Functions', () => {
       expect(result?.children.length).toBe(1);
       expect(result?.children[0].name).toBe('─example-file.txt');
     });
+
+    it('should keep a renamed root and reject invalid root names', () => {
+      const originalJson = JSON.stringify({
+        id: 'root-id',
+        name: 'root',
+        is_dir: true,
+        children: []
+      });
+
+      const renamed = parseTextToTree(`photos 2024/\n└── a.txt\n`, originalJson);
+      expect(renamed?.id).toBe('root-id');
+      expect(renamed?.name).toBe('photos 2024');
+
+      const invalid = parseTextToTree(`../other/\n└── a.txt\n`, originalJson);
+      expect(invalid?.name).toBe('root');
+
+      expect(renamedRootPath('/home/user/root', 'photos 2024')).toBe('/home/user/photos 2024');
+      expect(renamedRootPath('C:\\Users\\me\\root\\', 'photos')).toBe('C:\\Users\\me\\photos');
+    });
   });
 });
 
@@ -0,0 +1,66 @@
+import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
+import { ApplyFinished, ApplyProgress, TransactionReport } from '../types/Transaction';
+
+export interface ApplyArgs {
+  dirPath: string;
+  originalTree: string;
+  modifiedTree: string;
+}
+
+/**
+ * Applies the changes between two trees in the background with `start_apply` and
+ * resolves with the report of the `apply-finished` event. Every `apply-progress`
+ * event of the batch is passed to `onProgress`; `onStarted` receives the batch ID,
+ * which `cancel_apply` needs.
+ *
+ * Errors while planning, backing up or starting the journal reject as usual.
+ */
+export const applyInBackground = async (
+  args: ApplyArgs,
+  onProgress: (progress: ApplyProgress) => void,
+  onStarted?: (batchId: string) => void
+): Promise<TransactionReport> => {
+  // Events can arrive before start_apply returns the batch ID
+  let batchId: string | null = null;
+  const finished = new Map<string, TransactionReport>();
+  let resolveFinished: ((report: TransactionReport) => void) | null = null;
+
+  const unlistenProgress = await listen<ApplyProgress>('apply-progress', ({ payload }) => {
+    if (batchId === null || payload.batch_id === batchId) {
+      onProgress(payload);
+    }
+  });
+  const unlistenFinished = await listen<ApplyFinished>('apply-finished', ({ payload }) => {
+    finished.set(payload.batch_id, payload.report);
+    if (payload.batch_id === batchId && resolveFinished) {
+      resolveFinished(payload.report);
+    }
+  });
+
+  try {
+    const id = await invoke<string>('start_apply', { ...args });
+    batchId = id;
+    onStarted?.(id);
+    return finished.get(id) ?? await new Promise<TransactionReport>(resolve => {
+      resolveFinished = resolve;
+    });
+  } finally {
+    unlistenProgress();
+    unlistenFinished();
+  }
+};
+
+/**
+ * Asks a batch started with `applyInBackground` to stop before its next operation.
+ */
+export const cancelApply = (batchId: string): Promise<void> => invoke('cancel_apply', { batchId });
+
+/**
+ * Describes the operations of a batch that failed without stopping it.
+ */
+export const describeFailedOperations = (report: TransactionReport): string =>
+  report.results
+    .filter(result => !result.success)
+    .map(result => result.message)
+    .join('\n');
@@ -51,9 +51,10 @@ export const formatTreeToText = (
  * 
  * @param text Formatted tree text
  * @param originalJson Original tree structure as JSON string
+ * @param columnCount Number of metadata columns the tree was rendered with
  * @returns Map of line numbers to node IDs
  */
-export const buildIdMapping = (text: string, originalJson: string): Map<number, string> => {
+export const buildIdMapping = (text: string, originalJson: string, columnCount = 0): Map<number, string> => {
   const result = new Map<number, string>();
   
   try {
@@ -83,7 +84,7 @@ export const buildIdMapping = (text: string, originalJson: string): Map<number,
         if (!line) continue;
         
         // 提取节点名称
-        const { name } = extractNodeName(line);
+        const { name } = extractNodeName(line, columnCount);
         
         // 在所有节点中查找匹配的节点
         const matchingNode = findNodeByName(allNodes, name);
@@ -135,17 +136,46 @@ function findNodeByName(nodes: { id: string; name: string }[], name: string): {
   return nodes.find(node => node.name === name);
 }
 
+/**
+ * Separator between a node name and its read-only metadata columns
+ * (size, modified date, item count) rendered by the backend.
+ */
+export const COLUMN_SEPARATOR = '┊';
+
+/**
+ * Removes the read-only metadata columns from a line of tree text rendered with
+ * `columnCount` columns. Every column starts with one separator, so the columns
+ * are found by counting separators from the end of the line: their content never
+ * matters and names containing the separator are kept. A line with fewer
+ * separators lost some of its columns and is cut at the first one.
+ */
+export const stripMetadataColumns = (line: string, columnCount: number): string => {
+  if (columnCount <= 0) {
+    return line;
+  }
+  const separators: number[] = [];
+  for (let index = line.indexOf(COLUMN_SEPARATOR); index !== -1; index = line.indexOf(COLUMN_SEPARATOR, index + 1)) {
+    separators.push(index);
+  }
+  if (separators.length === 0) {
+    return line;
+  }
+  const start = separators.length >= columnCount ? separators[separators.length - columnCount] : separators[0];
+  return line.slice(0, start).trimEnd();
+};
+
 /**
  * Extracts the node name and whether it's a directory from a formatted tree line.
  * Handles all tree formatting characters (├──, └──, │) and preserves filenames 
  * that might start with those characters.
  * 
  * @param line Line from formatted tree
+ * @param columnCount Number of metadata columns the tree was rendered with
  * @returns Object with name and is_dir properties
  */
-export const extractNodeName = (line: string): { name: string; is_dir: boolean } => {
-  // Trim any whitespace but preserve internal spaces
-  const trimmedLine = line.trim();
+export const extractNodeName = (line: string, columnCount = 0): { name: string; is_dir: boolean } => {
+  // Drop metadata columns, then trim any whitespace but preserve internal spaces
+  const trimmedLine = stripMetadataColumns(line, columnCount).trim();
   
   // Look for tree formatting characters and the content after them
   const treeFormatRegex = /^(?:[│├└]\s*)*(?:[├└]──\s+)(.+)$/;
@@ -197,6 +227,7 @@ export const validateRootNameChange = (
       };
     }
     
+    // The root line carries no metadata columns
     const editedRootName = lines[0].trim();
     
     return {
@@ -210,17 +241,35 @@ export const validateRootNameChange = (
   }
 };
 
+/**
+ * Whether a root name can be used as a single path component
+ */
+export const isValidRootName = (name: string): boolean => {
+  return name.length > 0 && name !== '.' && name !== '..' && !/[\/\\]/.test(name);
+};
+
+/**
+ * Returns the path of the scanned directory after its root has been renamed
+ */
+export const renamedRootPath = (dirPath: string, newName: string): string => {
+  const trimmed = dirPath.replace(/[\/\\]+$/, '');
+  const separator = Math.max(trimmed.lastIndexOf('/'), trimmed.lastIndexOf('\\'));
+  return separator >= 0 ? trimmed.slice(0, separator + 1) + newName : newName;
+};
+
 /**
  * Parses formatted text representation back to TreeNode structure.
  * Uses indentation to determine parent-child relationships.
  * 
  * @param text Formatted tree text
  * @param originalTree Original tree structure as JSON string (for ID preservation)
+ * @param columnCount Number of metadata columns the tree was rendered with
  * @returns Parsed TreeNode structure, or null if parsing failed
  */
 export const parseTextToTree = (
   text: string, 
-  originalTree: string
+  originalTree: string,
+  columnCount = 0
 ): TreeNode | null => {
   try {
     console.log('\n======== PARSING TREE ========');
@@ -233,20 +282,21 @@ export const parseTextToTree = (
     // Get original root info
     const originalTreeObj = JSON.parse(originalTree) as TreeNode;
     
-    // Extract root name from first line
+    // Extract root name from first line (it carries no metadata columns)
     let rootLine = lines[0].trim();
     const isRootDir = rootLine.endsWith('/') || originalTreeObj.is_dir;
     
-    // Check if root name changed and maintain original if it did
-    if (originalTreeObj.name !== rootLine.replace(/\/$/, '')) {
-      console.warn('Root name was modified. Using original root name for consistency.');
+    // A renamed root is kept (it is renamed in its parent directory);
+    // an invalid name falls back to the original one
+    if (!isValidRootName(rootLine.replace(/\/$/, ''))) {
+      console.warn('Root name is not a valid directory name. Using original root name.');
       rootLine = originalTreeObj.name + (isRootDir ? '/' : '');
     }
     
     const rootName = isRootDir ? rootLine.replace(/\/$/, '') : rootLine;
     
     // 使用行号来映射ID，不会生成新ID
-    const lineToIdMap = buildIdMapping(text, originalTree);
+    const lineToIdMap = buildIdMapping(text, originalTree, columnCount);
     
     // Create original node path mapping to track changes
     const originalNodePaths = new Map<string, string>();
@@ -291,7 +341,7 @@ export const parseTextToTree = (
       const level = calculateIndentLevel(line);
       
       // Extract node name and type
-      const { name, is_dir } = extractNodeName(line);
+      const { name, is_dir } = extractNodeName(line, columnCount);
       
       // 增加调试信息，帮助理解缩进计算和父子关系
       console.debug(`[Node] Line ${i}: "${name}" (level ${level}, is_dir ${is_dir})`);